@@ -1,15 +1,15 @@
 // Clap - command line lib
-use clap::FromArgMatches;
-use clap::{builder::PossibleValue, Args, Command, Parser, Subcommand, ValueHint};
+use clap::{Parser, Subcommand, ValueHint};
 // Verbosity
 pub use clap_verbosity::Verbosity;
+use jucenit_core::metrics;
 use jucenit_core::nginx::CertificateStore;
-// Serde
-use serde::{Deserialize, Serialize};
 // Error Handling
-use miette::Result;
+use miette::{IntoDiagnostic, Result};
 //
-use jucenit_core::{ConfigFile, NginxConfig};
+use jucenit_core::{
+    ConfigFile, ContainerProvider, Daemon, Drift, HealthCheck, NginxStatus, Reconcile,
+};
 
 /*
 The Cli struct is the entrypoint for command line argument parsing:
@@ -40,14 +40,16 @@ pub struct Cli {
 }
 impl Cli {
     pub fn hydrate() -> Result<()> {
-        let cli = Cli::parse();
+        let _cli = Cli::parse();
         Ok(())
     }
     pub async fn run() -> Result<()> {
         let cli = Cli::parse();
         match cli.commands {
             Commands::Push(args) => {
-                if let Some(file) = args.file {
+                if let Some(path) = args.watch {
+                    ConfigFile::watch(&path).await?;
+                } else if let Some(file) = args.file {
                     let config = ConfigFile::load(&file)?;
                     config.push().await?;
                 } else if let Some(raw) = args.raw {
@@ -66,6 +68,41 @@ impl Cli {
                 let config = ConfigFile::pull().await?;
                 config.edit().await?;
             }
+            Commands::Daemon(args) => {
+                let mut daemon = Daemon::default();
+                if let Some(socket) = args.socket {
+                    daemon.socket = socket;
+                }
+                daemon.token = args.token.or(std::env::var("JUCENIT_TOKEN").ok());
+                tokio::try_join!(
+                    daemon.serve(),
+                    CertificateStore::watch(),
+                    HealthCheck::watch(),
+                    Reconcile::watch(),
+                    serve_extras(&args.metrics, &args.containers)
+                )?;
+            }
+            Commands::Status(args) => {
+                let mut status = NginxStatus::get().await?;
+                let config = ConfigFile::pull().await?;
+                status.attribute(&config);
+                if args.json {
+                    let json = serde_json::to_string_pretty(&status).into_diagnostic()?;
+                    println!("{}", json);
+                } else {
+                    print!("{}", status);
+                }
+            }
+            Commands::Reconcile(args) => {
+                if args.dry_run {
+                    print!("{}", Drift::check().await?);
+                } else {
+                    let drift = Reconcile::default().run().await?;
+                    if drift.is_empty() {
+                        print!("{}", drift);
+                    }
+                }
+            }
             Commands::Ssl(args) => {
                 if args.renew {
                     CertificateStore::hydrate().await?;
@@ -73,18 +110,56 @@ impl Cli {
                 if args.clean {
                     CertificateStore::clean().await?;
                 }
+                if let Some(path) = &args.export {
+                    CertificateStore::export(path).await?;
+                }
+                if let Some(path) = &args.import {
+                    for name in CertificateStore::import(path).await? {
+                        println!("imported: {}", name);
+                    }
+                }
                 if args.watch {
-                    CertificateStore::watch().await?;
+                    // Probe upstream servers alongside certificate renewal
+                    tokio::try_join!(
+                        CertificateStore::watch(),
+                        HealthCheck::watch(),
+                        Reconcile::watch(),
+                        serve_extras(&args.metrics, &args.containers)
+                    )?;
                 }
             }
-            _ => {
-                // Err
-            }
         };
         Ok(())
     }
 }
 
+/**
+* Serve metrics and route labelled containers, when asked to,
+* alongside the long running watchers.
+*/
+async fn serve_extras(metrics: &Option<String>, containers: &Option<String>) -> Result<()> {
+    let metrics = async {
+        match metrics {
+            Some(address) => metrics::serve(address).await,
+            None => Ok(()),
+        }
+    };
+    let containers = async {
+        match containers {
+            Some(socket) => {
+                let provider = ContainerProvider {
+                    socket: socket.to_owned(),
+                    ..Default::default()
+                };
+                provider.watch().await
+            }
+            None => Ok(()),
+        }
+    };
+    tokio::try_join!(metrics, containers)?;
+    Ok(())
+}
+
 /*
 An enumaration over the differen types of commands available:
 */
@@ -94,6 +169,9 @@ pub enum Commands {
     Push(File),
     #[command(arg_required_else_help = true)]
     Ssl(Ssl),
+    Status(Status),
+    Reconcile(ReconcileArgs),
+    Daemon(DaemonArgs),
     // Developper commands
     #[command(hide = true)]
     Clean,
@@ -106,6 +184,49 @@ pub struct File {
     pub file: Option<String>,
     #[arg(help = "A toml/yaml string", long)]
     pub raw: Option<String>,
+    #[arg(
+        help = "A configuration file or directory to push on every change",
+        long,
+        value_hint = ValueHint::AnyPath
+    )]
+    pub watch: Option<String>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Parser)]
+pub struct DaemonArgs {
+    #[arg(help = "The control api unix socket path", long, value_hint = ValueHint::FilePath)]
+    pub socket: Option<String>,
+    #[arg(
+        help = "Require this bearer token instead of peer credentials (or set JUCENIT_TOKEN)",
+        long
+    )]
+    pub token: Option<String>,
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        help = "Serve prometheus metrics, example: 127.0.0.1:9100"
+    )]
+    pub metrics: Option<String>,
+    #[arg(
+        long,
+        value_name = "SOCKET",
+        num_args = 0..=1,
+        default_missing_value = "/var/run/docker.sock",
+        help = "Route labelled containers from the Docker/Podman api socket"
+    )]
+    pub containers: Option<String>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Parser)]
+pub struct Status {
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Parser)]
+pub struct ReconcileArgs {
+    #[arg(long, help = "Report differences without repairing them")]
+    pub dry_run: bool,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Parser)]
@@ -114,14 +235,44 @@ pub struct Ssl {
     pub renew: bool,
     #[arg(long)]
     pub watch: bool,
+    #[arg(
+        long,
+        requires = "watch",
+        value_name = "ADDRESS",
+        help = "Serve prometheus metrics while watching, example: 127.0.0.1:9100"
+    )]
+    pub metrics: Option<String>,
+    #[arg(
+        long,
+        requires = "watch",
+        value_name = "SOCKET",
+        num_args = 0..=1,
+        default_missing_value = "/var/run/docker.sock",
+        help = "Route labelled containers from the Docker/Podman api socket"
+    )]
+    pub containers: Option<String>,
     #[arg(long, hide = false)]
     pub clean: bool,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write the issued certificates and keys to a file",
+        value_hint = ValueHint::FilePath
+    )]
+    pub export: Option<String>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Install certificates exported from another server",
+        value_hint = ValueHint::FilePath
+    )]
+    pub import: Option<String>,
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Cli, Commands};
-    use clap::FromArgMatches;
+    use super::Cli;
+
     use clap::Parser;
 
     use assert_cmd::prelude::*; // Add methods on commands
@@ -148,6 +299,8 @@ mod tests {
         Ok(())
     }
 
+    // "--help" exits the process, run by hand
+    #[allow(dead_code)]
     fn parse_command_line() -> Result<()> {
         let e = "jucenit --help";
         let os_str: Vec<&str> = e.split(' ').collect();
@@ -166,6 +319,7 @@ mod tests {
     }
 
     // #[tokio::test]
+    #[allow(dead_code)]
     async fn renew_ssl() -> Result<()> {
         set_testing_config().await?;
         let mut cmd = Command::cargo_bin("jucenit").into_diagnostic()?;
@@ -1,8 +1,15 @@
+use crate::health::HealthCheck;
+use crate::nginx::config::{
+    AccessLog, ConfigMode, Forwarded, Server, Session, Settings, TlsPreset,
+};
 use crate::nginx::Config as NginxConfig;
+use crate::reconcile::Reconcile;
+use crate::ssl::{Acme, UnitAcme};
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 // Error Handling
-use crate::error::{TomlError, YamlError};
+use crate::error::{JucenitError, TomlError, YamlError};
 use miette::{Error, IntoDiagnostic, Result};
 // Filesystem
 use std::fs;
@@ -18,7 +25,24 @@ use pipelight::utils::{error::PipelightError, files::FileType, teleport::Portal}
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
+    #[serde(default)]
     pub unit: Vec<Unit>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub application: Vec<Application>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub upstream: Vec<Upstream>,
+    // Replace the whole nginx-unit configuration or merge into it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<ConfigMode>,
+    // Server wide options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub settings: Option<Settings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_log: Option<AccessLog>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reconcile: Option<Reconcile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acme: Option<Acme>,
 }
 
 impl Config {
@@ -29,7 +53,11 @@ impl Config {
         let mut portal = Portal::new().into_diagnostic()?;
         portal.seed("jucenit");
         let res = portal.search().into_diagnostic()?;
-        let config = Config::load(&portal.target.file_path.unwrap())?;
+        let file_path = portal
+            .target
+            .file_path
+            .ok_or(JucenitError::Config("No config file found".to_owned()))?;
+        let config = Config::load(&file_path)?;
         Ok(config)
     }
 
@@ -45,20 +73,74 @@ impl Config {
         // TODO: add Hcl and Kcl.
         let extension = &Path::new(file_path)
             .extension()
-            .unwrap()
-            .to_str()
-            .unwrap()
+            .and_then(|x| x.to_str())
+            .ok_or(JucenitError::Config(format!(
+                "File {:?} has no extension",
+                file_path
+            )))?
             .to_owned();
 
         let file_type = FileType::from(extension);
-        let config = match file_type {
+        let mut config = match file_type {
             FileType::Toml | FileType::Tml => Config::from_toml_file(file_path)?,
             FileType::Yaml | FileType::Yml => Config::from_yaml_file(file_path)?,
             _ => {
-                let msg = format!("File type is unknown");
-                return Err(Error::msg(msg));
+                let message = format!("File type of {:?} is unknown", file_path);
+                return Err(JucenitError::Config(message).into());
             }
         };
+        let source = fs::canonicalize(file_path)
+            .map(|x| x.to_str().unwrap().to_owned())
+            .unwrap_or(file_path.to_owned());
+        for unit in &mut config.unit {
+            unit.source = Some(source.clone());
+        }
+        Ok(config)
+    }
+    /**
+     * Load and merge every config file (.toml or .yml) of a directory.
+     * Files are read in alphabetical order, the last server wide options win.
+     */
+    pub fn load_dir(dir_path: &str) -> Result<Config> {
+        let mut paths: Vec<String> = vec![];
+        for entry in fs::read_dir(dir_path).into_diagnostic()? {
+            let path = entry.into_diagnostic()?.path();
+            let extension = path.extension().and_then(|x| x.to_str());
+            if path.is_file() && matches!(extension, Some("toml" | "tml" | "yaml" | "yml")) {
+                paths.push(path.to_str().unwrap().to_owned());
+            }
+        }
+        paths.sort();
+
+        let mut config = Config::default();
+        for path in paths {
+            let chunk = Config::load(&path)?;
+            for unit in chunk.unit {
+                if config.unit.iter().any(|x| x.uuid == unit.uuid) {
+                    let message =
+                        format!("Unit uuid {:?} is declared twice, in {:?}", unit.uuid, path);
+                    return Err(JucenitError::Config(message).into());
+                }
+                config.unit.push(unit);
+            }
+            config.application.extend(chunk.application);
+            config.upstream.extend(chunk.upstream);
+            if chunk.mode.is_some() {
+                config.mode = chunk.mode;
+            }
+            if chunk.settings.is_some() {
+                config.settings = chunk.settings;
+            }
+            if chunk.access_log.is_some() {
+                config.access_log = chunk.access_log;
+            }
+            if chunk.reconcile.is_some() {
+                config.reconcile = chunk.reconcile;
+            }
+            if chunk.acme.is_some() {
+                config.acme = chunk.acme;
+            }
+        }
         Ok(config)
     }
     /**
@@ -71,7 +153,7 @@ impl Config {
             Ok(res) => Ok(res),
             Err(e) => {
                 let err = TomlError::new(e, &tml);
-                Err(err.into())
+                Err(JucenitError::from(err).into())
             }
         }
     }
@@ -79,18 +161,17 @@ impl Config {
     Returns a jucenit configuration from a provided toml string.
     */
     pub fn from_toml_str(toml: &str) -> Result<Self> {
-        let res = toml::from_str::<Self>(&toml);
+        let res = toml::from_str::<Self>(toml);
         match res {
             Ok(res) => Ok(res),
             Err(e) => {
                 let err = TomlError::new(e, toml);
-                Err(err.into())
+                Err(JucenitError::from(err).into())
             }
         }
     }
     pub fn to_toml(&self) -> Result<String> {
-        let res = toml::to_string_pretty(self).into_diagnostic();
-        res
+        toml::to_string_pretty(self).into_diagnostic()
     }
     /**
      * Returns a jucenit configuration from a provided yaml file path.
@@ -102,7 +183,7 @@ impl Config {
             Ok(res) => Ok(res),
             Err(e) => {
                 let err = YamlError::new(e, &yml);
-                Err(err.into())
+                Err(JucenitError::from(err).into())
             }
         }
     }
@@ -110,18 +191,17 @@ impl Config {
     Returns a jucenit configuration from a provided yaml string.
     */
     pub fn from_yaml_str(yml: &str) -> Result<Self> {
-        let res = serde_yaml::from_str::<Self>(&yml);
+        let res = serde_yaml::from_str::<Self>(yml);
         match res {
             Ok(res) => Ok(res),
             Err(e) => {
-                let err = YamlError::new(e, &yml);
-                Err(err.into())
+                let err = YamlError::new(e, yml);
+                Err(JucenitError::from(err).into())
             }
         }
     }
     pub fn to_yaml(&self) -> Result<String> {
-        let res = serde_yaml::to_string(self).into_diagnostic();
-        res
+        serde_yaml::to_string(self).into_diagnostic()
     }
 }
 
@@ -135,41 +215,75 @@ pub struct Unit {
     #[serde(rename = "match")]
     pub match_: Match,
     pub listeners: Vec<String>,
+    // Listeners options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<Tls>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forwarded: Option<Forwarded>,
+    // Probe the proxy servers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheck>,
+    // Certificate authority for the unit hosts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acme: Option<UnitAcme>,
+    // Path of the file the unit was loaded from
+    #[serde(skip)]
+    pub source: Option<String>,
 }
 impl Unit {
     /**
     Returns a jucenit configuration from a provided toml string.
     */
     pub fn from_toml_str(toml: &str) -> Result<Self> {
-        let res = toml::from_str::<Self>(&toml);
+        let res = toml::from_str::<Self>(toml);
         match res {
             Ok(res) => Ok(res),
             Err(e) => {
                 let err = TomlError::new(e, toml);
-                Err(err.into())
+                Err(JucenitError::from(err).into())
             }
         }
     }
     pub fn to_toml(&self) -> Result<String> {
-        let res = toml::to_string_pretty(self).into_diagnostic();
-        res
+        toml::to_string_pretty(self).into_diagnostic()
     }
     /**
     Returns a jucenit configuration from a provided yaml string.
     */
     pub fn from_yaml_str(yml: &str) -> Result<Self> {
-        let res = serde_yaml::from_str::<Self>(&yml);
+        let res = serde_yaml::from_str::<Self>(yml);
         match res {
             Ok(res) => Ok(res),
             Err(e) => {
-                let err = YamlError::new(e, &yml);
-                Err(err.into())
+                let err = YamlError::new(e, yml);
+                Err(JucenitError::from(err).into())
             }
         }
     }
     pub fn to_yaml(&self) -> Result<String> {
-        let res = serde_yaml::to_string(self).into_diagnostic();
-        res
+        serde_yaml::to_string(self).into_diagnostic()
+    }
+    /**
+     * Name of the upstream generated from the proxy shorthand.
+     */
+    pub fn upstream_name(&self) -> String {
+        format!("jucenit_[{}]", self.uuid)
+    }
+    /**
+     * Check the unit has what pushing it requires:
+     * an action with parameters, and listeners.
+     */
+    pub fn validate(&self) -> Result<()> {
+        let params = self.action.as_ref().and_then(|x| x.raw_params.as_ref());
+        if !matches!(params, Some(serde_json::Value::Object(_))) {
+            let message = format!("Unit {:?} has no action", self.uuid);
+            return Err(JucenitError::Config(message).into());
+        }
+        if self.listeners.is_empty() {
+            let message = format!("Unit {:?} has no listeners", self.uuid);
+            return Err(JucenitError::Config(message).into());
+        }
+        Ok(())
     }
 }
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
@@ -180,6 +294,76 @@ pub struct Action {
     pub raw_params: Option<serde_json::Value>,
 }
 
+impl Action {
+    /**
+     * Return the application name if the action passes requests to one.
+     * ex: "applications/blog" or "applications/blog/admin" -> "blog"
+     */
+    pub fn application(&self) -> Option<String> {
+        self.pass_target("applications/")
+    }
+    /**
+     * Return the upstream name if the action passes requests to one.
+     * ex: "upstreams/api" -> "api"
+     */
+    pub fn upstream(&self) -> Option<String> {
+        self.pass_target("upstreams/")
+    }
+    fn pass_target(&self, prefix: &str) -> Option<String> {
+        let pass = self.raw_params.as_ref()?.get("pass")?.as_str()?;
+        let name = pass.strip_prefix(prefix)?.split('/').next()?;
+        // Skip names computed at runtime from variables.
+        if name.contains('$') {
+            return None;
+        }
+        Some(name.to_owned())
+    }
+    /**
+     * Return the servers of the load balanced proxy shorthand.
+     * ex: proxy = ["http://10.0.0.1:80", "http://10.0.0.2:80"]
+     */
+    pub fn proxies(&self) -> Option<Vec<String>> {
+        let proxy = self.raw_params.as_ref()?.get("proxy")?.as_array()?;
+        proxy
+            .iter()
+            .map(|x| x.as_str().map(str::to_owned))
+            .collect()
+    }
+    /**
+     * Return the single proxied server.
+     * ex: proxy = "http://10.0.0.1:80"
+     */
+    pub fn proxy(&self) -> Option<String> {
+        let proxy = self.raw_params.as_ref()?.get("proxy")?.as_str()?;
+        Some(proxy.to_owned())
+    }
+}
+
+/**
+* A group of load balanced servers.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Upstream {
+    pub name: String,
+    // Server ip socket (ex: "10.0.0.1:80") to options
+    pub servers: IndexMap<String, Server>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheck>,
+}
+
+/**
+* An nginx-unit application (Python, PHP, Go, WASM...).
+* Every parameter but the name is passed through to nginx-unit.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct Application {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(flatten)]
+    pub raw_params: Option<serde_json::Value>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
 pub struct Match {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -190,10 +374,30 @@ pub struct Match {
     pub raw_params: Option<serde_json::Value>,
 }
 
+/**
+* Tls options applied to the unit listeners.
+* Listeners on port 80 are left untouched.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Tls {
+    // A hardening preset ("modern" or "intermediate")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<TlsPreset>,
+    // Raw OpenSSL commands, override the preset ones.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conf_commands: Option<IndexMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<Session>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::Config as ConfigFile;
-    use miette::Result;
+    use crate::error::{CastError, JucenitError};
+    use crate::health::Probe;
+    use crate::nginx::config::{LogFormat, Source, TlsPreset};
+    use miette::{IntoDiagnostic, Result};
 
     #[test]
     fn get_from_toml_file() -> Result<()> {
@@ -208,6 +412,12 @@ mod tests {
         Ok(())
     }
     #[test]
+    fn load_config_directory() -> Result<()> {
+        let res = ConfigFile::load_dir("../examples")?;
+        assert!(!res.unit.is_empty());
+        Ok(())
+    }
+    #[test]
     fn get_from_toml_string() -> Result<()> {
         let toml = "
         [[unit]]
@@ -222,4 +432,196 @@ mod tests {
         println!("{:#?}", res);
         Ok(())
     }
+    #[test]
+    fn match_error_kinds() -> Result<()> {
+        let err = ConfigFile::from_toml_str("[[unit]\n").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<JucenitError>(),
+            Some(JucenitError::Cast(CastError::TomlError(_)))
+        ));
+        // Same unit in two files
+        let dir = "/tmp/jucenit/test_error_kinds";
+        std::fs::create_dir_all(dir).into_diagnostic()?;
+        let toml = "
+        [[unit]]
+        uuid = 'a1e2c58b-4b57-4d3a-8f7a-0c5a5d0f5e21'
+        listeners = ['*:80']
+        [unit.match]
+        hosts = ['example.com']
+        [unit.action]
+        proxy = 'http://127.0.0.1:8001'
+        ";
+        std::fs::write(format!("{}/a.toml", dir), toml).into_diagnostic()?;
+        std::fs::write(format!("{}/b.toml", dir), toml).into_diagnostic()?;
+        let err = ConfigFile::load_dir(dir).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<JucenitError>(),
+            Some(JucenitError::Config(_))
+        ));
+        // No extension
+        let err = ConfigFile::load("/tmp/jucenit/jucenit").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<JucenitError>(),
+            Some(JucenitError::Config(_))
+        ));
+        Ok(())
+    }
+    #[test]
+    fn get_tls_options_from_toml_string() -> Result<()> {
+        let toml = "
+        [[unit]]
+        uuid = 'd3630938-5851-43ab-a523-84e0c6af9eb1'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['test.com']
+        [unit.action]
+        proxy = 'http://127.0.0.1:8333'
+        [unit.tls]
+        preset = 'intermediate'
+        conf_commands = { groups = 'X25519' }
+        session = { cache_size = 10240, timeout = 60, tickets = true }
+       ";
+        let res = ConfigFile::from_toml_str(toml)?;
+        let tls = res.unit[0].tls.clone().unwrap();
+        assert_eq!(tls.preset, Some(TlsPreset::Intermediate));
+        assert_eq!(tls.session.unwrap().cache_size, Some(10240));
+        Ok(())
+    }
+    #[test]
+    fn get_applications_from_toml_string() -> Result<()> {
+        let toml = "
+        [[application]]
+        name = 'blog'
+        type = 'python 3.11'
+        path = '/srv/blog'
+        module = 'wsgi'
+
+        [[unit]]
+        uuid = 'd3630938-5851-43ab-a523-84e0c6af9eb1'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['blog.example.com']
+        [unit.action]
+        pass = 'applications/blog'
+       ";
+        let res = ConfigFile::from_toml_str(toml)?;
+        assert_eq!(res.application[0].name, "blog");
+        assert_eq!(
+            res.application[0].raw_params.clone().unwrap()["type"],
+            "python 3.11"
+        );
+        let action = res.unit[0].action.clone().unwrap();
+        assert_eq!(action.application(), Some("blog".to_owned()));
+        Ok(())
+    }
+    #[test]
+    fn get_upstreams_from_toml_string() -> Result<()> {
+        let toml = "
+        [[upstream]]
+        name = 'api'
+        [upstream.servers]
+        '10.0.0.1:80' = { weight = 2 }
+        '10.0.0.2:80' = {}
+
+        [[unit]]
+        uuid = 'd3630938-5851-43ab-a523-84e0c6af9eb1'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['api.example.com']
+        [unit.action]
+        pass = 'upstreams/api'
+
+        [[unit]]
+        uuid = 'cc4e626a-9354-480e-a78b-f9f845148984'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['example.com']
+        [unit.action]
+        proxy = ['http://10.0.0.3:80', 'http://10.0.0.4:80']
+       ";
+        let res = ConfigFile::from_toml_str(toml)?;
+        let servers = &res.upstream[0].servers;
+        assert_eq!(servers["10.0.0.1:80"].weight, Some(2.into()));
+        assert_eq!(servers["10.0.0.2:80"].weight, None);
+
+        let action = res.unit[0].action.clone().unwrap();
+        assert_eq!(action.upstream(), Some("api".to_owned()));
+        let action = res.unit[1].action.clone().unwrap();
+        assert_eq!(action.proxies().unwrap().len(), 2);
+        Ok(())
+    }
+    #[test]
+    fn get_health_checks_from_toml_string() -> Result<()> {
+        let toml = "
+        [[upstream]]
+        name = 'api'
+        servers = { '10.0.0.1:80' = {}, '10.0.0.2:80' = {} }
+        health_check = { probe = 'http', path = '/healthz', fall = 2 }
+
+        [[unit]]
+        uuid = 'd3630938-5851-43ab-a523-84e0c6af9eb1'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['example.com']
+        [unit.action]
+        proxy = 'http://10.0.0.3:80'
+        [unit.health_check]
+        interval = 5
+       ";
+        let res = ConfigFile::from_toml_str(toml)?;
+        let check = res.upstream[0].health_check.clone().unwrap();
+        assert_eq!(check.probe, Probe::Http);
+        assert_eq!(check.fall(), 2);
+        assert_eq!(check.rise(), 2);
+
+        let check = res.unit[0].health_check.clone().unwrap();
+        assert_eq!(check.probe, Probe::Tcp);
+        assert_eq!(check.interval().as_secs(), 5);
+        Ok(())
+    }
+    #[test]
+    fn get_global_options_from_toml_string() -> Result<()> {
+        let toml = "
+        [settings.http]
+        max_body_size = 8388608
+        idle_timeout = 180
+        static.mime_types = { 'text/plain' = ['.log', 'README'] }
+
+        [access_log]
+        path = '/var/log/unit/access.json'
+        format = { remote = '$remote_addr', uri = '$request_uri' }
+        if = '`${status >= 400}`'
+       ";
+        let res = ConfigFile::from_toml_str(toml)?;
+        let http = res.settings.unwrap().http.unwrap();
+        assert_eq!(http.max_body_size, Some(8388608));
+        assert!(http.static_.unwrap().mime_types.contains_key("text/plain"));
+        let access_log = res.access_log.unwrap();
+        assert!(matches!(access_log.format, Some(LogFormat::Json(_))));
+        Ok(())
+    }
+    #[test]
+    fn get_forwarded_options_from_toml_string() -> Result<()> {
+        let toml = "
+        [[unit]]
+        uuid = 'd3630938-5851-43ab-a523-84e0c6af9eb1'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['test.com']
+        [unit.action]
+        proxy = 'http://127.0.0.1:8333'
+        [unit.forwarded]
+        client_ip = 'X-Forwarded-For'
+        protocol = 'X-Forwarded-Proto'
+        source = ['10.0.0.0/8', '192.168.1.4']
+        recursive = true
+       ";
+        let res = ConfigFile::from_toml_str(toml)?;
+        let forwarded = res.unit[0].forwarded.clone().unwrap();
+        assert_eq!(
+            forwarded.source,
+            Source::Many(vec!["10.0.0.0/8".to_owned(), "192.168.1.4".to_owned()])
+        );
+        Ok(())
+    }
 }
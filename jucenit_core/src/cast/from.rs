@@ -2,32 +2,41 @@ use super::{Action, Match};
 
 use crate::database::entity::{prelude::*, *};
 use sea_orm::{prelude::*, sea_query::OnConflict, ActiveValue, InsertResult};
+// Error Handling
+use crate::error::JucenitError;
+use miette::Result;
 
 impl Action {
-    pub fn from(e: &action::Model) -> Action {
+    pub fn from(e: &action::Model) -> Result<Action> {
         let action = Action {
-            raw_params: serde_json::from_str(&e.raw_params).unwrap(),
+            raw_params: serde_json::from_str(&e.raw_params).map_err(|err| {
+                JucenitError::Database(format!("Malformed action {}: {}", e.id, err))
+            })?,
         };
-        action
+        Ok(action)
     }
 }
 
 impl Match {
-    pub fn from(e: &ng_match::Model, hosts: Vec<host::Model>) -> Self {
+    pub fn from(e: &ng_match::Model, hosts: Vec<host::Model>) -> Result<Self> {
         let domain_names: Vec<String> = hosts.iter().map(|x| x.clone().domain).collect();
-        let hosts;
-        if domain_names.is_empty() {
-            hosts = None;
+
+        let hosts = if domain_names.is_empty() {
+            None
         } else {
-            hosts = Some(domain_names)
-        }
+            Some(domain_names)
+        };
         let match_ = Self {
             hosts,
             raw_params: e
                 .raw_params
                 .clone()
-                .map(|x| serde_json::from_str(&x).unwrap()),
+                .map(|x| serde_json::from_str(&x))
+                .transpose()
+                .map_err(|err| {
+                    JucenitError::Database(format!("Malformed match {:?}: {}", e.uuid, err))
+                })?,
         };
-        match_
+        Ok(match_)
     }
 }
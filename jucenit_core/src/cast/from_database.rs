@@ -1,10 +1,10 @@
 // Database
 use super::config;
-use crate::database::{connect_db, fresh_db};
+use crate::database::{connect_db, fresh_db, parse_column};
 use crate::{ConfigFile, ConfigUnit, NginxConfig};
 // Sea orm
-// use indexmap::IndexMap;
 use crate::database::entity::{prelude::*, *};
+use indexmap::IndexMap;
 use rayon::iter::Update;
 use sea_orm::{
     prelude::*, query::*, sea_query::OnConflict, ActiveValue, InsertResult, MockDatabase,
@@ -13,6 +13,7 @@ use sea_orm::{Database, DatabaseConnection};
 // Logging
 use tracing::{debug, Level};
 // Error Handling
+use crate::error::JucenitError;
 use miette::{Error, IntoDiagnostic, Result, WrapErr};
 
 // Fs
@@ -27,31 +28,180 @@ impl ConfigFile {
 
         let mut config = ConfigFile::default();
 
-        let matches = NgMatch::find().all(&db).await.into_diagnostic()?;
+        let matches = NgMatch::find().all(&db).await.map_err(JucenitError::from)?;
 
         for match_ in matches {
             let action = match_
                 .find_related(Action)
                 .one(&db)
                 .await
-                .into_diagnostic()?;
-            let hosts = match_.find_related(Host).all(&db).await.into_diagnostic()?;
+                .map_err(JucenitError::from)?;
+            let hosts = match_
+                .find_related(Host)
+                .all(&db)
+                .await
+                .map_err(JucenitError::from)?;
             let listeners = match_
                 .find_related(Listener)
                 .all(&db)
                 .await
-                .into_diagnostic()?;
+                .map_err(JucenitError::from)?;
+
+            let action = action.ok_or_else(|| {
+                JucenitError::Database(format!("Unit {:?} has no action", match_.uuid))
+            })?;
 
-            let unit = ConfigUnit {
+            let mut unit = ConfigUnit {
                 uuid: match_.clone().uuid,
-                action: Some(config::Action::from(&action.unwrap())),
-                match_: config::Match::from(&match_, hosts),
+                action: Some(config::Action::from(&action)?),
+                match_: config::Match::from(&match_, hosts)?,
                 listeners: listeners.iter().map(|x| x.clone().ip_socket).collect(),
+                tls: listeners
+                    .iter()
+                    .find_map(|x| x.tls.clone())
+                    .map(|x| parse_column(&format!("tls of unit {:?}", match_.uuid), &x))
+                    .transpose()?,
+                forwarded: listeners
+                    .iter()
+                    .find_map(|x| x.forwarded.clone())
+                    .map(|x| parse_column(&format!("forwarded of unit {:?}", match_.uuid), &x))
+                    .transpose()?,
+                acme: match_
+                    .acme
+                    .clone()
+                    .map(|x| parse_column(&format!("acme of unit {:?}", match_.uuid), &x))
+                    .transpose()?,
+                source: match_.source.clone(),
                 ..Default::default()
             };
 
+            // Restore the proxy shorthand from the generated upstream
+            let mut action = unit.action.clone().ok_or_else(|| {
+                JucenitError::Database(format!("Unit {:?} has no action", match_.uuid))
+            })?;
+            if action.upstream() == Some(unit.upstream_name()) {
+                let upstream = Upstream::find()
+                    .filter(upstream::Column::Name.eq(unit.upstream_name()))
+                    .one(&db)
+                    .await
+                    .map_err(JucenitError::from)?
+                    .ok_or_else(|| {
+                        JucenitError::Database(format!(
+                            "Upstream {:?} of unit {:?} is missing",
+                            unit.upstream_name(),
+                            match_.uuid
+                        ))
+                    })?;
+                let servers = upstream
+                    .find_related(UpstreamServer)
+                    .all(&db)
+                    .await
+                    .map_err(JucenitError::from)?;
+                let proxy: Vec<String> = servers
+                    .iter()
+                    .map(|x| format!("http://{}", x.address))
+                    .collect();
+                let mut params = match action.raw_params.clone() {
+                    Some(serde_json::Value::Object(params)) => params,
+                    _ => {
+                        let message =
+                            format!("Invalid action parameters of unit {:?}", match_.uuid);
+                        return Err(JucenitError::Database(message).into());
+                    }
+                };
+                params.remove("pass");
+                params.insert("proxy".to_owned(), serde_json::json!(proxy));
+                action.raw_params = Some(serde_json::Value::Object(params));
+                unit.action = Some(action);
+                unit.health_check = upstream
+                    .health_check
+                    .map(|x| parse_column(&format!("health check of upstream {}", upstream.id), &x))
+                    .transpose()?;
+            }
+
             config.unit.push(unit);
         }
+
+        // Upstreams
+        // Generated ones are restored as proxy shorthands.
+        let upstreams: Vec<(upstream::Model, Vec<upstream_server::Model>)> = Upstream::find()
+            .find_with_related(UpstreamServer)
+            .filter(upstream::Column::Name.not_like("jucenit_[%"))
+            .all(&db)
+            .await
+            .map_err(JucenitError::from)?;
+        for (upstream, servers) in upstreams {
+            let mut list = IndexMap::new();
+            for server in servers {
+                let raw_params = parse_column(
+                    &format!("upstream server {:?}", server.address),
+                    &server.raw_params,
+                )?;
+                list.insert(server.address, raw_params);
+            }
+            config.upstream.push(config::Upstream {
+                name: upstream.name,
+                servers: list,
+                health_check: upstream
+                    .health_check
+                    .map(|x| parse_column(&format!("health check of upstream {}", upstream.id), &x))
+                    .transpose()?,
+            });
+        }
+
+        // Applications
+        let applications = Application::find()
+            .all(&db)
+            .await
+            .map_err(JucenitError::from)?;
+        for app in applications {
+            let raw_params = parse_column(&format!("application {:?}", app.name), &app.raw_params)?;
+            config.application.push(config::Application {
+                name: app.name,
+                raw_params: Some(raw_params),
+            });
+        }
+
+        // Server wide options
+        let options = GlobalOption::find()
+            .all(&db)
+            .await
+            .map_err(JucenitError::from)?;
+        for option in options {
+            match option.name.as_str() {
+                "mode" => {
+                    config.mode = Some(parse_column(
+                        &format!("option {:?}", option.name),
+                        &option.raw_params,
+                    )?)
+                }
+                "settings" => {
+                    config.settings = Some(parse_column(
+                        &format!("option {:?}", option.name),
+                        &option.raw_params,
+                    )?)
+                }
+                "access_log" => {
+                    config.access_log = Some(parse_column(
+                        &format!("option {:?}", option.name),
+                        &option.raw_params,
+                    )?)
+                }
+                "reconcile" => {
+                    config.reconcile = Some(parse_column(
+                        &format!("option {:?}", option.name),
+                        &option.raw_params,
+                    )?)
+                }
+                "acme" => {
+                    config.acme = Some(parse_column(
+                        &format!("option {:?}", option.name),
+                        &option.raw_params,
+                    )?)
+                }
+                _ => {}
+            }
+        }
         Ok(config)
     }
     pub async fn edit(&self) -> Result<()> {
@@ -90,7 +240,7 @@ impl ConfigFile {
 #[cfg(test)]
 mod test {
     use crate::database::entity::{prelude::*, *};
-    use crate::database::{connect_db, fresh_db};
+    use crate::database::{connect_db, fresh_db, parse_column};
     use crate::{ConfigFile, Match, NginxConfig};
     use sea_orm::{prelude::*, sea_query::OnConflict, ActiveValue, InsertResult, MockDatabase};
     // Logging
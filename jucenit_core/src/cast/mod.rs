@@ -2,6 +2,7 @@ pub mod config;
 mod from;
 pub mod from_database;
 pub mod to_database;
+mod watch;
 
 // Public Reexport
 pub use config::*;
@@ -1,4 +1,5 @@
 // Database
+use crate::cast::config;
 use crate::database::{connect_db, fresh_db};
 use crate::{ConfigFile, ConfigUnit, NginxConfig};
 // Sea orm
@@ -11,7 +12,8 @@ use serde_json::json;
 // Logging
 use tracing::{debug, Level};
 // Error Handling
-use miette::{Error, IntoDiagnostic, Result, WrapErr};
+use crate::error::JucenitError;
+use miette::{IntoDiagnostic, Result, WrapErr};
 
 impl ConfigFile {
     pub async fn remove(&self) -> Result<()> {
@@ -21,8 +23,85 @@ impl ConfigFile {
         Ok(())
     }
     pub async fn remove_from_db(&self) -> Result<()> {
+        let db = connect_db().await?;
+        self.remove_from(&db).await
+    }
+    /**
+     * Remove file through a database connection or transaction
+     */
+    pub(crate) async fn remove_from<C: ConnectionTrait>(&self, db: &C) -> Result<()> {
         for unit in &self.unit {
-            unit.remove_from_db().await?;
+            unit.remove_from(db).await?;
+        }
+        self.remove_applications_from_db(db).await?;
+        self.remove_upstreams_from_db(db).await?;
+        Ok(())
+    }
+    /**
+     * Remove declared upstreams from database.
+     * Fail if remaining units still pass requests to them.
+     */
+    async fn remove_upstreams_from_db<C: ConnectionTrait>(&self, db: &C) -> Result<()> {
+        if self.upstream.is_empty() {
+            return Ok(());
+        }
+        let used: Vec<String> = Action::find()
+            .all(db)
+            .await
+            .map_err(JucenitError::from)?
+            .iter()
+            .map(config::Action::from)
+            .collect::<Result<Vec<_>>>()?
+            .iter()
+            .filter_map(|x| x.upstream())
+            .collect();
+        for upstream in &self.upstream {
+            if used.contains(&upstream.name) {
+                let message = format!(
+                    "Couldn't remove upstream {:?}, units still pass requests to it",
+                    upstream.name
+                );
+                return Err(JucenitError::Config(message).into());
+            }
+            Upstream::delete_many()
+                .filter(upstream::Column::Name.eq(&upstream.name))
+                .exec(db)
+                .await
+                .map_err(JucenitError::from)?;
+        }
+        Ok(())
+    }
+    /**
+     * Remove declared applications from database.
+     * Fail if remaining units still pass requests to them.
+     */
+    async fn remove_applications_from_db<C: ConnectionTrait>(&self, db: &C) -> Result<()> {
+        if self.application.is_empty() {
+            return Ok(());
+        }
+        let used: Vec<String> = Action::find()
+            .all(db)
+            .await
+            .map_err(JucenitError::from)?
+            .iter()
+            .map(config::Action::from)
+            .collect::<Result<Vec<_>>>()?
+            .iter()
+            .filter_map(|x| x.application())
+            .collect();
+        for app in &self.application {
+            if used.contains(&app.name) {
+                let message = format!(
+                    "Couldn't remove application {:?}, units still pass requests to it",
+                    app.name
+                );
+                return Err(JucenitError::Config(message).into());
+            }
+            Application::delete_many()
+                .filter(application::Column::Name.eq(&app.name))
+                .exec(db)
+                .await
+                .map_err(JucenitError::from)?;
         }
         Ok(())
     }
@@ -32,23 +111,29 @@ impl ConfigFile {
         let like = json!({"uri":[]});
         let like = like.to_string();
 
-        let matches = NgMatch::find().all(&db).await.into_diagnostic()?;
-        let challenges: Vec<&ng_match::Model> = matches
-            .iter()
-            .filter(|x| {
-                if let Some(raw_params) = x.raw_params.clone() {
-                    let raw_params: serde_json::Value = serde_json::from_str(&raw_params).unwrap();
-                    return raw_params["uri"]
-                        .to_string()
-                        .contains("/.well-known/acme-challenge/");
+        let matches = NgMatch::find().all(&db).await.map_err(JucenitError::from)?;
+        let mut challenges: Vec<&ng_match::Model> = vec![];
+        for x in &matches {
+            if let Some(raw_params) = &x.raw_params {
+                let raw_params: serde_json::Value =
+                    serde_json::from_str(raw_params).map_err(|e| {
+                        JucenitError::Database(format!(
+                            "Invalid match parameters of unit {:?}: {}",
+                            x.uuid, e
+                        ))
+                    })?;
+                if raw_params["uri"]
+                    .to_string()
+                    .contains("/.well-known/acme-challenge/")
+                {
+                    challenges.push(x);
                 }
-                return false;
-            })
-            .collect();
+            }
+        }
 
         for x in challenges {
             let x = x.clone();
-            x.delete(&db).await.into_diagnostic()?;
+            x.delete(&db).await.map_err(JucenitError::from)?;
         }
         Ok(())
     }
@@ -61,17 +146,27 @@ impl ConfigUnit {
         Ok(())
     }
     pub async fn remove_from_db(&self) -> Result<()> {
-        let unit = self;
         let db = connect_db().await?;
+        self.remove_from(&db).await
+    }
+    /**
+     * Remove unit through a database connection or transaction
+     */
+    pub(crate) async fn remove_from<C: ConnectionTrait>(&self, db: &C) -> Result<()> {
+        let unit = self;
 
         let match_ = NgMatch::find()
             .filter(Condition::all().add(ng_match::Column::Uuid.eq(&unit.uuid)))
-            .one(&db)
+            .one(db)
             .await
-            .into_diagnostic()?;
+            .map_err(JucenitError::from)?;
 
         if let Some(match_) = match_ {
-            let hosts = match_.find_related(Host).all(&db).await.into_diagnostic()?;
+            let hosts = match_
+                .find_related(Host)
+                .all(db)
+                .await
+                .map_err(JucenitError::from)?;
             for host in hosts {
                 // Delete host if not linked to other matches.
                 if host
@@ -81,26 +176,26 @@ impl ConfigUnit {
                             .not()
                             .add(ng_match::Column::Uuid.eq(&unit.uuid)),
                     )
-                    .all(&db)
+                    .all(db)
                     .await
-                    .into_diagnostic()?
+                    .map_err(JucenitError::from)?
                     .is_empty()
                 {
-                    host.delete(&db).await.into_diagnostic()?;
+                    host.delete(db).await.map_err(JucenitError::from)?;
                 }
             }
             let action = match_
                 .find_related(Action)
-                .one(&db)
+                .one(db)
                 .await
-                .into_diagnostic()?;
+                .map_err(JucenitError::from)?;
             let action = action.unwrap();
 
             let listeners = match_
                 .find_related(Listener)
-                .all(&db)
+                .all(db)
                 .await
-                .into_diagnostic()?;
+                .map_err(JucenitError::from)?;
             for listener in listeners {
                 // Delete listeners if no related match
                 if listener
@@ -110,12 +205,12 @@ impl ConfigUnit {
                             .not()
                             .add(ng_match::Column::Uuid.eq(&unit.uuid)),
                     )
-                    .all(&db)
+                    .all(db)
                     .await
-                    .into_diagnostic()?
+                    .map_err(JucenitError::from)?
                     .is_empty()
                 {
-                    listener.delete(&db).await.into_diagnostic()?;
+                    listener.delete(db).await.map_err(JucenitError::from)?;
                 }
             }
 
@@ -128,19 +223,25 @@ impl ConfigUnit {
                         .not()
                         .add(ng_match::Column::Uuid.eq(&unit.uuid)),
                 )
-                .all(&db)
+                .all(db)
                 .await
-                .into_diagnostic()?
+                .map_err(JucenitError::from)?
                 .is_empty()
             {
                 del_action = true;
             }
 
-            match_.delete(&db).await.into_diagnostic()?;
+            match_.delete(db).await.map_err(JucenitError::from)?;
             // Delete action after match (fk constraint)
             if del_action {
-                action.delete(&db).await.into_diagnostic()?;
+                action.delete(db).await.map_err(JucenitError::from)?;
             }
+            // Delete the upstream generated from the proxy shorthand
+            Upstream::delete_many()
+                .filter(upstream::Column::Name.eq(unit.upstream_name()))
+                .exec(db)
+                .await
+                .map_err(JucenitError::from)?;
         }
         Ok(())
     }
@@ -184,7 +285,7 @@ mod test {
         [action]
         share = ['/tmp/jucenit/challenge_uuid.txt']
         ";
-        let unit = ConfigUnit::from_toml_str(&toml)?;
+        let unit = ConfigUnit::from_toml_str(toml)?;
         unit.push().await?;
 
         ConfigFile::purge_http_challenge().await?;
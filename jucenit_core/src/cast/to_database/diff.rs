@@ -0,0 +1,264 @@
+// Database
+use crate::database::connect_db;
+use crate::error::JucenitError;
+use crate::{ConfigFile, ConfigUnit, NginxConfig};
+use sea_orm::TransactionTrait;
+// Error Handling
+use miette::{IntoDiagnostic, Result};
+
+impl ConfigFile {
+    /**
+     * Push what changed since a previously pushed file
+     * and update nginx
+     */
+    pub async fn push_diff(&self, previous: &ConfigFile) -> Result<()> {
+        self.push_diff_to_db(previous).await?;
+        let nginx_config = NginxConfig::pull().await?;
+        nginx_config.set().await?;
+        Ok(())
+    }
+    /**
+     * Push what changed since a previously pushed file,
+     * and take this file as the new base once the database holds it.
+     * If nginx-unit rejects the update, later diffs are still computed
+     * against what the database holds.
+     */
+    pub async fn push_diff_over(self, previous: &mut ConfigFile) -> Result<()> {
+        self.push_diff_to_db(previous).await?;
+        *previous = self;
+        let nginx_config = NginxConfig::pull().await?;
+        nginx_config.set().await?;
+        Ok(())
+    }
+    /**
+     * Push what changed since a previously pushed file to database:
+     * - remove vanished units, applications and upstreams,
+     * - replace modified units,
+     * - push new units.
+     *
+     * Changes are applied all at once or not at all.
+     */
+    pub async fn push_diff_to_db(&self, previous: &ConfigFile) -> Result<()> {
+        // Units to remove or replace
+        let mut stale: Vec<&ConfigUnit> = vec![];
+        for unit in &previous.unit {
+            match self.unit.iter().find(|x| x.uuid == unit.uuid) {
+                Some(x) if same(x, unit)? => {}
+                _ => stale.push(unit),
+            }
+        }
+        // Declarations that vanished from the file
+        let removed = ConfigFile {
+            application: previous
+                .application
+                .iter()
+                .filter(|x| !self.application.iter().any(|y| y.name == x.name))
+                .cloned()
+                .collect(),
+            upstream: previous
+                .upstream
+                .iter()
+                .filter(|x| !self.upstream.iter().any(|y| y.name == x.name))
+                .cloned()
+                .collect(),
+            ..Default::default()
+        };
+
+        let db = connect_db().await?;
+        let txn = db.begin().await.map_err(JucenitError::from)?;
+        for unit in &stale {
+            unit.remove_from(&txn).await?;
+        }
+        self.push_options_to_db(&txn).await?;
+        self.push_applications_to_db(&txn).await?;
+        self.push_upstreams_to_db(&txn).await?;
+        for unit in &self.unit {
+            let unchanged = previous
+                .unit
+                .iter()
+                .any(|x| x.uuid == unit.uuid && !stale.iter().any(|y| y.uuid == unit.uuid));
+            if !unchanged {
+                unit.push_to(&txn).await?;
+            }
+        }
+        // Remove declarations after the units that used them
+        removed.remove_from(&txn).await?;
+        // Rolled back on drop if anything failed
+        txn.commit().await.map_err(JucenitError::from)?;
+        Ok(())
+    }
+}
+
+fn same(a: &ConfigUnit, b: &ConfigUnit) -> Result<bool> {
+    let a = serde_json::to_value(a).into_diagnostic()?;
+    let b = serde_json::to_value(b).into_diagnostic()?;
+    Ok(a == b)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::database::connect_db;
+    use crate::database::entity::{prelude::*, *};
+    use crate::ConfigFile;
+    use sea_orm::prelude::*;
+    use serial_test::serial;
+    // Error Handling
+    use miette::{IntoDiagnostic, Result};
+
+    #[tokio::test]
+    #[serial]
+    async fn push_changes_only() -> Result<()> {
+        ConfigFile::default().set().await?;
+
+        let previous = ConfigFile::from_toml_str(
+            "
+        [[unit]]
+        uuid = 'd3630938-5851-43ab-a523-84e0c6af9eb1'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['example.com']
+        [unit.action]
+        proxy = 'http://127.0.0.1:8888'
+
+        [[unit]]
+        uuid = 'f37490cb-d4eb-4f37-bb85-d39dad6a21ab'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['test.com']
+        [unit.action]
+        share = ['/home/website/static']
+        ",
+        )?;
+        previous.push_to_db().await?;
+
+        // Modify the first unit and drop the second one
+        let config = ConfigFile::from_toml_str(
+            "
+        [[unit]]
+        uuid = 'd3630938-5851-43ab-a523-84e0c6af9eb1'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['example.com']
+        [unit.action]
+        proxy = 'http://127.0.0.1:9999'
+        ",
+        )?;
+        config.push_diff_to_db(&previous).await?;
+
+        let db = connect_db().await?;
+        let matches = NgMatch::find().all(&db).await.into_diagnostic()?;
+        assert_eq!(matches.len(), 1);
+        let action = matches[0]
+            .find_related(Action)
+            .one(&db)
+            .await
+            .into_diagnostic()?
+            .unwrap();
+        assert!(action.raw_params.contains("9999"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn rollback_failed_changes() -> Result<()> {
+        ConfigFile::default().set().await?;
+
+        let previous = ConfigFile::from_toml_str(
+            "
+        [[unit]]
+        uuid = 'd3630938-5851-43ab-a523-84e0c6af9eb1'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['example.com']
+        [unit.action]
+        proxy = 'http://127.0.0.1:8888'
+        ",
+        )?;
+        previous.push_to_db().await?;
+
+        // The replacing unit passes to an undefined application.
+        let config = ConfigFile::from_toml_str(
+            "
+        [[unit]]
+        uuid = 'd3630938-5851-43ab-a523-84e0c6af9eb1'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['example.com']
+        [unit.action]
+        pass = 'applications/ghost'
+        ",
+        )?;
+        assert!(config.push_diff_to_db(&previous).await.is_err());
+
+        // The previous unit is left in place.
+        let db = connect_db().await?;
+        let matches = NgMatch::find().all(&db).await.into_diagnostic()?;
+        assert_eq!(matches.len(), 1);
+        let action = matches[0]
+            .find_related(Action)
+            .one(&db)
+            .await
+            .into_diagnostic()?
+            .unwrap();
+        assert!(action.raw_params.contains("8888"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn edit_after_rejected_push() -> Result<()> {
+        ConfigFile::default().set().await?;
+
+        let unit = "
+        [[unit]]
+        uuid = 'd3630938-5851-43ab-a523-84e0c6af9eb1'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['example.com']
+        [unit.action]
+        proxy = 'http://127.0.0.1:8888'
+        ";
+        let mut previous = ConfigFile::from_toml_str(&format!(
+            "{}
+        [[unit]]
+        uuid = 'f37490cb-d4eb-4f37-bb85-d39dad6a21ab'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['test.com']
+        [unit.action]
+        share = ['/home/website/static']
+        ",
+            unit
+        ))?;
+        previous.push().await?;
+
+        // Drop the second unit, and declare an application unit rejects (no type).
+        let rejected = ConfigFile::from_toml_str(&format!(
+            "{}
+        [[application]]
+        name = 'broken'
+        processes = 1
+        ",
+            unit
+        ))?;
+        assert!(rejected.push_diff_over(&mut previous).await.is_err());
+        // The database holds the rejected file, so does the base.
+        assert!(previous.application.iter().any(|x| x.name == "broken"));
+
+        // Fixing the file removes the application and keeps the second unit away.
+        let config = ConfigFile::from_toml_str(unit)?;
+        config.push_diff_over(&mut previous).await?;
+
+        let db = connect_db().await?;
+        let matches = NgMatch::find().all(&db).await.into_diagnostic()?;
+        assert_eq!(matches.len(), 1);
+        assert!(Application::find()
+            .all(&db)
+            .await
+            .into_diagnostic()?
+            .is_empty());
+        let live = crate::NginxConfig::get_value().await?;
+        assert!(live["applications"].get("broken").is_none());
+        Ok(())
+    }
+}
@@ -1,6 +1,10 @@
 // Database
-use crate::database::{connect_db, fresh_db};
+use crate::database::{clear_config, connect_db};
+use crate::health::HealthCheck;
+use crate::nginx::config::{Ownership, Server};
 use crate::{ConfigFile, ConfigUnit, NginxConfig};
+use indexmap::IndexMap;
+use std::net::SocketAddr;
 // Sea orm
 // use indexmap::IndexMap;
 use crate::database::entity::{prelude::*, *};
@@ -13,25 +17,93 @@ use sea_orm::{Database, DatabaseConnection};
 // Logging
 use tracing::{debug, Level};
 // Error Handling
-use miette::{Error, IntoDiagnostic, Result, WrapErr};
+use crate::error::JucenitError;
+use miette::{IntoDiagnostic, Result, WrapErr};
 
 impl ConfigFile {
     /**
      * Push file to database
      */
     pub async fn push_to_db(&self) -> Result<()> {
+        let db = connect_db().await?;
+        self.push_to(&db).await
+    }
+    /**
+     * Push file through a database connection or transaction
+     */
+    pub(crate) async fn push_to<C: ConnectionTrait>(&self, db: &C) -> Result<()> {
+        self.push_options_to_db(db).await?;
+        self.push_applications_to_db(db).await?;
+        self.push_upstreams_to_db(db).await?;
         for unit in &self.unit {
-            unit.push_to_db().await?;
+            unit.push_to(db).await?;
         }
         Ok(())
     }
     /**
-     * Clean up database and push file to database
+     * Replace the configuration in database with the file.
+     * Certificates, acme accounts and what jucenit declared before
+     * (removed in merge mode) are kept.
      */
     async fn push_to_fresh_db(&self) -> Result<()> {
-        fresh_db().await?;
-        for unit in &self.unit {
-            unit.push_to_db().await?;
+        let db = connect_db().await?;
+        let txn = db.begin().await.map_err(JucenitError::from)?;
+        clear_config(&txn).await?;
+        self.push_to(&txn).await?;
+        txn.commit().await.map_err(JucenitError::from)?;
+        Ok(())
+    }
+    /**
+     * Push server wide options (mode, settings, access_log, reconcile, acme) to database.
+     * Options absent from the file are left untouched.
+     */
+    pub(super) async fn push_options_to_db<C: ConnectionTrait>(&self, db: &C) -> Result<()> {
+        let mut options: Vec<(&str, String)> = vec![];
+        if let Some(mode) = &self.mode {
+            options.push(("mode", serde_json::to_string(mode).into_diagnostic()?));
+        }
+        if let Some(settings) = &self.settings {
+            options.push((
+                "settings",
+                serde_json::to_string(settings).into_diagnostic()?,
+            ));
+        }
+        if let Some(access_log) = &self.access_log {
+            options.push((
+                "access_log",
+                serde_json::to_string(access_log).into_diagnostic()?,
+            ));
+        }
+        if let Some(reconcile) = &self.reconcile {
+            options.push((
+                "reconcile",
+                serde_json::to_string(reconcile).into_diagnostic()?,
+            ));
+        }
+        if let Some(acme) = &self.acme {
+            acme.ratio()?;
+            acme.challenge().warn("the server");
+            options.push(("acme", serde_json::to_string(acme).into_diagnostic()?));
+        }
+        if options.is_empty() {
+            return Ok(());
+        }
+
+        for (name, raw_params) in options {
+            let option = global_option::ActiveModel {
+                name: ActiveValue::Set(name.to_owned()),
+                raw_params: ActiveValue::Set(raw_params),
+                ..Default::default()
+            };
+            GlobalOption::insert(option)
+                .on_conflict(
+                    OnConflict::column(global_option::Column::Name)
+                        .update_column(global_option::Column::RawParams)
+                        .to_owned(),
+                )
+                .exec(db)
+                .await
+                .map_err(JucenitError::from)?;
         }
         Ok(())
     }
@@ -55,6 +127,67 @@ impl ConfigFile {
         nginx_config.set().await?;
         Ok(())
     }
+    /**
+     * Push applications to database.
+     * Replace the parameters of already declared applications.
+     */
+    pub(super) async fn push_applications_to_db<C: ConnectionTrait>(&self, db: &C) -> Result<()> {
+        if self.application.is_empty() {
+            return Ok(());
+        }
+        for app in &self.application {
+            let raw_params = app
+                .raw_params
+                .clone()
+                .unwrap_or(serde_json::Value::Object(Default::default()))
+                .to_string();
+            let application = application::ActiveModel {
+                name: ActiveValue::Set(app.name.clone()),
+                raw_params: ActiveValue::Set(raw_params),
+                ..Default::default()
+            };
+            Application::insert(application)
+                .on_conflict(
+                    OnConflict::column(application::Column::Name)
+                        .update_column(application::Column::RawParams)
+                        .to_owned(),
+                )
+                .exec(db)
+                .await
+                .map_err(JucenitError::from)?;
+        }
+        let names: Vec<String> = self.application.iter().map(|x| x.name.clone()).collect();
+        Ownership::declare(db, &names, &[]).await?;
+        Ok(())
+    }
+    /**
+     * Push upstreams to database.
+     * Replace the servers of already declared upstreams.
+     */
+    pub(super) async fn push_upstreams_to_db<C: ConnectionTrait>(&self, db: &C) -> Result<()> {
+        if self.upstream.is_empty() {
+            return Ok(());
+        }
+        for upstream in &self.upstream {
+            if upstream.name.starts_with("jucenit_[") {
+                let message = format!(
+                    "Upstream name {:?} is reserved to the proxy shorthand",
+                    upstream.name
+                );
+                return Err(JucenitError::Config(message).into());
+            }
+            push_upstream(
+                db,
+                &upstream.name,
+                &upstream.servers,
+                &upstream.health_check,
+            )
+            .await?;
+        }
+        let names: Vec<String> = self.upstream.iter().map(|x| x.name.clone()).collect();
+        Ownership::declare(db, &[], &names).await?;
+        Ok(())
+    }
 }
 impl ConfigUnit {
     pub async fn push(&self) -> Result<()> {
@@ -64,6 +197,13 @@ impl ConfigUnit {
         Ok(())
     }
     pub async fn push_to_db(&self) -> Result<()> {
+        let db = connect_db().await?;
+        self.push_to(&db).await
+    }
+    /**
+     * Push unit through a database connection or transaction
+     */
+    pub(crate) async fn push_to<C: ConnectionTrait>(&self, db: &C) -> Result<()> {
         let unit = self;
         // Logic Guards
         // Ignore gracefully if matching pattern lakes parameters
@@ -80,10 +220,168 @@ impl ConfigUnit {
             }
         }
 
-        let db = connect_db().await?;
+        unit.validate()?;
+
+        // Ensure passed applications are declared
+        if let Some(name) = unit.action.as_ref().and_then(|x| x.application()) {
+            let app = Application::find()
+                .filter(application::Column::Name.eq(&name))
+                .one(db)
+                .await
+                .map_err(JucenitError::from)?;
+            if app.is_none() {
+                let message = format!(
+                    "Unit {:?} passes requests to the undefined application {:?}",
+                    unit.uuid, name
+                );
+                return Err(JucenitError::Config(message).into());
+            }
+        }
+
+        // Ensure passed upstreams are declared
+        if let Some(name) = unit.action.as_ref().and_then(|x| x.upstream()) {
+            let upstream = Upstream::find()
+                .filter(upstream::Column::Name.eq(&name))
+                .one(db)
+                .await
+                .map_err(JucenitError::from)?;
+            if upstream.is_none() {
+                let message = format!(
+                    "Unit {:?} passes requests to the undefined upstream {:?}",
+                    unit.uuid, name
+                );
+                return Err(JucenitError::Config(message).into());
+            }
+        }
+
+        // Load balanced proxy shorthand
+        // Replace the server list with a pass to a generated upstream.
+        // A single checked server also goes through an upstream.
+        let mut action = unit.action.clone().unwrap_or_default();
+        let proxies = match (action.proxies(), &unit.health_check) {
+            (Some(proxies), _) => Some(proxies),
+            (None, Some(_)) => action.proxy().map(|x| vec![x]),
+            (None, None) => None,
+        };
+        if unit.health_check.is_some() && proxies.is_none() {
+            let message = format!(
+                "Unit {:?} declares a health check without proxy servers to probe",
+                unit.uuid
+            );
+            return Err(JucenitError::Config(message).into());
+        }
+        if let Some(proxies) = proxies {
+            let mut servers: IndexMap<String, Server> = IndexMap::new();
+            for proxy in proxies {
+                // Nginx-unit upstreams only accept ip sockets (no hostnames).
+                let address = proxy
+                    .strip_prefix("http://")
+                    .map(|x| x.trim_end_matches('/'))
+                    .filter(|x| x.parse::<SocketAddr>().is_ok());
+                match address {
+                    Some(address) => {
+                        servers.insert(address.to_owned(), Server::default());
+                    }
+                    None => {
+                        let message = format!(
+                            "Unit {:?} can only load balance plain \"http://<ip>:<port>\" servers, got {:?}",
+                            unit.uuid, proxy
+                        );
+                        return Err(JucenitError::Config(message).into());
+                    }
+                };
+            }
+            let name = unit.upstream_name();
+            push_upstream(db, &name, &servers, &unit.health_check).await?;
+
+            if let Some(serde_json::Value::Object(params)) = action.raw_params.as_mut() {
+                params.remove("proxy");
+                params.insert(
+                    "pass".to_owned(),
+                    serde_json::Value::String(format!("upstreams/{}", name)),
+                );
+            }
+        }
+
+        // Listeners options
+        let tls: Option<String> = unit
+            .tls
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()
+            .into_diagnostic()?;
+        let forwarded: Option<String> = unit
+            .forwarded
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()
+            .into_diagnostic()?;
+
+        // Update existing listeners options
+        // Fail before any insertion if options conflict with other units.
+        let existing = Listener::find()
+            .filter(listener::Column::IpSocket.is_in(&unit.listeners))
+            .all(db)
+            .await
+            .map_err(JucenitError::from)?;
+        let mut updates: Vec<listener::ActiveModel> = vec![];
+        for model in &existing {
+            let tls = merge_listener_option(db, model, &unit.uuid, "tls", &model.tls, &tls).await?;
+            let forwarded = merge_listener_option(
+                db,
+                model,
+                &unit.uuid,
+                "forwarded",
+                &model.forwarded,
+                &forwarded,
+            )
+            .await?;
+            if tls != model.tls || forwarded != model.forwarded {
+                let mut active = listener::ActiveModel::from(model.to_owned());
+                active.tls = ActiveValue::Set(tls);
+                active.forwarded = ActiveValue::Set(forwarded);
+                updates.push(active);
+            }
+        }
+        for active in updates {
+            active.update(db).await.map_err(JucenitError::from)?;
+        }
+
+        // Insert listeners
+        let mut listeners: Vec<listener::ActiveModel> = vec![];
+        for l in &unit.listeners {
+            let listener = listener::ActiveModel {
+                ip_socket: ActiveValue::Set(l.to_owned()),
+                tls: ActiveValue::Set(tls.clone()),
+                forwarded: ActiveValue::Set(forwarded.clone()),
+                ..Default::default()
+            };
+            listeners.push(listener);
+        }
+        let res = Listener::insert_many(listeners.clone())
+            .on_conflict(
+                OnConflict::column(listener::Column::IpSocket)
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .do_nothing()
+            .exec_without_returning(db)
+            .await
+            .map_err(JucenitError::from)?;
+
+        // Populate entities with ids
+        let models = Listener::find()
+            .filter(listener::Column::IpSocket.is_in(&unit.listeners))
+            .all(db)
+            .await
+            .map_err(JucenitError::from)?;
+        listeners = models
+            .iter()
+            .map(|x| listener::ActiveModel::from(x.to_owned()))
+            .collect();
 
         // Insert Action
-        let raw_params: String = unit.action.clone().unwrap().raw_params.unwrap().to_string();
+        let raw_params: String = action.raw_params.unwrap_or_default().to_string();
 
         let mut action = action::ActiveModel {
             raw_params: ActiveValue::Set(raw_params.clone()),
@@ -96,9 +394,9 @@ impl ConfigUnit {
                     .do_nothing()
                     .to_owned(),
             )
-            .exec_with_returning(&db)
+            .exec_with_returning(db)
             .await
-            .into_diagnostic();
+            .map_err(JucenitError::from);
 
         // Populate entities with ids
         action = match res {
@@ -108,10 +406,12 @@ impl ConfigUnit {
                 // println!("{}", e);
                 let model = Action::find()
                     .filter(action::Column::RawParams.eq(raw_params))
-                    .one(&db)
+                    .one(db)
                     .await
-                    .into_diagnostic()?
-                    .unwrap()
+                    .map_err(JucenitError::from)?
+                    .ok_or_else(|| {
+                        JucenitError::Database(format!("Action of unit {:?} is missing", unit.uuid))
+                    })?
                     .into();
                 model
             }
@@ -119,21 +419,33 @@ impl ConfigUnit {
 
         // Insert Match
         let raw_params: Option<String> = unit.match_.clone().raw_params.map(|x| x.to_string());
+        if let Some(challenge) = unit.acme.as_ref().and_then(|x| x.challenge.as_ref()) {
+            challenge.warn(&format!("unit {:?}", unit.uuid));
+        }
+        let acme: Option<String> = unit
+            .acme
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()
+            .into_diagnostic()?;
         let mut match_ = ng_match::ActiveModel {
             uuid: ActiveValue::Set(unit.uuid.clone()),
             action_id: action.id,
             raw_params: ActiveValue::Set(raw_params.clone()),
+            source: ActiveValue::Set(unit.source.clone()),
+            acme: ActiveValue::Set(acme),
             ..Default::default()
         };
         let res = NgMatch::insert(match_)
             .on_conflict(
+                // Keep track of the file the unit moved to
                 OnConflict::column(ng_match::Column::Uuid)
-                    .do_nothing()
+                    .update_columns([ng_match::Column::Source, ng_match::Column::Acme])
                     .to_owned(),
             )
-            .exec_with_returning(&db)
+            .exec_with_returning(db)
             .await
-            .into_diagnostic();
+            .map_err(JucenitError::from);
 
         // Return the existing entity
         match_ = match res {
@@ -144,48 +456,15 @@ impl ConfigUnit {
                 // println!("{:#?}", raw_params);
                 let model = NgMatch::find()
                     .filter(ng_match::Column::Uuid.eq(&unit.uuid))
-                    .one(&db)
+                    .one(db)
                     .await
-                    .into_diagnostic()?
+                    .map_err(JucenitError::from)?
                     .unwrap()
                     .into();
                 model
             }
         };
 
-        // Insert listeners
-        assert!(!&unit.listeners.is_empty());
-        let mut listeners: Vec<listener::ActiveModel> = vec![];
-        for l in &unit.listeners {
-            let listener = listener::ActiveModel {
-                ip_socket: ActiveValue::Set(l.to_owned()),
-                ..Default::default()
-            };
-            listeners.push(listener);
-        }
-        let res = Listener::insert_many(listeners.clone())
-            .on_conflict(
-                OnConflict::column(listener::Column::IpSocket)
-                    .update_column(listener::Column::Tls)
-                    .do_nothing()
-                    .to_owned(),
-            )
-            .do_nothing()
-            .exec_without_returning(&db)
-            .await
-            .into_diagnostic()?;
-
-        // Populate entities with ids
-        let models = Listener::find()
-            .filter(listener::Column::IpSocket.is_in(&unit.listeners))
-            .all(&db)
-            .await
-            .into_diagnostic()?;
-        listeners = models
-            .iter()
-            .map(|x| listener::ActiveModel::from(x.to_owned()))
-            .collect();
-
         // Join Match and Listener
         assert!(!&listeners.is_empty());
         let mut list: Vec<match_listener::ActiveModel> = vec![];
@@ -206,9 +485,9 @@ impl ConfigUnit {
                 .to_owned(),
             )
             .do_nothing()
-            .exec(&db)
+            .exec(db)
             .await
-            .into_diagnostic()?;
+            .map_err(JucenitError::from)?;
 
         // Insert Hosts
         if unit.match_.hosts.is_some() {
@@ -228,17 +507,17 @@ impl ConfigUnit {
                             .to_owned(),
                     )
                     .do_nothing()
-                    .exec_without_returning(&db)
+                    .exec_without_returning(db)
                     .await
-                    .into_diagnostic();
+                    .map_err(JucenitError::from);
                 // Populate entities with ids
                 // debug!("{}", e);
                 // println!("{}", e);
                 let models = Host::find()
                     .filter(host::Column::Domain.is_in(dns))
-                    .all(&db)
+                    .all(db)
                     .await
-                    .into_diagnostic()?;
+                    .map_err(JucenitError::from)?;
                 hosts = models
                     .iter()
                     .map(|x| host::ActiveModel::from(x.to_owned()))
@@ -264,19 +543,148 @@ impl ConfigUnit {
                     .to_owned(),
                 )
                 .do_nothing()
-                .exec(&db)
+                .exec(db)
                 .await
-                .into_diagnostic()?;
+                .map_err(JucenitError::from)?;
         }
         Ok(())
     }
 }
 
+/**
+* Insert or update an upstream
+* and replace its servers.
+*/
+async fn push_upstream<C: ConnectionTrait>(
+    db: &C,
+    name: &str,
+    servers: &IndexMap<String, Server>,
+    health_check: &Option<HealthCheck>,
+) -> Result<()> {
+    let health_check: Option<String> = health_check
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .into_diagnostic()?;
+    let upstream = upstream::ActiveModel {
+        name: ActiveValue::Set(name.to_owned()),
+        health_check: ActiveValue::Set(health_check),
+        ..Default::default()
+    };
+    let _ = Upstream::insert(upstream)
+        .on_conflict(
+            OnConflict::column(upstream::Column::Name)
+                .update_column(upstream::Column::HealthCheck)
+                .to_owned(),
+        )
+        .exec_without_returning(db)
+        .await
+        .map_err(JucenitError::from)?;
+    let upstream = Upstream::find()
+        .filter(upstream::Column::Name.eq(name))
+        .one(db)
+        .await
+        .map_err(JucenitError::from)?
+        .ok_or(JucenitError::Database(format!(
+            "Upstream {:?} missing after insertion",
+            name
+        )))?;
+
+    // Keep servers out of rotation until they recover
+    let down: Vec<String> = upstream
+        .find_related(UpstreamServer)
+        .filter(upstream_server::Column::Down.eq(true))
+        .all(db)
+        .await
+        .map_err(JucenitError::from)?
+        .into_iter()
+        .map(|x| x.address)
+        .collect();
+    UpstreamServer::delete_many()
+        .filter(upstream_server::Column::UpstreamId.eq(upstream.id))
+        .exec(db)
+        .await
+        .map_err(JucenitError::from)?;
+    if servers.is_empty() {
+        return Ok(());
+    }
+    let mut list: Vec<upstream_server::ActiveModel> = vec![];
+    for (address, server) in servers {
+        let server = upstream_server::ActiveModel {
+            upstream_id: ActiveValue::Set(upstream.id),
+            address: ActiveValue::Set(address.to_owned()),
+            raw_params: ActiveValue::Set(serde_json::to_string(server).into_diagnostic()?),
+            down: ActiveValue::Set(upstream.health_check.is_some() && down.contains(address)),
+            ..Default::default()
+        };
+        list.push(server);
+    }
+    UpstreamServer::insert_many(list)
+        .exec_without_returning(db)
+        .await
+        .map_err(JucenitError::from)?;
+    Ok(())
+}
+
+/**
+* Reconcile a listener option declared by a unit
+* with the one already stored in the listener row.
+*
+* Returns the value to store.
+* Fails if other units on the same socket already declared
+* a different value.
+*/
+async fn merge_listener_option<C: ConnectionTrait>(
+    db: &C,
+    listener: &listener::Model,
+    uuid: &str,
+    name: &str,
+    stored: &Option<String>,
+    declared: &Option<String>,
+) -> Result<Option<String>> {
+    let (stored_json, declared_json) = match (stored, declared) {
+        (_, None) => return Ok(stored.to_owned()),
+        (None, Some(_)) => return Ok(declared.to_owned()),
+        (Some(stored), Some(declared)) => (
+            serde_json::from_str::<serde_json::Value>(stored).into_diagnostic()?,
+            serde_json::from_str::<serde_json::Value>(declared).into_diagnostic()?,
+        ),
+    };
+    if stored_json == declared_json {
+        return Ok(declared.to_owned());
+    }
+    // The unit is the only one to use the socket and may change its options.
+    let others = listener
+        .find_related(NgMatch)
+        .filter(Condition::all().not().add(ng_match::Column::Uuid.eq(uuid)))
+        .all(db)
+        .await
+        .map_err(JucenitError::from)?;
+    if others.is_empty() {
+        return Ok(declared.to_owned());
+    }
+    let message = format!(
+        "Conflicting {} options on listener {:?}:\n
+        unit {:?} declares {}\n
+        while units {:?} already use {}",
+        name,
+        listener.ip_socket,
+        uuid,
+        declared_json,
+        others
+            .iter()
+            .map(|x| x.uuid.clone())
+            .collect::<Vec<String>>(),
+        stored_json
+    );
+    Err(JucenitError::Config(message).into())
+}
+
 #[cfg(test)]
 mod test {
     use crate::database::entity::{prelude::*, *};
     use crate::database::{connect_db, fresh_db};
-    use crate::{ConfigFile, Match, NginxConfig};
+    use crate::{ConfigFile, JucenitError, Match, NginxConfig};
     use sea_orm::{prelude::*, sea_query::OnConflict, ActiveValue, InsertResult, MockDatabase};
     // Logging
     use tracing::{debug, Level};
@@ -324,4 +732,123 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn push_load_balanced_proxy() -> Result<()> {
+        set_testing_config().await?;
+
+        let toml = "
+        [[unit]]
+        uuid = '5d1e4c5e-7f0a-4be1-9a36-3c8d1c1ef3a0'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['lb.example.com']
+        [unit.action]
+        proxy = ['http://127.0.0.1:8222', 'http://127.0.0.1:8333']
+        ";
+        let config = ConfigFile::from_toml_str(toml)?;
+        config.push_to_db().await?;
+
+        let nginx_config = NginxConfig::pull().await?;
+        let name = config.unit[0].upstream_name();
+        let servers = &nginx_config.upstreams[&name].servers;
+        assert_eq!(servers.len(), 2);
+        assert!(servers.contains_key("127.0.0.1:8333"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reject_proxy_hostnames() -> Result<()> {
+        let toml = "
+        [[unit]]
+        uuid = '9c3e5a7b-2d4f-4a6b-8c0e-1f3a5b7c9d24'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['lb.example.com']
+        [unit.action]
+        proxy = ['http://backend:8222', 'http://127.0.0.1:8333']
+        ";
+        let config = ConfigFile::from_toml_str(toml)?;
+        let err = config.push_to_db().await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<JucenitError>(),
+            Some(JucenitError::Config(x)) if x.contains("http://backend:8222")
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reject_undefined_application() -> Result<()> {
+        let toml = "
+        [[unit]]
+        uuid = '6a0f2d8e-1c3b-4e5f-9a7d-2b4c6e8f0a13'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['ghost.example.com']
+        [unit.action]
+        pass = 'applications/ghost'
+        ";
+        let config = ConfigFile::from_toml_str(toml)?;
+        let err = config.push_to_db().await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<JucenitError>(),
+            Some(JucenitError::Config(x)) if x.contains("undefined application")
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn keep_state_on_set() -> Result<()> {
+        let db = connect_db().await?;
+        let account = acme_account::ActiveModel {
+            directory: ActiveValue::Set("https://kept.example.com/directory".to_owned()),
+            private_key: ActiveValue::Set("key".to_owned()),
+            contacts: ActiveValue::Set("[]".to_owned()),
+            ..Default::default()
+        };
+        AcmeAccount::insert(account)
+            .on_conflict(
+                OnConflict::column(acme_account::Column::Directory)
+                    .update_column(acme_account::Column::PrivateKey)
+                    .to_owned(),
+            )
+            .exec(&db)
+            .await
+            .into_diagnostic()?;
+        let certificate = certificate::ActiveModel {
+            host: ActiveValue::Set("kept.example.com".to_owned()),
+            renewal_id: ActiveValue::Set(Some("aaaa.bbbb".to_owned())),
+            ..Default::default()
+        };
+        Certificate::insert(certificate)
+            .on_conflict(
+                OnConflict::column(certificate::Column::Host)
+                    .update_column(certificate::Column::RenewalId)
+                    .to_owned(),
+            )
+            .exec(&db)
+            .await
+            .into_diagnostic()?;
+
+        // jucenit clean, jucenit edit
+        ConfigFile::default().set().await?;
+
+        let account = AcmeAccount::find()
+            .filter(acme_account::Column::Directory.eq("https://kept.example.com/directory"))
+            .one(&db)
+            .await
+            .into_diagnostic()?;
+        assert!(account.is_some());
+        let certificate = Certificate::find()
+            .filter(certificate::Column::Host.eq("kept.example.com"))
+            .one(&db)
+            .await
+            .into_diagnostic()?;
+        assert_eq!(
+            certificate.unwrap().renewal_id,
+            Some("aaaa.bbbb".to_owned())
+        );
+        assert!(NgMatch::find().all(&db).await.into_diagnostic()?.is_empty());
+        Ok(())
+    }
 }
@@ -1,8 +1,10 @@
 mod delete;
+mod diff;
 mod insert;
 mod methods;
 
 // Reexports
 pub use delete::*;
+pub use diff::*;
 pub use insert::*;
 pub use methods::*;
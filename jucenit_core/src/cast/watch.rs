@@ -0,0 +1,76 @@
+use super::Config;
+use std::path::Path;
+// Watch
+use notify::{RecursiveMode, Watcher};
+use std::time::Duration;
+use tokio::sync::mpsc;
+// Error Handling
+use miette::{IntoDiagnostic, Result};
+
+impl Config {
+    /**
+     * Load a config file, or every config file of a directory.
+     */
+    pub fn load_path(path: &str) -> Result<Config> {
+        if Path::new(path).is_dir() {
+            Config::load_dir(path)
+        } else {
+            Config::load(path)
+        }
+    }
+    /**
+     * Push a config file (or directory) and push changes
+     * every time it is modified.
+     *
+     * Invalid files are reported and the running configuration is kept.
+     */
+    pub async fn watch(path: &str) -> Result<()> {
+        let mut previous = Config::load_path(path)?;
+        previous.push().await?;
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            if let Ok(event) = res {
+                if !event.kind.is_access() {
+                    let _ = tx.send(());
+                }
+            }
+        })
+        .into_diagnostic()?;
+        // Watch the parent directory of files
+        // as editors replace files on save.
+        let target = match Path::new(path) {
+            x if x.is_dir() => x.to_owned(),
+            x => match x.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_owned(),
+                _ => Path::new(".").to_owned(),
+            },
+        };
+        watcher
+            .watch(&target, RecursiveMode::NonRecursive)
+            .into_diagnostic()?;
+
+        while rx.recv().await.is_some() {
+            // Debounce bursts of events
+            while let Ok(Some(_)) =
+                tokio::time::timeout(Duration::from_millis(500), rx.recv()).await
+            {}
+            let config = match Config::load_path(path) {
+                Ok(config) => config,
+                Err(e) => {
+                    println!("{:?}", e);
+                    continue;
+                }
+            };
+            if serde_json::to_value(&config).into_diagnostic()?
+                == serde_json::to_value(&previous).into_diagnostic()?
+            {
+                continue;
+            }
+            if let Err(e) = config.push_diff_over(&mut previous).await {
+                println!("{:?}", e);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+use crate::http::{Request, Response};
+use crate::{ConfigFile, ConfigUnit};
+use serde_json::json;
+// Global vars
+use once_cell::sync::Lazy;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+// Error Handling
+use miette::{IntoDiagnostic, Result};
+
+// Apply one change at a time.
+static LOCK: Lazy<Arc<Mutex<()>>> = Lazy::new(|| Arc::new(Mutex::new(())));
+
+/**
+* Control api routes:
+* - GET /units
+* - PUT /units/<uuid>
+* - DELETE /units/<uuid>
+*/
+pub async fn route(req: &Request) -> Response {
+    let path: Vec<&str> = req.path.trim_matches('/').split('/').collect();
+    let res = match (req.method.as_str(), &path[..]) {
+        ("GET", ["units"]) => get_units().await,
+        ("PUT", ["units", uuid]) => put_unit(uuid, &req.body).await,
+        ("DELETE", ["units", uuid]) => delete_unit(uuid).await,
+        _ => Ok(Response::json(
+            "404 Not Found",
+            &json!({ "error": "Not found" }),
+        )),
+    };
+    res.unwrap_or_else(|e| {
+        Response::json(
+            "500 Internal Server Error",
+            &json!({ "error": e.to_string() }),
+        )
+    })
+}
+
+async fn get_units() -> Result<Response> {
+    let config = ConfigFile::pull().await?;
+    let units = serde_json::to_value(&config.unit).into_diagnostic()?;
+    Ok(Response::json("200 OK", &units))
+}
+
+/**
+* Replace the unit.
+* The previous unit is restored if the new one is rejected.
+*/
+async fn put_unit(uuid: &str, body: &[u8]) -> Result<Response> {
+    let mut value: serde_json::Value = match serde_json::from_slice(body) {
+        Ok(value) => value,
+        Err(e) => return Ok(bad_request(&e.to_string())),
+    };
+    match value.get("uuid") {
+        None => {
+            if let Some(x) = value.as_object_mut() {
+                x.insert("uuid".to_owned(), json!(uuid));
+            }
+        }
+        Some(x) if x == uuid => {}
+        Some(x) => {
+            let message = format!("Unit uuid {} doesn't match the path uuid {:?}", x, uuid);
+            return Ok(bad_request(&message));
+        }
+    };
+    let unit: ConfigUnit = match serde_json::from_value(value) {
+        Ok(unit) => unit,
+        Err(e) => return Ok(bad_request(&e.to_string())),
+    };
+    // Check before removing the registered unit
+    if let Err(e) = unit.validate() {
+        return Ok(bad_request(&e.to_string()));
+    }
+
+    let _lock = LOCK.lock().await;
+    let previous = ConfigFile::pull()
+        .await?
+        .unit
+        .into_iter()
+        .find(|x| x.uuid == uuid);
+    unit.remove_from_db().await?;
+    if let Err(e) = unit.push().await {
+        unit.remove_from_db().await?;
+        if let Some(previous) = previous {
+            previous.push().await?;
+        }
+        return Ok(bad_request(&e.to_string()));
+    }
+    let unit = serde_json::to_value(&unit).into_diagnostic()?;
+    Ok(Response::json("200 OK", &unit))
+}
+
+async fn delete_unit(uuid: &str) -> Result<Response> {
+    let _lock = LOCK.lock().await;
+    let unit = ConfigFile::pull()
+        .await?
+        .unit
+        .into_iter()
+        .find(|x| x.uuid == uuid);
+    match unit {
+        Some(unit) => {
+            unit.remove().await?;
+            Ok(Response::json(
+                "200 OK",
+                &json!({ "success": "Unit removed" }),
+            ))
+        }
+        None => Ok(Response::json(
+            "404 Not Found",
+            &json!({ "error": format!("No unit with uuid {:?}", uuid) }),
+        )),
+    }
+}
+
+fn bad_request(message: &str) -> Response {
+    Response::json("400 Bad Request", &json!({ "error": message }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    // Error Handling
+    use miette::Result;
+
+    fn request(method: &str, path: &str, body: &str) -> Request {
+        Request {
+            method: method.to_owned(),
+            path: path.to_owned(),
+            body: body.as_bytes().to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn register_and_deregister_unit() -> Result<()> {
+        ConfigFile::default().set().await?;
+        let uuid = "9b1f0c9e-3a44-4c1e-8f7e-1d2a3b4c5d6e";
+        let body = r#"{
+          "listeners": ["*:443"],
+          "match": { "hosts": ["preview.example.com"] },
+          "action": { "proxy": "http://127.0.0.1:8444" }
+        }"#;
+        let path = format!("/units/{}", uuid);
+
+        let res = route(&request("PUT", &path, body)).await;
+        assert_eq!(res.status, "200 OK");
+        let res = route(&request("GET", "/units", "")).await;
+        assert!(res.body.contains(uuid));
+
+        // Mismatching uuid
+        let res = route(&request(
+            "PUT",
+            "/units/other",
+            &format!(r#"{{"uuid": "{}"}}"#, uuid),
+        ))
+        .await;
+        assert_eq!(res.status, "400 Bad Request");
+
+        let res = route(&request("DELETE", &path, "")).await;
+        assert_eq!(res.status, "200 OK");
+        let res = route(&request("DELETE", &path, "")).await;
+        assert_eq!(res.status, "404 Not Found");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn keep_unit_replaced_without_action() -> Result<()> {
+        ConfigFile::default().set().await?;
+        let uuid = "4a7c2e1f-8b3d-4f6a-9c0e-2d1b3a4c5e6f";
+        let body = r#"{
+          "listeners": ["*:443"],
+          "match": { "hosts": ["kept.example.com"] },
+          "action": { "proxy": "http://127.0.0.1:8445" }
+        }"#;
+        let path = format!("/units/{}", uuid);
+        let res = route(&request("PUT", &path, body)).await;
+        assert_eq!(res.status, "200 OK");
+
+        let body = r#"{
+          "listeners": ["*:443"],
+          "match": { "hosts": ["kept.example.com"] }
+        }"#;
+        let res = route(&request("PUT", &path, body)).await;
+        assert_eq!(res.status, "400 Bad Request");
+        assert!(res.body.contains("no action"));
+
+        // The registered unit is left in place.
+        let units = ConfigFile::pull().await?.unit;
+        let unit = units.iter().find(|x| x.uuid == uuid).unwrap();
+        assert_eq!(
+            unit.action.clone().unwrap().proxy(),
+            Some("http://127.0.0.1:8445".to_owned())
+        );
+        route(&request("DELETE", &path, "")).await;
+        Ok(())
+    }
+}
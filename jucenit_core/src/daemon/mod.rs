@@ -0,0 +1,121 @@
+mod api;
+// Reexport
+pub use api::route;
+
+use crate::http::{Request, Response};
+use serde_json::json;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+// Server
+use tokio::fs;
+use tokio::net::{UnixListener, UnixStream};
+// Error Handling
+use miette::{IntoDiagnostic, Result};
+
+/**
+* The local control api.
+* Services register and deregister their units at runtime
+* through a unix socket.
+*/
+#[derive(Debug, Clone)]
+pub struct Daemon {
+    pub socket: String,
+    // Require "Authorization: Bearer <token>" instead of peer credentials.
+    pub token: Option<String>,
+}
+impl Default for Daemon {
+    fn default() -> Self {
+        Daemon {
+            socket: "/run/jucenit/api.sock".to_owned(),
+            token: None,
+        }
+    }
+}
+impl Daemon {
+    /**
+     * Serve the control api on the unix socket.
+     */
+    pub async fn serve(&self) -> Result<()> {
+        let path = Path::new(&self.socket);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await.into_diagnostic()?;
+        }
+        // Remove socket left by a previous run
+        let _ = fs::remove_file(path).await;
+        let listener = UnixListener::bind(path).into_diagnostic()?;
+        fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))
+            .await
+            .into_diagnostic()?;
+        // Without token, only the socket owner and root are trusted.
+        let owner = fs::metadata(path).await.into_diagnostic()?.uid();
+
+        loop {
+            let (stream, _) = listener.accept().await.into_diagnostic()?;
+            let token = self.token.clone();
+            tokio::spawn(async move {
+                let _ = handle(stream, token, owner).await;
+            });
+        }
+    }
+}
+
+async fn handle(mut stream: UnixStream, token: Option<String>, owner: u32) -> Result<()> {
+    let res = match Request::read(&mut stream).await {
+        Err(e) => Response::refused(&e),
+        Ok(req) => {
+            if authorized(&stream, &req, &token, owner) {
+                route(&req).await
+            } else {
+                Response::json("401 Unauthorized", &json!({ "error": "Unauthorized" }))
+            }
+        }
+    };
+    res.write(&mut stream).await
+}
+
+fn authorized(stream: &UnixStream, req: &Request, token: &Option<String>, owner: u32) -> bool {
+    match token {
+        Some(token) => req.headers.get("authorization") == Some(&format!("Bearer {}", token)),
+        None => stream
+            .peer_cred()
+            .map(|x| x.uid() == 0 || x.uid() == owner)
+            .unwrap_or(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    async fn query(socket: &str, raw: &str) -> Result<String> {
+        let mut stream = UnixStream::connect(socket).await.into_diagnostic()?;
+        stream.write_all(raw.as_bytes()).await.into_diagnostic()?;
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await.into_diagnostic()?;
+        Ok(res)
+    }
+
+    #[tokio::test]
+    async fn require_token() -> Result<()> {
+        let daemon = Daemon {
+            socket: "/tmp/jucenit/test_api.sock".to_owned(),
+            token: Some("secret".to_owned()),
+        };
+        tokio::spawn({
+            let daemon = daemon.clone();
+            async move { daemon.serve().await }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let res = query(&daemon.socket, "GET /units HTTP/1.1\r\n\r\n").await?;
+        assert!(res.starts_with("HTTP/1.1 401"));
+        let res = query(
+            &daemon.socket,
+            "GET /unknown HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n",
+        )
+        .await?;
+        assert!(res.starts_with("HTTP/1.1 404"));
+        Ok(())
+    }
+}
@@ -1,7 +1,7 @@
 use crate::cast::Config as ConfigFile;
 use crate::{Action, Match};
 use indexmap::IndexMap;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 // File
 use crate::nginx::Config as NginxConfig;
 use std::env;
@@ -22,6 +22,7 @@ use sea_orm::{
 use sea_orm::{prelude::*, sea_query::OnConflict, ActiveValue, InsertResult, MockDatabase};
 
 // Error Handling
+use crate::error::JucenitError;
 use miette::{Error, IntoDiagnostic, Result, WrapErr};
 
 // Global vars
@@ -29,27 +30,88 @@ use miette::{Error, IntoDiagnostic, Result, WrapErr};
 // use std::sync::Arc;
 // use tokio::sync::Mutex;
 
+// Server wide options set from the configuration file
+const CONFIG_OPTIONS: [&str; 5] = ["mode", "settings", "access_log", "reconcile", "acme"];
+
 pub async fn connect_db() -> Result<DatabaseConnection> {
     let database_url = "sqlite:////var/spool/jucenit/config.sqlite?mode=rw";
-    // let db: DatabaseConnection = Database::connect(database_url).await.into_diagnostic()?;
+    // let db: DatabaseConnection = Database::connect(database_url).await.map_err(JucenitError::from)?;
     let db = Database::connect(database_url).await;
-    match &db {
-        Err(e) => {
-            let db = fresh_db().await?;
-            return Ok(db);
-        }
-        _ => {}
+    if let Err(e) = &db {
+        let db = fresh_db().await?;
+        return Ok(db);
     };
-    Ok(db.into_diagnostic()?)
+    let db = db.map_err(JucenitError::from)?;
+    // Apply pending schema changes to existing databases
+    Migrator::up(&db, None).await.map_err(JucenitError::from)?;
+    Ok(db)
 }
 pub async fn fresh_db() -> Result<DatabaseConnection> {
     let database_url = "sqlite:////var/spool/jucenit/config.sqlite?mode=rwc";
     let db = sea_orm::Database::connect(database_url)
         .await
-        .into_diagnostic()?;
-    Migrator::fresh(&db).await.into_diagnostic()?;
+        .map_err(JucenitError::from)?;
+    Migrator::fresh(&db).await.map_err(JucenitError::from)?;
     Ok(db)
 }
+/**
+* Remove the configuration (units, applications, upstreams and file options)
+* and keep the state jucenit accumulated: certificates, acme accounts,
+* declared objects and provider units.
+*/
+pub async fn clear_config<C: ConnectionTrait>(db: &C) -> Result<()> {
+    MatchHost::delete_many()
+        .exec(db)
+        .await
+        .map_err(JucenitError::from)?;
+    MatchListener::delete_many()
+        .exec(db)
+        .await
+        .map_err(JucenitError::from)?;
+    NgMatch::delete_many()
+        .exec(db)
+        .await
+        .map_err(JucenitError::from)?;
+    prelude::Action::delete_many()
+        .exec(db)
+        .await
+        .map_err(JucenitError::from)?;
+    Host::delete_many()
+        .exec(db)
+        .await
+        .map_err(JucenitError::from)?;
+    Listener::delete_many()
+        .exec(db)
+        .await
+        .map_err(JucenitError::from)?;
+    UpstreamServer::delete_many()
+        .exec(db)
+        .await
+        .map_err(JucenitError::from)?;
+    Upstream::delete_many()
+        .exec(db)
+        .await
+        .map_err(JucenitError::from)?;
+    Application::delete_many()
+        .exec(db)
+        .await
+        .map_err(JucenitError::from)?;
+    GlobalOption::delete_many()
+        .filter(global_option::Column::Name.is_in(CONFIG_OPTIONS))
+        .exec(db)
+        .await
+        .map_err(JucenitError::from)?;
+    Ok(())
+}
+/**
+* Parse a json column of a stored row,
+* a malformed value is a database error naming the row.
+*/
+pub fn parse_column<T: DeserializeOwned>(row: &str, raw: &str) -> Result<T> {
+    serde_json::from_str(raw)
+        .map_err(|e| JucenitError::Database(format!("Malformed {}: {}", row, e)).into())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -62,4 +124,15 @@ mod test {
         fresh_db().await?;
         Ok(())
     }
+    #[test]
+    fn reject_malformed_column() -> Result<()> {
+        let value: Vec<String> = parse_column("option \"owned\"", "[\"a\"]")?;
+        assert_eq!(value, vec!["a".to_owned()]);
+        let err = parse_column::<Vec<String>>("option \"owned\"", "[a").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<JucenitError>(),
+            Some(JucenitError::Database(_))
+        ));
+        Ok(())
+    }
 }
@@ -10,6 +10,7 @@ pub struct Model {
     #[sea_orm(unique)]
     pub ip_socket: String,
     pub tls: Option<String>,
+    pub forwarded: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
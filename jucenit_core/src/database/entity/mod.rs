@@ -2,9 +2,15 @@
 
 pub mod prelude;
 
+pub mod acme_account;
 pub mod action;
+pub mod application;
+pub mod certificate;
+pub mod global_option;
 pub mod host;
 pub mod listener;
 pub mod match_host;
 pub mod match_listener;
 pub mod ng_match;
+pub mod upstream;
+pub mod upstream_server;
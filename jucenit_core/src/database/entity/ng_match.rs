@@ -11,6 +11,8 @@ pub struct Model {
     pub uuid: String,
     pub action_id: i32,
     pub raw_params: Option<String>,
+    pub source: Option<String>,
+    pub acme: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
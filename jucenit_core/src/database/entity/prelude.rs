@@ -1,8 +1,14 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.12.15
 
+pub use super::acme_account::Entity as AcmeAccount;
 pub use super::action::Entity as Action;
+pub use super::application::Entity as Application;
+pub use super::certificate::Entity as Certificate;
+pub use super::global_option::Entity as GlobalOption;
 pub use super::host::Entity as Host;
 pub use super::listener::Entity as Listener;
 pub use super::match_host::Entity as MatchHost;
 pub use super::match_listener::Entity as MatchListener;
 pub use super::ng_match::Entity as NgMatch;
+pub use super::upstream::Entity as Upstream;
+pub use super::upstream_server::Entity as UpstreamServer;
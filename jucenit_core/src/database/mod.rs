@@ -12,5 +12,5 @@ pub mod entity;
 
 // Reexports
 // pub use crud::*;
-pub use crud::{connect_db, fresh_db};
+pub use crud::{clear_config, connect_db, fresh_db, parse_column};
 pub use entity::*;
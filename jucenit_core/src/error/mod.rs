@@ -2,10 +2,78 @@
 // mod test;
 
 // Error Handling
-use miette::{Diagnostic, SourceOffset, SourceSpan};
+use miette::{Diagnostic, NamedSource, SourceOffset, SourceSpan};
 use std::{convert, fmt, option};
 use thiserror::Error;
 
+/**
+Every error kind raised by jucenit.
+Functions return miette reports,
+match on kinds with `report.downcast_ref::<JucenitError>()`.
+*/
+#[derive(Error, Diagnostic, Debug)]
+pub enum JucenitError {
+    #[error("Database error: {0}")]
+    #[diagnostic(
+        code(jucenit::database),
+        help("Check that /var/spool/jucenit exists and is writable")
+    )]
+    Database(String),
+    #[error("Nginx-unit api error: {0}")]
+    #[diagnostic(
+        code(jucenit::unit_api),
+        help("Check that unitd is running and its control api is reachable")
+    )]
+    UnitApi(String),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Rejected(#[from] UnitError),
+    #[error("ACME error: {0}")]
+    #[diagnostic(
+        code(jucenit::acme),
+        help("Check that the domain resolves to this server and port 80 is reachable")
+    )]
+    Acme(String),
+    #[error("Invalid configuration: {0}")]
+    #[diagnostic(code(jucenit::config))]
+    Config(String),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Cast(#[from] CastError),
+    #[error("Invalid certificate for {host:?}: {reason}")]
+    #[diagnostic(
+        code(jucenit::certificate),
+        help("Renew the certificate with `jucenit ssl --renew`")
+    )]
+    Certificate { host: String, reason: String },
+}
+impl From<sea_orm::DbErr> for JucenitError {
+    fn from(e: sea_orm::DbErr) -> Self {
+        JucenitError::Database(e.to_string())
+    }
+}
+impl From<acme2::Error> for JucenitError {
+    fn from(e: acme2::Error) -> Self {
+        JucenitError::Acme(e.to_string())
+    }
+}
+
+impl From<TomlError> for JucenitError {
+    fn from(e: TomlError) -> Self {
+        JucenitError::Cast(e.into())
+    }
+}
+impl From<YamlError> for JucenitError {
+    fn from(e: YamlError) -> Self {
+        JucenitError::Cast(e.into())
+    }
+}
+impl From<JsonError> for JucenitError {
+    fn from(e: JsonError) -> Self {
+        JucenitError::Cast(e.into())
+    }
+}
+
 #[derive(Error, Diagnostic, Debug)]
 pub enum CastError {
     #[error(transparent)]
@@ -133,3 +201,23 @@ impl TomlError {
         }
     }
 }
+
+/**
+A nginx-unit rejection report,
+pointing to the unit and config file that broke the configuration.
+*/
+#[derive(Error, Diagnostic, Debug)]
+#[diagnostic(code(nginx::config))]
+#[error("Nginx-unit rejected the configuration: {error}")]
+pub struct UnitError {
+    pub error: String,
+    // Unit response detail and error location
+    #[help]
+    pub help: Option<String>,
+    // Uuid of the offending unit
+    pub uuid: Option<String>,
+    #[label("this unit")]
+    pub at: Option<SourceSpan>,
+    #[source_code]
+    pub src: Option<NamedSource<String>>,
+}
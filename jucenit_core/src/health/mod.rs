@@ -0,0 +1,105 @@
+mod watch;
+// Reexport
+pub use watch::{ServerHealth, HEALTH};
+
+use serde::{Deserialize, Serialize};
+// Probes
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+/**
+* Active health checking of upstream servers.
+* Servers failing "fall" consecutive probes are removed from rotation
+* and restored after "rise" consecutive successes.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct HealthCheck {
+    #[serde(default)]
+    pub probe: Probe,
+    // Http probe path (ex: "/healthz")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    // Seconds between probes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval: Option<u64>,
+    // Seconds before a probe is considered failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fall: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rise: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Probe {
+    // Tcp connect
+    #[default]
+    Tcp,
+    // Http GET, healthy on 2xx and 3xx responses
+    Http,
+}
+
+impl HealthCheck {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval.unwrap_or(10))
+    }
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout.unwrap_or(2))
+    }
+    pub fn fall(&self) -> u32 {
+        self.fall.unwrap_or(3)
+    }
+    pub fn rise(&self) -> u32 {
+        self.rise.unwrap_or(2)
+    }
+    /**
+     * Probe a server ip socket (ex: "10.0.0.1:80").
+     */
+    pub async fn probe(&self, address: &str) -> bool {
+        match self.probe {
+            Probe::Tcp => tokio::time::timeout(self.timeout(), TcpStream::connect(address))
+                .await
+                .map(|x| x.is_ok())
+                .unwrap_or(false),
+            Probe::Http => {
+                let url = format!(
+                    "http://{}{}",
+                    address,
+                    self.path.clone().unwrap_or("/".to_owned())
+                );
+                let client = reqwest::Client::builder().timeout(self.timeout()).build();
+                let res = match client {
+                    Ok(client) => client.get(url).send().await,
+                    Err(_) => return false,
+                };
+                match res {
+                    Ok(res) => res.status().is_success() || res.status().is_redirection(),
+                    Err(_) => false,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    // Error Handling
+    use miette::{IntoDiagnostic, Result};
+
+    #[tokio::test]
+    async fn probe_tcp() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.into_diagnostic()?;
+        let address = listener.local_addr().into_diagnostic()?.to_string();
+
+        let check = HealthCheck::default();
+        assert!(check.probe(&address).await);
+        drop(listener);
+        assert!(!check.probe(&address).await);
+        Ok(())
+    }
+}
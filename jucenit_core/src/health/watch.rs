@@ -0,0 +1,175 @@
+use super::HealthCheck;
+use crate::nginx::Config as NginxConfig;
+use std::collections::HashMap;
+// Database
+use crate::database::connect_db;
+use crate::database::entity::{prelude::*, *};
+use sea_orm::{prelude::*, query::*, ActiveValue};
+// Global vars
+use once_cell::sync::Lazy;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+// Loop
+use futures::future::join_all;
+use std::time::{Duration, Instant};
+// Error Handling
+use crate::error::JucenitError;
+use miette::{IntoDiagnostic, Result};
+
+// Probe counters by upstream name and server address
+type Probes = HashMap<(String, String), ServerHealth>;
+/**
+* Probe counters of every checked server,
+* indexed by upstream name and server address.
+*/
+pub static HEALTH: Lazy<Arc<Mutex<Probes>>> = Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+#[derive(Debug, Clone, Default)]
+pub struct ServerHealth {
+    // Consecutive probe results
+    pub successes: u32,
+    pub failures: u32,
+    pub last_probe: Option<Instant>,
+}
+impl ServerHealth {
+    /**
+     * Count a probe result.
+     * Returns the new server state ("down") when a threshold is crossed.
+     */
+    pub fn record(&mut self, healthy: bool, check: &HealthCheck, down: bool) -> Option<bool> {
+        if healthy {
+            self.successes += 1;
+            self.failures = 0;
+            if down && self.successes >= check.rise() {
+                return Some(false);
+            }
+        } else {
+            self.failures += 1;
+            self.successes = 0;
+            if !down && self.failures >= check.fall() {
+                return Some(true);
+            }
+        }
+        None
+    }
+}
+
+impl HealthCheck {
+    /**
+     * Probe upstream servers at their configured intervals
+     * and update nginx-unit when servers go down or recover.
+     */
+    pub async fn watch() -> Result<()> {
+        loop {
+            // A failed round (ex: database locked) must not stop the daemon.
+            if let Err(e) = HealthCheck::run().await {
+                println!("{:?}", e);
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+    /**
+     * Probe every server that is due for a check.
+     */
+    pub async fn run() -> Result<()> {
+        let db = connect_db().await?;
+        let upstreams: Vec<(upstream::Model, Vec<upstream_server::Model>)> = Upstream::find()
+            .find_with_related(UpstreamServer)
+            .filter(upstream::Column::HealthCheck.is_not_null())
+            .all(&db)
+            .await
+            .map_err(JucenitError::from)?;
+
+        // Select due servers
+        let mut probes: Vec<(String, upstream_server::Model, HealthCheck)> = vec![];
+        {
+            let mut state = HEALTH.lock().await;
+            let mut checked: Vec<(String, String)> = vec![];
+            for (upstream, servers) in upstreams {
+                let check: HealthCheck =
+                    serde_json::from_str(upstream.health_check.as_ref().unwrap())
+                        .into_diagnostic()?;
+                for server in servers {
+                    let key = (upstream.name.clone(), server.address.clone());
+                    let health = state.entry(key.clone()).or_default();
+                    let due = health
+                        .last_probe
+                        .map(|x| x.elapsed() >= check.interval())
+                        .unwrap_or(true);
+                    if due {
+                        health.last_probe = Some(Instant::now());
+                        probes.push((upstream.name.clone(), server, check.clone()));
+                    }
+                    checked.push(key);
+                }
+            }
+            // Forget removed servers
+            state.retain(|k, _| checked.contains(k));
+        }
+        if probes.is_empty() {
+            return Ok(());
+        }
+
+        let results = join_all(
+            probes
+                .iter()
+                .map(|(_, server, check)| check.probe(&server.address)),
+        )
+        .await;
+
+        // Apply state transitions
+        let mut changed = false;
+        for ((name, server, check), healthy) in probes.into_iter().zip(results) {
+            let transition = {
+                let mut state = HEALTH.lock().await;
+                let key = (name.clone(), server.address.clone());
+                state
+                    .get_mut(&key)
+                    .and_then(|x| x.record(healthy, &check, server.down))
+            };
+            if let Some(down) = transition {
+                if down {
+                    println!(
+                        "health: server {} of upstream {} is down",
+                        server.address, name
+                    );
+                } else {
+                    println!(
+                        "health: server {} of upstream {} is up",
+                        server.address, name
+                    );
+                }
+                let mut active = upstream_server::ActiveModel::from(server);
+                active.down = ActiveValue::Set(down);
+                active.update(&db).await.map_err(JucenitError::from)?;
+                changed = true;
+            }
+        }
+        if changed {
+            NginxConfig::pull().await?.set().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cross_thresholds() {
+        let check = HealthCheck {
+            fall: Some(2),
+            rise: Some(2),
+            ..Default::default()
+        };
+        let mut health = ServerHealth::default();
+        assert_eq!(health.record(false, &check, false), None);
+        assert_eq!(health.record(false, &check, false), Some(true));
+        // A single success doesn't restore the server
+        assert_eq!(health.record(true, &check, true), None);
+        assert_eq!(health.record(false, &check, true), None);
+        assert_eq!(health.record(true, &check, true), None);
+        assert_eq!(health.record(true, &check, true), Some(false));
+    }
+}
@@ -0,0 +1,241 @@
+//!
+//! A minimal HTTP/1.1 implementation for the local endpoints
+//! (metrics and control api).
+//! One request per connection.
+//!
+
+use indexmap::IndexMap;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+// Error Handling
+use miette::{Diagnostic, Error, IntoDiagnostic, Result};
+use thiserror::Error;
+
+// Refuse larger request bodies.
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+// Refuse larger request line and headers.
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+
+/**
+* Requests refused before routing.
+*/
+#[derive(Error, Diagnostic, Debug)]
+pub enum RequestError {
+    #[error("Malformed request: {0}")]
+    Malformed(String),
+    #[error("Request header fields are too large")]
+    HeadTooLarge,
+    #[error("Request body is too large")]
+    BodyTooLarge,
+}
+impl RequestError {
+    pub fn status(&self) -> &str {
+        match self {
+            RequestError::Malformed(_) => "400 Bad Request",
+            RequestError::HeadTooLarge => "431 Request Header Fields Too Large",
+            RequestError::BodyTooLarge => "413 Content Too Large",
+        }
+    }
+}
+
+/**
+* Read a line without exceeding the remaining head size.
+*/
+async fn read_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    remaining: &mut usize,
+) -> Result<String> {
+    let mut line = vec![];
+    let n = reader
+        .take(*remaining as u64 + 1)
+        .read_until(b'\n', &mut line)
+        .await
+        .into_diagnostic()?;
+    if n > *remaining {
+        return Err(RequestError::HeadTooLarge.into());
+    }
+    *remaining -= n;
+    String::from_utf8(line).map_err(|_| RequestError::Malformed("non utf-8 head".to_owned()).into())
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    // Lowercase header names
+    pub headers: IndexMap<String, String>,
+    pub body: Vec<u8>,
+}
+impl Request {
+    /**
+     * Read a request from a stream.
+     */
+    pub async fn read<R: tokio::io::AsyncRead + Unpin>(stream: R) -> Result<Request> {
+        let mut reader = BufReader::new(stream);
+        let mut remaining = MAX_HEAD_SIZE;
+
+        let line = read_line(&mut reader, &mut remaining).await?;
+        let mut parts = line.split_whitespace();
+        let (method, path) = match (parts.next(), parts.next()) {
+            (Some(method), Some(path)) => (method.to_owned(), path.to_owned()),
+            _ => {
+                let message = format!("request line {:?}", line);
+                return Err(RequestError::Malformed(message).into());
+            }
+        };
+
+        let mut headers = IndexMap::new();
+        loop {
+            let line = read_line(&mut reader, &mut remaining).await?;
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() >= MAX_HEADERS {
+                return Err(RequestError::HeadTooLarge.into());
+            }
+            if let Some((k, v)) = line.split_once(':') {
+                headers.insert(k.trim().to_lowercase(), v.trim().to_owned());
+            }
+        }
+
+        let length: usize = match headers.get("content-length") {
+            Some(x) => x
+                .parse()
+                .map_err(|_| RequestError::Malformed(format!("content-length {:?}", x)))?,
+            None => 0,
+        };
+        if length > MAX_BODY_SIZE {
+            return Err(RequestError::BodyTooLarge.into());
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).await.into_diagnostic()?;
+
+        Ok(Request {
+            method,
+            path,
+            headers,
+            body,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    // Status line code and reason (ex: "200 OK")
+    pub status: String,
+    pub content_type: String,
+    pub body: String,
+}
+impl Response {
+    /**
+     * The response to a request refused before routing.
+     */
+    pub fn refused(e: &Error) -> Response {
+        let status = e
+            .downcast_ref::<RequestError>()
+            .map(|x| x.status())
+            .unwrap_or("400 Bad Request");
+        Response::json(status, &serde_json::json!({ "error": e.to_string() }))
+    }
+    pub fn new(status: &str, content_type: &str, body: &str) -> Response {
+        Response {
+            status: status.to_owned(),
+            content_type: content_type.to_owned(),
+            body: body.to_owned(),
+        }
+    }
+    /**
+     * A json response like nginx-unit ones:
+     * {"success": "..."} or {"error": "..."}.
+     */
+    pub fn json(status: &str, body: &serde_json::Value) -> Response {
+        Response::new(status, "application/json", &format!("{:#}\n", body))
+    }
+    /**
+     * Write the response to a stream and close the connection.
+     */
+    pub async fn write<W: tokio::io::AsyncWrite + Unpin>(&self, mut stream: W) -> Result<()> {
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            self.content_type,
+            self.body.len(),
+            self.body
+        );
+        stream
+            .write_all(response.as_bytes())
+            .await
+            .into_diagnostic()?;
+        stream.shutdown().await.into_diagnostic()?;
+        Ok(())
+    }
+}
+
+/**
+* Send a GET request over a unix socket
+* and return the response body.
+*/
+pub async fn get_unix(socket: &str, path: &str) -> Result<String> {
+    let mut stream = UnixStream::connect(socket).await.into_diagnostic()?;
+    // Http/1.0 avoids chunked responses
+    let request = format!("GET {} HTTP/1.0\r\nHost: localhost\r\n\r\n", path);
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .into_diagnostic()?;
+    let mut res = String::new();
+    stream.read_to_string(&mut res).await.into_diagnostic()?;
+
+    let (head, body) = res
+        .split_once("\r\n\r\n")
+        .ok_or(Error::msg(format!("Malformed response from {:?}", socket)))?;
+    let status = head
+        .lines()
+        .next()
+        .and_then(|x| x.split_whitespace().nth(1))
+        .unwrap_or_default();
+    if !status.starts_with('2') {
+        let message = format!("{:?} answered {} on {:?}: {}", socket, status, path, body);
+        return Err(Error::msg(message));
+    }
+    Ok(body.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    // Error Handling
+    use miette::Result;
+
+    #[tokio::test]
+    async fn read_request() -> Result<()> {
+        let raw = "PUT /units/abc HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\n\r\n{}";
+        let req = Request::read(raw.as_bytes()).await?;
+        assert_eq!(req.method, "PUT");
+        assert_eq!(req.path, "/units/abc");
+        assert_eq!(req.headers["host"], "localhost");
+        assert_eq!(req.body, b"{}");
+        Ok(())
+    }
+    #[tokio::test]
+    async fn refuse_large_heads() -> Result<()> {
+        let raw = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_HEAD_SIZE));
+        let err = Request::read(raw.as_bytes()).await.unwrap_err();
+        assert!(Response::refused(&err).status.starts_with("431"));
+
+        let mut raw = "GET / HTTP/1.1\r\n".to_owned();
+        for i in 0..=MAX_HEADERS {
+            raw += &format!("X-{}: 1\r\n", i);
+        }
+        raw += "\r\n";
+        let err = Request::read(raw.as_bytes()).await.unwrap_err();
+        assert!(Response::refused(&err).status.starts_with("431"));
+
+        let raw = "PUT / HTTP/1.1\r\nContent-Length: 99999999\r\n\r\n";
+        let err = Request::read(raw.as_bytes()).await.unwrap_err();
+        assert!(Response::refused(&err).status.starts_with("413"));
+        Ok(())
+    }
+}
@@ -1,9 +1,21 @@
 #![cfg_attr(debug_assertions, allow(dead_code, unused_imports, unused_variables))]
 
 mod cast;
+mod daemon;
 pub mod database;
 mod error;
+mod health;
+mod http;
+pub mod metrics;
 pub mod nginx;
+mod provider;
+mod reconcile;
 mod ssl;
 pub use cast::{Action, Config as ConfigFile, Match, Unit as ConfigUnit};
-pub use nginx::{CertificateStore, Config as NginxConfig, Nginx};
+pub use daemon::Daemon;
+pub use error::{CastError, JucenitError, UnitError};
+pub use health::HealthCheck;
+pub use nginx::{CertificateStore, Config as NginxConfig, Nginx, Status as NginxStatus};
+pub use provider::ContainerProvider;
+pub use reconcile::{Drift, Reconcile};
+pub use ssl::Acme;
@@ -0,0 +1,240 @@
+mod serve;
+// Reexport
+pub use serve::serve;
+
+use crate::nginx::certificate::CertificateInfo;
+use crate::nginx::{CertificateStore, Status};
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::fmt::Write;
+// Global vars
+use once_cell::sync::Lazy;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+pub static METRICS: Lazy<Arc<Mutex<Metrics>>> =
+    Lazy::new(|| Arc::new(Mutex::new(Metrics::default())));
+
+/**
+* Counters collected by the running process.
+* Certificates and nginx-unit statistics are fetched on scrape.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    // Host to count
+    pub renewal_attempts: IndexMap<String, u64>,
+    pub renewal_failures: IndexMap<String, u64>,
+    // Unix timestamp of the last successful configuration update
+    pub last_apply: Option<i64>,
+}
+impl Metrics {
+    /**
+     * Collect every metric in Prometheus text format.
+     */
+    pub async fn gather() -> String {
+        let certificates = CertificateStore::get_all().await.ok();
+        let status = Status::get().await.ok();
+        let metrics = METRICS.lock().await.clone();
+        metrics.render(certificates.as_ref(), status.as_ref())
+    }
+    pub fn render(
+        &self,
+        certificates: Option<&HashMap<String, CertificateInfo>>,
+        status: Option<&Status>,
+    ) -> String {
+        let mut s = String::new();
+
+        // Certificates
+        header(
+            &mut s,
+            "jucenit_certificate_expiry_days",
+            "gauge",
+            "Days until the certificate expires.",
+        );
+        if let Some(certificates) = certificates {
+            let mut certificates: Vec<(&String, &CertificateInfo)> = certificates.iter().collect();
+            certificates.sort_by_key(|(k, _)| k.to_owned());
+            for (host, cert) in certificates {
+                if let Ok(rest) = cert.validity.remaining_time() {
+                    let days = rest.num_seconds() as f64 / 86400.0;
+                    let _ = writeln!(
+                        s,
+                        "jucenit_certificate_expiry_days{{host=\"{}\"}} {:.3}",
+                        escape(host),
+                        days
+                    );
+                }
+            }
+        }
+        header(
+            &mut s,
+            "jucenit_certificate_renewal_attempts_total",
+            "counter",
+            "Certificate renewal attempts.",
+        );
+        for (host, count) in &self.renewal_attempts {
+            let _ = writeln!(
+                s,
+                "jucenit_certificate_renewal_attempts_total{{host=\"{}\"}} {}",
+                escape(host),
+                count
+            );
+        }
+        header(
+            &mut s,
+            "jucenit_certificate_renewal_failures_total",
+            "counter",
+            "Failed certificate renewals.",
+        );
+        for (host, count) in &self.renewal_failures {
+            let _ = writeln!(
+                s,
+                "jucenit_certificate_renewal_failures_total{{host=\"{}\"}} {}",
+                escape(host),
+                count
+            );
+        }
+
+        // Configuration
+        if let Some(last_apply) = self.last_apply {
+            header(
+                &mut s,
+                "jucenit_config_last_apply_timestamp_seconds",
+                "gauge",
+                "Last successful nginx-unit configuration update.",
+            );
+            let _ = writeln!(
+                s,
+                "jucenit_config_last_apply_timestamp_seconds {}",
+                last_apply
+            );
+        }
+
+        // Nginx-unit statistics
+        header(
+            &mut s,
+            "jucenit_unit_up",
+            "gauge",
+            "Whether nginx-unit status could be fetched.",
+        );
+        let status = match status {
+            Some(status) => status,
+            None => {
+                let _ = writeln!(s, "jucenit_unit_up 0");
+                return s;
+            }
+        };
+        let _ = writeln!(s, "jucenit_unit_up 1");
+        let c = &status.connections;
+        for (name, kind, value) in [
+            ("connections_accepted_total", "counter", c.accepted),
+            ("connections_active", "gauge", c.active),
+            ("connections_idle", "gauge", c.idle),
+            ("connections_closed_total", "counter", c.closed),
+            (
+                "requests_total",
+                "counter",
+                status.requests.total.unwrap_or(0),
+            ),
+        ] {
+            let name = format!("jucenit_unit_{}", name);
+            header(&mut s, &name, kind, "Nginx-unit status counter.");
+            let _ = writeln!(s, "{} {}", name, value);
+        }
+        header(
+            &mut s,
+            "jucenit_unit_application_processes",
+            "gauge",
+            "Application processes by state.",
+        );
+        for (app, app_status) in &status.applications {
+            let p = &app_status.processes;
+            for (state, value) in [
+                ("running", p.running),
+                ("starting", p.starting),
+                ("idle", p.idle),
+            ] {
+                let _ = writeln!(
+                    s,
+                    "jucenit_unit_application_processes{{application=\"{}\",state=\"{}\"}} {}",
+                    escape(app),
+                    state,
+                    value
+                );
+            }
+        }
+        header(
+            &mut s,
+            "jucenit_unit_application_requests_active",
+            "gauge",
+            "Requests being processed by the application.",
+        );
+        for (app, app_status) in &status.applications {
+            let _ = writeln!(
+                s,
+                "jucenit_unit_application_requests_active{{application=\"{}\"}} {}",
+                escape(app),
+                app_status.requests.active.unwrap_or(0)
+            );
+        }
+        s
+    }
+}
+
+fn header(s: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(s, "# HELP {} {}", name, help);
+    let _ = writeln!(s, "# TYPE {} {}", name, kind);
+}
+
+/**
+* Escape a label value.
+*/
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    // Error Handling
+    use miette::{IntoDiagnostic, Result};
+
+    #[test]
+    fn render_metrics() -> Result<()> {
+        let mut metrics = Metrics::default();
+        metrics.renewal_attempts.insert("example.com".to_owned(), 2);
+        metrics.renewal_failures.insert("example.com".to_owned(), 1);
+        metrics.last_apply = Some(1760000000);
+
+        let status: Status = serde_json::from_str(
+            r#"{
+              "connections": { "accepted": 10, "active": 1, "idle": 0, "closed": 9 },
+              "requests": { "total": 42 },
+              "applications": {
+                "blog": {
+                  "processes": { "running": 2, "starting": 0, "idle": 1 },
+                  "requests": { "active": 3 }
+                }
+              }
+            }"#,
+        )
+        .into_diagnostic()?;
+
+        let res = metrics.render(None, Some(&status));
+        println!("{}", res);
+        assert!(res.contains("jucenit_certificate_renewal_attempts_total{host=\"example.com\"} 2"));
+        assert!(res.contains("jucenit_certificate_renewal_failures_total{host=\"example.com\"} 1"));
+        assert!(res.contains("jucenit_config_last_apply_timestamp_seconds 1760000000"));
+        assert!(res.contains("jucenit_unit_requests_total 42"));
+        assert!(res.contains(
+            "jucenit_unit_application_processes{application=\"blog\",state=\"running\"} 2"
+        ));
+
+        let res = metrics.render(None, None);
+        assert!(res.contains("jucenit_unit_up 0"));
+        Ok(())
+    }
+}
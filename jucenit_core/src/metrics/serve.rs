@@ -0,0 +1,66 @@
+use super::Metrics;
+use crate::http::{Request, Response};
+// Server
+use tokio::net::{TcpListener, TcpStream};
+// Error Handling
+use miette::{IntoDiagnostic, Result};
+
+/**
+* Serve metrics on "GET /metrics" at the given address (ex: "127.0.0.1:9100").
+*/
+pub async fn serve(address: &str) -> Result<()> {
+    let listener = TcpListener::bind(address).await.into_diagnostic()?;
+    loop {
+        let (stream, _) = listener.accept().await.into_diagnostic()?;
+        tokio::spawn(async move {
+            let _ = handle(stream).await;
+        });
+    }
+}
+
+async fn handle(mut stream: TcpStream) -> Result<()> {
+    let req = match Request::read(&mut stream).await {
+        Ok(req) => req,
+        Err(e) => return Response::refused(&e).write(&mut stream).await,
+    };
+    let res = match (req.method.as_str(), req.path.as_str()) {
+        ("GET", "/metrics") => Response::new(
+            "200 OK",
+            "text/plain; version=0.0.4",
+            &Metrics::gather().await,
+        ),
+        _ => Response::new("404 Not Found", "text/plain", "Not found\n"),
+    };
+    res.write(&mut stream).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn serve_metrics() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.into_diagnostic()?;
+        let address = listener.local_addr().into_diagnostic()?.to_string();
+        drop(listener);
+        tokio::spawn({
+            let address = address.clone();
+            async move { serve(&address).await }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let res = reqwest::get(format!("http://{}/metrics", address))
+            .await
+            .into_diagnostic()?;
+        assert!(res.status().is_success());
+        let body = res.text().await.into_diagnostic()?;
+        assert!(body.contains("# TYPE jucenit_unit_up gauge"));
+
+        let res = reqwest::get(format!("http://{}/", address))
+            .await
+            .into_diagnostic()?;
+        assert_eq!(res.status().as_u16(), 404);
+        Ok(())
+    }
+}
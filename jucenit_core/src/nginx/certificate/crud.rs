@@ -2,9 +2,10 @@ use super::{CertificateInfo, CertificateStore, RawCertificate};
 use serde::{Deserialize, Serialize};
 use std::default::Default;
 // Globals
-use crate::nginx::SETTINGS;
+use crate::nginx::client::client;
+use crate::ssl::Acme;
 // Error Handling
-use crate::error::JsonError;
+use crate::error::{JsonError, JucenitError};
 use miette::{Error, IntoDiagnostic, Result};
 
 use chrono::{DateTime, Duration, FixedOffset, NaiveDateTime, Utc};
@@ -15,37 +16,37 @@ impl CertificateStore {
      * Get a certificate from nginx-unit certificate store.
      */
     pub async fn get(dns: &str) -> Result<CertificateInfo> {
-        let settings = SETTINGS.lock().await.clone();
-
-        let mut cert = reqwest::get(settings.get_url() + "/certificates/" + dns + "/chain")
-            .await
-            .into_diagnostic()?
-            .json::<Vec<CertificateInfo>>()
-            .await
-            .into_diagnostic()?;
-
-        // Get first element
-        let message = format!("No certificate in the store for {:?}", dns);
-        let err = Error::msg(message);
-
-        cert.reverse();
-        cert.pop().ok_or(err)
+        let mut certificates = Self::get_all().await?;
+
+        certificates.remove(dns).ok_or(
+            JucenitError::Certificate {
+                host: dns.to_owned(),
+                reason: "not in the store".to_owned(),
+            }
+            .into(),
+        )
     }
     /**
      * Get every certificate from nginx-unit certificate store.
      */
     pub async fn get_all() -> Result<HashMap<String, CertificateInfo>> {
-        let settings = SETTINGS.lock().await.clone();
-        let res = reqwest::get(settings.get_url() + "/certificates")
-            .await
-            .into_diagnostic()?
-            .json::<HashMap<String, RawCertificate>>()
-            .await
-            .into_diagnostic()?;
+        let res = client().await.get_certificates().await?;
+        let res: HashMap<String, RawCertificate> = serde_json::from_value(res).into_diagnostic()?;
 
         let mut map: HashMap<String, CertificateInfo> = HashMap::new();
         for (k, v) in res.iter() {
-            map.insert(k.to_owned(), v.chain.first().unwrap().to_owned());
+            let cert = v.chain.first().ok_or(JucenitError::Certificate {
+                host: k.to_owned(),
+                reason: "empty certificate chain".to_owned(),
+            })?;
+            // Fail early on unreadable dates
+            cert.validity
+                .remaining_time()
+                .map_err(|e| JucenitError::Certificate {
+                    host: k.to_owned(),
+                    reason: format!("{:?}", e),
+                })?;
+            map.insert(k.to_owned(), cert.to_owned());
         }
         Ok(map)
     }
@@ -53,16 +54,18 @@ impl CertificateStore {
      * Get every certificate non close to expirity from nginx-unit certificate store.
      */
     pub async fn get_all_valid() -> Result<HashMap<String, CertificateInfo>> {
+        let ratio = Acme::pull().await?.ratio()?;
         let mut res = Self::get_all().await?;
-        res.retain(|k, v| !v.validity.should_renew().unwrap());
+        res.retain(|k, v| v.validity.should_renew_at(ratio).is_ok_and(|x| !x));
         Ok(res)
     }
     /**
      * Get every almost expired certificate from nginx-unit certificate store.
      */
     pub async fn get_all_expired() -> Result<HashMap<String, CertificateInfo>> {
+        let ratio = Acme::pull().await?.ratio()?;
         let mut res = Self::get_all().await?;
-        res.retain(|k, v| v.validity.should_renew().unwrap());
+        res.retain(|k, v| v.validity.should_renew_at(ratio).is_ok_and(|x| x));
         Ok(res)
     }
 }
@@ -119,7 +122,7 @@ mod tests {
     async fn get_validity_info() -> Result<()> {
         let dns = "example.com";
         gen_fake_cert(dns).await?;
-        let cert = CertificateStore::get(&dns).await?;
+        let cert = CertificateStore::get(dns).await?;
 
         println!(
             "Certificate remainig vaildity time: {:#?} weeks",
@@ -3,9 +3,10 @@ use serde::{Deserialize, Serialize};
 use std::default::Default;
 // Globals
 use crate::nginx::SETTINGS;
+use crate::ssl::DEFAULT_RENEWAL_RATIO;
 // Error Handling
 use crate::error::JsonError;
-use miette::{Error, IntoDiagnostic, Result};
+use miette::{Error, IntoDiagnostic, Result, WrapErr};
 
 use chrono::{DateTime, Duration, FixedOffset, NaiveDateTime, Utc};
 use std::collections::HashMap;
@@ -23,15 +24,25 @@ pub struct CertificateInfo {
     pub validity: Validity,
 }
 impl CertificateInfo {
-    pub fn should_renew(self: &Self) -> Result<()> {
+    pub fn should_renew(&self) -> Result<()> {
         println!("{:?}", self.validity);
         Ok(())
     }
+    /**
+     * Whether the certificate is valid for every hosts of a group.
+     */
+    pub fn covers(&self, hosts: &[String]) -> bool {
+        hosts
+            .iter()
+            .all(|x| &self.subject.common_name == x || self.subject.alt_names.contains(x))
+    }
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Identity {
     common_name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    alt_names: Vec<String>,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -41,10 +52,13 @@ pub struct Validity {
 }
 impl Validity {
     pub fn remaining_time(&self) -> Result<Duration> {
-        ComputeValidity::from(self).remaining_time()
+        ComputeValidity::try_from(self)?.remaining_time()
     }
     pub fn should_renew(&self) -> Result<bool> {
-        ComputeValidity::from(self).should_renew()
+        ComputeValidity::try_from(self)?.should_renew()
+    }
+    pub fn should_renew_at(&self, ratio: f64) -> Result<bool> {
+        ComputeValidity::try_from(self)?.should_renew_at(ratio)
     }
 }
 
@@ -53,16 +67,19 @@ pub struct ComputeValidity {
     since: DateTime<Utc>,
     until: DateTime<Utc>,
 }
-impl From<&Validity> for ComputeValidity {
-    fn from(e: &Validity) -> ComputeValidity {
-        ComputeValidity {
-            since: NaiveDateTime::parse_from_str(&e.since, "%b %e %T %Y %Z")
-                .unwrap()
-                .and_utc(),
-            until: NaiveDateTime::parse_from_str(&e.until, "%b %e %T %Y %Z")
-                .unwrap()
-                .and_utc(),
-        }
+impl TryFrom<&Validity> for ComputeValidity {
+    type Error = miette::Report;
+    fn try_from(e: &Validity) -> Result<ComputeValidity> {
+        let parse = |date: &str| {
+            NaiveDateTime::parse_from_str(date, "%b %e %T %Y %Z")
+                .map(|x| x.and_utc())
+                .into_diagnostic()
+                .wrap_err(format!("Couldn't parse certificate date {:?}", date))
+        };
+        Ok(ComputeValidity {
+            since: parse(&e.since)?,
+            until: parse(&e.until)?,
+        })
     }
 }
 impl ComputeValidity {
@@ -70,7 +87,58 @@ impl ComputeValidity {
         let rest = self.until - Utc::now();
         Ok(rest)
     }
+    pub fn lifetime(&self) -> Duration {
+        self.until - self.since
+    }
     pub fn should_renew(&self) -> Result<bool> {
-        Ok(self.remaining_time()? <= Duration::weeks(3))
+        self.should_renew_at(DEFAULT_RENEWAL_RATIO)
+    }
+    /**
+     * Whether less than a fraction (ex: 1/3) of the certificate lifetime remains.
+     * Relative thresholds suit short lived (6 days) and long lived (90 days) certificates alike.
+     */
+    pub fn should_renew_at(&self, ratio: f64) -> Result<bool> {
+        let threshold = self.lifetime().num_seconds() as f64 * ratio;
+        Ok((self.remaining_time()?.num_seconds() as f64) <= threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validity(since_days: i64, until_days: i64) -> Validity {
+        let format = "%b %e %T %Y GMT";
+        Validity {
+            since: (Utc::now() + Duration::days(since_days))
+                .format(format)
+                .to_string(),
+            until: (Utc::now() + Duration::days(until_days))
+                .format(format)
+                .to_string(),
+        }
+    }
+    #[test]
+    fn renew_relative_to_lifetime() -> Result<()> {
+        // 6 days certificate with 3 days left
+        assert!(!validity(-3, 3).should_renew()?);
+        assert!(validity(-5, 1).should_renew()?);
+        // 90 days certificate with 40 then 20 days left
+        assert!(!validity(-50, 40).should_renew()?);
+        assert!(validity(-70, 20).should_renew()?);
+        assert!(validity(-50, 40).should_renew_at(0.5)?);
+        Ok(())
+    }
+    #[test]
+    fn cover_group_hosts() {
+        let cert = CertificateInfo {
+            subject: Identity {
+                common_name: "example.com".to_owned(),
+                alt_names: vec!["example.com".to_owned(), "www.example.com".to_owned()],
+            },
+            ..Default::default()
+        };
+        assert!(cert.covers(&["example.com".to_owned(), "www.example.com".to_owned()]));
+        assert!(!cert.covers(&["example.com".to_owned(), "blog.example.com".to_owned()]));
     }
 }
@@ -4,12 +4,14 @@ use miette::{Error, IntoDiagnostic, Result};
 use crate::ssl;
 use crate::ssl::Letsencrypt as LetsencryptCertificate;
 use crate::ConfigFile;
+use indexmap::IndexMap;
 use rayon::prelude::*;
 use std::collections::HashMap;
 
 // Globals
+use crate::metrics::METRICS;
+use crate::nginx::client::client;
 use crate::nginx::Config as NginxConfig;
-use crate::nginx::SETTINGS;
 
 use crate::database::connect_db;
 use crate::database::entity::{prelude::*, *};
@@ -18,13 +20,23 @@ use sea_orm::{prelude::*, query::*, sea_query::OnConflict, ActiveValue, InsertRe
 // Loop
 use futures::future::{join_all, try_join_all};
 use futures::Future;
-use std::thread::sleep;
 use std::time::{Duration, *};
 use tokio::task::JoinSet;
+use tokio::time::sleep;
+// Global vars
+use once_cell::sync::Lazy;
+use std::sync::Arc;
+use tokio::sync::Mutex;
 
 // Struct
 use super::CertificateInfo;
 
+/**
+* Held while certificates are restored or ordered,
+* so that concurrent loops don't place the same orders.
+*/
+pub static RENEWAL_LOCK: Lazy<Arc<Mutex<()>>> = Lazy::new(|| Arc::new(Mutex::new(())));
+
 #[derive(Debug, Clone, Default)]
 pub struct CertificateStore;
 impl CertificateStore {
@@ -34,11 +46,17 @@ impl CertificateStore {
      * and update nginx-unit configuration with fresh ssl.
      */
     pub async fn hydrate() -> Result<()> {
-        let db = connect_db().await?;
-        let hosts = Host::find().all(&db).await.into_diagnostic()?;
-        let domains: Vec<String> = hosts.iter().map(|x| x.domain.clone()).collect();
+        let _lock = RENEWAL_LOCK.lock().await;
+        // Reuse kept certificates before ordering new ones
+        Self::restore().await?;
+
+        // One certificate per host or per group of hosts
+        let groups = ssl::certificate_groups().await?;
+        ssl::set_certificate_groups(&groups).await?;
 
-        let parallel = domains.iter().map(Self::hydrate_one);
+        let parallel = groups
+            .iter()
+            .map(|(name, hosts)| Self::hydrate_one(name, hosts));
         // TODO
         // Use JoinSet instead of join_all for better error report
         try_join_all(parallel).await?;
@@ -48,25 +66,59 @@ impl CertificateStore {
         let config = ConfigFile::pull().await?;
         config.push().await?;
 
+        // Listeners no longer use them.
+        Self::remove_stale(&groups).await?;
+
         // Clean failed challenge routes
         ConfigFile::purge_http_challenge().await?;
 
         Ok(())
     }
+    /**
+     * Remove the issued certificates that no longer cover a group
+     * (ex: per host certificates after grouping hosts by unit).
+     */
+    async fn remove_stale(groups: &IndexMap<String, Vec<String>>) -> Result<()> {
+        let acme = ssl::Acme::pull().await?;
+        let mut names: Vec<String> = vec![];
+        for name in groups.keys() {
+            for (store_name, _) in acme.certificates_for(name).await? {
+                names.push(store_name);
+            }
+        }
+        for name in Self::issued().await? {
+            if names.contains(&name) {
+                continue;
+            }
+            let removed = CertificateStore::remove(&name).await.is_ok()
+                || CertificateStore::get(&name).await.is_err();
+            if removed {
+                Self::forget(&name).await?;
+            }
+        }
+        Ok(())
+    }
 
-    async fn hydrate_one(host: &String) -> Result<()> {
-        let dns = host.to_owned();
+    async fn hydrate_one(name: &String, hosts: &[String]) -> Result<()> {
+        let dns = name.to_owned();
         // For ACME limitation rate reason
-        // Check if a certificate already exists
-        let cert = CertificateStore::get(&dns).await;
-        match cert {
-            Ok(res) => {
-                if res.validity.should_renew()? {
-                    CertificateStore::descrete_update(&dns).await?;
-                }
+        // Check if a certificate already exists, for every key type
+        let mut due = vec![];
+        for (store_name, key_type) in ssl::Acme::pull().await?.certificates_for(&dns).await? {
+            let cert = CertificateStore::get(&store_name).await;
+            let renew = match cert {
+                // Hosts added to the group need a new certificate.
+                Ok(res) => !res.covers(hosts) || ssl::should_renew(&dns, &store_name, &res).await?,
+                Err(_) => true,
+            };
+            if renew {
+                due.push((store_name, key_type));
             }
-            Err(_) => {
-                CertificateStore::descrete_update(&dns).await?;
+        }
+        if !due.is_empty() {
+            // Fail silently.
+            if let Err(e) = CertificateStore::update_keys(&dns, &due).await {
+                println!("{}", e);
             }
         }
         Ok(())
@@ -74,14 +126,15 @@ impl CertificateStore {
     /**
      * Replace a certificate bundle:
      *  - a .pem file
-     *   with intermediate certs and private key)
+     *    (with intermediate certs and private key)
+     *
      * to nginx-unit certificate store
      *
      *  Fail silently.
      */
     pub async fn descrete_update(dns: &str) -> Result<()> {
         // Remove preceding certificate if it exists
-        let res = CertificateStore::update(&dns).await;
+        let res = CertificateStore::update(dns).await;
         match res {
             Ok(res) => {}
             Err(e) => {
@@ -93,25 +146,72 @@ impl CertificateStore {
     /**
      * Replace a certificate bundle:
      *  - a .pem file
-     *   with intermediate certs and private key)
+     *    (with intermediate certs and private key)
+     *
      * to nginx-unit certificate store
+     *
+     * The certificate covers every hosts of its group,
+     * and is issued once per key type.
      */
     pub async fn update(dns: &str) -> Result<serde_json::Value> {
-        let account = ssl::set_account().await?.clone();
-        let bundle = LetsencryptCertificate::get_cert_bundle(&dns, &account).await?;
-        // Remove preceding certificate if it exists
-        let _ = CertificateStore::remove(dns).await;
+        let certificates = ssl::Acme::pull().await?.certificates_for(dns).await?;
+        CertificateStore::update_keys(dns, &certificates).await
+    }
+    /**
+     * Replace some certificate bundles of a group,
+     * by store name and key type.
+     */
+    pub async fn update_keys(
+        dns: &str,
+        certificates: &[(String, ssl::KeyType)],
+    ) -> Result<serde_json::Value> {
+        *METRICS
+            .lock()
+            .await
+            .renewal_attempts
+            .entry(dns.to_owned())
+            .or_default() += 1;
+        let res: Result<serde_json::Value> = async {
+            let acme = ssl::Acme::pull().await?;
+            let directory = acme.directory_for(dns).await?;
+            let account = ssl::set_account(&directory).await?.clone();
+            let hosts = ssl::group_hosts(dns).await?;
+            let mut res = serde_json::Value::Null;
+            for (store_name, key_type) in certificates {
+                let bundle =
+                    LetsencryptCertificate::get_cert_bundle(dns, &hosts, key_type, &account)
+                        .await?;
+                // Remove preceding certificate if it exists
+                let _ = CertificateStore::remove(store_name).await;
 
-        let res = CertificateStore::add(dns, &bundle).await?;
-        Ok(res)
+                res = CertificateStore::add(store_name, &bundle).await?;
+                CertificateStore::save(store_name, &bundle).await?;
+                ssl::set_renewal_id(store_name, &bundle).await?;
+            }
+            Ok(res)
+        }
+        .await;
+        if res.is_err() {
+            *METRICS
+                .lock()
+                .await
+                .renewal_failures
+                .entry(dns.to_owned())
+                .or_default() += 1;
+        }
+        res
     }
     /**
      * Poll certificate store and declared hosts every minutes for changes.
+     * Failures (unreachable nginx-unit or CA...) are reported
+     * and retried on the next poll.
      */
     pub async fn watch() -> Result<()> {
         loop {
-            CertificateStore::hydrate().await?;
-            sleep(Duration::from_secs(60));
+            if let Err(e) = CertificateStore::hydrate().await {
+                println!("{:?}", e);
+            }
+            sleep(Duration::from_secs(60)).await;
         }
     }
     /**
@@ -130,38 +230,18 @@ impl CertificateStore {
     /**
      * Upload a certificate bundle:
      *  - a .pem file
-     *   with intermediate certs and private key)
+     *    (with intermediate certs and private key)
+     *
      * to nginx-unit certificate store
      */
     pub async fn add(dns: &str, bundle: &str) -> Result<serde_json::Value> {
-        let settings = SETTINGS.lock().await.clone();
-        let client = reqwest::Client::new();
-        let res = client
-            .put(settings.get_url() + "/certificates/" + dns)
-            .body(bundle.to_owned())
-            .send()
-            .await
-            .into_diagnostic()?
-            .json::<serde_json::Value>()
-            .await
-            .into_diagnostic()?;
-        Ok(res)
+        client().await.put_certificate(dns, bundle).await
     }
     /**
      * Remove a certificate from nginx-unit certificate store.
      */
     pub async fn remove(dns: &str) -> Result<serde_json::Value> {
-        let settings = SETTINGS.lock().await.clone();
-        let client = reqwest::Client::new();
-        let res = client
-            .delete(settings.get_url() + "/certificates/" + &dns)
-            .send()
-            .await
-            .into_diagnostic()?
-            .json::<serde_json::Value>()
-            .await
-            .into_diagnostic()?;
-        Ok(res)
+        client().await.delete_certificate(dns).await
     }
 }
 
@@ -201,8 +281,7 @@ mod tests {
     // #[tokio::test]
     // #[serial]
     async fn clean_cert_store() -> Result<()> {
-        let res = CertificateStore::clean().await?;
-        println!("{:#?}", res);
+        CertificateStore::clean().await?;
         Ok(())
     }
     // #[tokio::test]
@@ -238,7 +317,7 @@ mod tests {
     async fn hydrate_cert_store() -> Result<()> {
         set_testing_config().await?;
 
-        let res = CertificateStore::hydrate().await?;
+        CertificateStore::hydrate().await?;
 
         let certificates = CertificateStore::get_all().await?;
         let mut dns_list: Vec<String> = certificates.into_keys().collect();
@@ -253,4 +332,41 @@ mod tests {
         assert_eq!(expected, dns_list);
         Ok(())
     }
+
+    #[tokio::test]
+    #[serial]
+    async fn remove_stale_certificates() -> Result<()> {
+        CertificateStore::clean().await?;
+        let toml = "
+        [[unit]]
+        uuid = '5f0a1c3e-2b7d-4e8f-9a6b-1c2d3e4f5a61'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['kept.stale.com']
+        [unit.action]
+        proxy = 'http://127.0.0.1:8001'
+        ";
+        ConfigFile::from_toml_str(toml)?.set().await?;
+        let groups = ssl::certificate_groups().await?;
+        ssl::set_certificate_groups(&groups).await?;
+
+        for dns in ["kept.stale.com", "gone.stale.com"] {
+            let bundle = FakeCertificate::get(dns)?;
+            CertificateStore::add(dns, &bundle).await?;
+            CertificateStore::save(dns, &bundle).await?;
+        }
+        // Uploaded by hand
+        let manual = "manual.stale.com";
+        CertificateStore::add(manual, &FakeCertificate::get(manual)?).await?;
+
+        CertificateStore::remove_stale(&groups).await?;
+        let store = CertificateStore::get_all().await?;
+        assert!(store.contains_key("kept.stale.com"));
+        assert!(store.contains_key(manual));
+        assert!(!store.contains_key("gone.stale.com"));
+        assert!(!CertificateStore::issued()
+            .await?
+            .contains(&"gone.stale.com".to_owned()));
+        Ok(())
+    }
 }
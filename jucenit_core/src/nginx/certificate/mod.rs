@@ -1,8 +1,10 @@
 mod crud;
 mod mappings;
 mod methods;
+mod state;
 
 // Reexports
 pub use crud::*;
 pub use mappings::*;
 pub use methods::*;
+pub use state::*;
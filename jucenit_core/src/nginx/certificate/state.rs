@@ -0,0 +1,289 @@
+//!
+//! Keep a copy of issued certificates out of nginx-unit,
+//! to restore them after a certificate store wipe
+//! or move them to another server instead of ordering new ones.
+//!
+//! Bundles (chain and private key) are stored as pem files
+//! readable by their owner only.
+//!
+
+use openssl::asn1::Asn1Time;
+use openssl::x509::X509;
+use std::collections::HashMap;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+// Database
+use crate::database::connect_db;
+use crate::database::entity::{prelude::*, *};
+use sea_orm::prelude::*;
+// Error Handling
+use crate::error::JucenitError;
+use miette::{IntoDiagnostic, Result};
+// Crate structs
+use super::CertificateStore;
+use crate::ConfigFile;
+
+pub static CERTIFICATE_DIR: &str = "/var/spool/jucenit/certificates";
+
+/**
+* Whether the leaf certificate of a bundle has not expired.
+*/
+fn is_valid(bundle: &str) -> bool {
+    let now = match Asn1Time::days_from_now(0) {
+        Ok(x) => x,
+        Err(_) => return false,
+    };
+    X509::from_pem(bundle.as_bytes())
+        .is_ok_and(|x| x.not_after().compare(&now).is_ok_and(|x| x.is_gt()))
+}
+
+/**
+* Whether a certificate name is a host or group name
+* that can't escape the certificate directory.
+*/
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains("..")
+        && name
+            .chars()
+            .all(|x| x.is_ascii_alphanumeric() || matches!(x, '.' | '-' | '_' | '*'))
+}
+
+/**
+* Write a file readable by its owner only,
+* never exposing its content to other users.
+*/
+async fn write_private(path: &Path, content: &str) -> Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .await
+        .into_diagnostic()?;
+    // An existing file keeps its permissions.
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))
+        .await
+        .into_diagnostic()?;
+    file.write_all(content.as_bytes()).await.into_diagnostic()?;
+    file.flush().await.into_diagnostic()?;
+    Ok(())
+}
+
+impl CertificateStore {
+    fn state_path(name: &str) -> Result<PathBuf> {
+        if !is_valid_name(name) {
+            let message = format!("Invalid certificate name {:?}", name);
+            return Err(JucenitError::Config(message).into());
+        }
+        Ok(Path::new(CERTIFICATE_DIR).join(format!("{}.pem", name)))
+    }
+    /**
+     * Keep a copy of an issued certificate bundle.
+     */
+    pub async fn save(name: &str, bundle: &str) -> Result<()> {
+        let path = Self::state_path(name)?;
+        fs::create_dir_all(CERTIFICATE_DIR)
+            .await
+            .into_diagnostic()?;
+        fs::set_permissions(CERTIFICATE_DIR, std::fs::Permissions::from_mode(0o700))
+            .await
+            .into_diagnostic()?;
+        write_private(&path, bundle).await
+    }
+    /**
+     * Returns the kept certificate bundles by name.
+     */
+    pub async fn saved() -> Result<HashMap<String, String>> {
+        let mut res = HashMap::new();
+        let mut dir = match fs::read_dir(CERTIFICATE_DIR).await {
+            Ok(x) => x,
+            Err(_) => return Ok(res),
+        };
+        while let Some(entry) = dir.next_entry().await.into_diagnostic()? {
+            let path = entry.path();
+            let name = match path.file_name().and_then(|x| x.to_str()) {
+                Some(x) if x.ends_with(".pem") => x.trim_end_matches(".pem").to_owned(),
+                _ => continue,
+            };
+            if !is_valid_name(&name) {
+                continue;
+            }
+            let bundle = fs::read_to_string(&path).await.into_diagnostic()?;
+            res.insert(name, bundle);
+        }
+        Ok(res)
+    }
+    /**
+     * Returns the names of the certificates issued by jucenit,
+     * as opposed to the ones uploaded by hand.
+     */
+    pub async fn issued() -> Result<Vec<String>> {
+        let db = connect_db().await?;
+        let mut res: Vec<String> = Certificate::find()
+            .all(&db)
+            .await
+            .map_err(JucenitError::from)?
+            .into_iter()
+            .map(|x| x.host)
+            .collect();
+        res.extend(Self::saved().await?.into_keys());
+        res.sort();
+        res.dedup();
+        Ok(res)
+    }
+    /**
+     * Forget an issued certificate: its kept copy and renewal information.
+     */
+    pub async fn forget(name: &str) -> Result<()> {
+        let db = connect_db().await?;
+        Certificate::delete_many()
+            .filter(certificate::Column::Host.eq(name))
+            .exec(&db)
+            .await
+            .map_err(JucenitError::from)?;
+        if let Err(e) = fs::remove_file(Self::state_path(name)?).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(e).into_diagnostic();
+            }
+        }
+        Ok(())
+    }
+    /**
+     * Upload the kept certificates missing from (or expired in)
+     * the nginx-unit certificate store.
+     * Returns the restored certificate names.
+     */
+    pub async fn restore() -> Result<Vec<String>> {
+        let live = Self::get_all().await?;
+        let valid = Self::get_all_valid().await?;
+        let mut res = vec![];
+        for (name, bundle) in Self::saved().await? {
+            if valid.contains_key(&name) || !is_valid(&bundle) {
+                continue;
+            }
+            if live.contains_key(&name) {
+                let _ = Self::remove(&name).await;
+            }
+            Self::add(&name, &bundle).await?;
+            res.push(name);
+        }
+        res.sort();
+        Ok(res)
+    }
+    /**
+     * Write the kept certificates to a json file (name to bundle).
+     */
+    pub async fn export(path: &str) -> Result<()> {
+        let saved = Self::saved().await?;
+        let json = serde_json::to_string_pretty(&saved).into_diagnostic()?;
+        write_private(Path::new(path), &json).await
+    }
+    /**
+     * Keep the valid certificates of an exported file,
+     * upload them and update listeners.
+     * Returns the imported certificate names.
+     */
+    pub async fn import(path: &str) -> Result<Vec<String>> {
+        let json = fs::read_to_string(path).await.map_err(|e| {
+            let message = format!("Couldn't read certificates at {:?}: {}", path, e);
+            JucenitError::Config(message)
+        })?;
+        let bundles: HashMap<String, String> = serde_json::from_str(&json).into_diagnostic()?;
+        if let Some(name) = bundles.keys().find(|x| !is_valid_name(x)) {
+            let message = format!("Invalid certificate name {:?} in {:?}", name, path);
+            return Err(JucenitError::Config(message).into());
+        }
+        let mut res = vec![];
+        for (name, bundle) in bundles {
+            if is_valid(&bundle) {
+                Self::save(&name, &bundle).await?;
+                res.push(name);
+            }
+        }
+        res.sort();
+        Self::restore().await?;
+        // Update listeners tls option with the certificates
+        let config = ConfigFile::pull().await?;
+        config.push().await?;
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssl::Fake as FakeCertificate;
+    use serial_test::serial;
+
+    #[tokio::test]
+    #[serial]
+    async fn restore_saved_certificates() -> Result<()> {
+        CertificateStore::clean().await?;
+        let dns = "saved.example.com";
+        let bundle = FakeCertificate::get(dns)?;
+        CertificateStore::save(dns, &bundle).await?;
+
+        let mode = std::fs::metadata(CertificateStore::state_path(dns)?)
+            .into_diagnostic()?
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        assert!(CertificateStore::restore().await?.contains(&dns.to_owned()));
+        assert!(CertificateStore::get(dns).await.is_ok());
+        // Already in the store
+        assert!(!CertificateStore::restore().await?.contains(&dns.to_owned()));
+        Ok(())
+    }
+    #[tokio::test]
+    #[serial]
+    async fn export_and_import() -> Result<()> {
+        let dns = "moved.example.com";
+        let bundle = FakeCertificate::get(dns)?;
+        CertificateStore::save(dns, &bundle).await?;
+
+        let path = std::env::temp_dir().join("jucenit_certificates.json");
+        let path = path.to_str().unwrap();
+        CertificateStore::export(path).await?;
+        fs::remove_file(CertificateStore::state_path(dns)?)
+            .await
+            .into_diagnostic()?;
+
+        CertificateStore::clean().await?;
+        let res = CertificateStore::import(path).await?;
+        assert!(res.contains(&dns.to_owned()));
+        assert_eq!(CertificateStore::saved().await?[dns], bundle);
+        assert!(CertificateStore::get(dns).await.is_ok());
+
+        let mode = std::fs::metadata(path)
+            .into_diagnostic()?
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        Ok(())
+    }
+    #[tokio::test]
+    #[serial]
+    async fn reject_traversal_names() -> Result<()> {
+        for name in ["../../etc/cron.d/x", "a/b", "..", "", "a\0b"] {
+            assert!(CertificateStore::state_path(name).is_err());
+        }
+        assert!(CertificateStore::state_path("*.example.com").is_ok());
+
+        let path = std::env::temp_dir().join("jucenit_traversal.json");
+        let json = serde_json::json!({ "../../tmp/evil": "bundle" }).to_string();
+        fs::write(&path, json).await.into_diagnostic()?;
+        let err = CertificateStore::import(path.to_str().unwrap())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<JucenitError>(),
+            Some(JucenitError::Config(x)) if x.contains("Invalid certificate name")
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use super::UnitClient;
+use async_trait::async_trait;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde_json::Value;
+// Global vars
+use crate::nginx::SETTINGS;
+// Error Handling
+use crate::error::JucenitError;
+use miette::{IntoDiagnostic, Result};
+
+// Keep unreserved characters as is in path segments
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/**
+* Query nginx-unit at the url from the global settings.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct HttpClient;
+
+impl HttpClient {
+    pub fn encode(path: &[String]) -> String {
+        path.iter()
+            .map(|x| "/".to_owned() + &utf8_percent_encode(x, SEGMENT).to_string())
+            .collect()
+    }
+    async fn url(&self, path: &str) -> String {
+        let settings = SETTINGS.lock().await.clone();
+        settings.get_url() + path
+    }
+    async fn get(&self, path: &str) -> Result<Value> {
+        let res = reqwest::get(self.url(path).await)
+            .await
+            .map_err(unit_api)?
+            .json::<Value>()
+            .await
+            .map_err(unit_api)?;
+        Ok(res)
+    }
+    async fn send(&self, req: reqwest::RequestBuilder) -> Result<Value> {
+        let res = req
+            .send()
+            .await
+            .map_err(unit_api)?
+            .json::<Value>()
+            .await
+            .map_err(unit_api)?;
+        Ok(res)
+    }
+}
+
+/**
+* Unreachable or unreadable control api.
+*/
+fn unit_api(e: reqwest::Error) -> JucenitError {
+    JucenitError::UnitApi(e.to_string())
+}
+
+#[async_trait]
+impl UnitClient for HttpClient {
+    async fn get_config(&self) -> Result<Value> {
+        self.get("/config").await
+    }
+    async fn put_config(&self, path: &[String], value: &Value) -> Result<Value> {
+        let url = self
+            .url(&("/config".to_owned() + &HttpClient::encode(path)))
+            .await;
+        let body = serde_json::to_string(value).into_diagnostic()?;
+        self.send(reqwest::Client::new().put(url).body(body)).await
+    }
+    async fn delete_config(&self, path: &[String]) -> Result<Value> {
+        let url = self
+            .url(&("/config".to_owned() + &HttpClient::encode(path)))
+            .await;
+        self.send(reqwest::Client::new().delete(url)).await
+    }
+    async fn get_certificates(&self) -> Result<Value> {
+        self.get("/certificates").await
+    }
+    async fn put_certificate(&self, name: &str, bundle: &str) -> Result<Value> {
+        let url = self.url(&format!("/certificates/{}", name)).await;
+        self.send(reqwest::Client::new().put(url).body(bundle.to_owned()))
+            .await
+    }
+    async fn delete_certificate(&self, name: &str) -> Result<Value> {
+        let url = self.url(&format!("/certificates/{}", name)).await;
+        self.send(reqwest::Client::new().delete(url)).await
+    }
+    async fn get_status(&self) -> Result<Value> {
+        self.get("/status").await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_path() {
+        let path = vec!["routes".to_owned(), "jucenit_[*:80]".to_owned()];
+        assert_eq!(HttpClient::encode(&path), "/routes/jucenit_%5B%2A%3A80%5D");
+    }
+}
@@ -0,0 +1,401 @@
+use super::UnitClient;
+use async_trait::async_trait;
+use openssl::{nid::Nid, pkey::PKey, x509::X509};
+use serde_json::{json, Map, Value};
+use std::sync::Mutex;
+// Error Handling
+use miette::Result;
+
+/**
+* An in-process nginx-unit stand-in.
+* Configurations are checked for their shape and references
+* (listeners and actions passing to existing objects, certificates in the store)
+* and certificate bundles are parsed, like unitd does.
+*/
+#[derive(Debug)]
+pub struct MemoryClient {
+    state: Mutex<State>,
+}
+#[derive(Debug, Clone)]
+struct State {
+    config: Value,
+    certificates: Map<String, Value>,
+}
+impl Default for MemoryClient {
+    fn default() -> Self {
+        MemoryClient {
+            state: Mutex::new(State {
+                config: empty_config(),
+                certificates: Map::new(),
+            }),
+        }
+    }
+}
+
+fn empty_config() -> Value {
+    json!({ "listeners": {}, "routes": {}, "applications": {} })
+}
+fn success(message: &str) -> Value {
+    json!({ "success": message })
+}
+fn error(message: &str, detail: Option<String>) -> Value {
+    match detail {
+        Some(detail) => json!({ "error": message, "detail": detail }),
+        None => json!({ "error": message }),
+    }
+}
+
+/**
+* Returns the parent of the value at path, and the value key.
+*/
+fn parent<'a>(root: &'a mut Value, path: &'a [String]) -> Option<(&'a mut Value, &'a String)> {
+    let (last, path) = path.split_last()?;
+    let mut node = root;
+    for segment in path {
+        node = match node {
+            Value::Object(x) => x.get_mut(segment)?,
+            Value::Array(x) => x.get_mut(segment.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some((node, last))
+}
+
+impl MemoryClient {
+    /**
+     * Check a whole configuration the way unitd would before applying it.
+     */
+    fn validate(config: &Value, certificates: &Map<String, Value>) -> Result<(), String> {
+        let config = config
+            .as_object()
+            .ok_or("The configuration must be an object")?;
+        for key in config.keys() {
+            if ![
+                "listeners",
+                "routes",
+                "applications",
+                "upstreams",
+                "settings",
+                "access_log",
+            ]
+            .contains(&key.as_str())
+            {
+                return Err(format!("Unknown parameter {:?}", key));
+            }
+        }
+        let objects = |key: &str| -> Result<Map<String, Value>, String> {
+            match config.get(key) {
+                None => Ok(Map::new()),
+                Some(Value::Object(x)) => Ok(x.clone()),
+                Some(_) => Err(format!("The {:?} value must be an object", key)),
+            }
+        };
+        let listeners = objects("listeners")?;
+        let applications = objects("applications")?;
+        let upstreams = objects("upstreams")?;
+
+        // Routes are either named or a single array
+        let mut routes: Map<String, Value> = Map::new();
+        let mut anonymous = false;
+        match config.get("routes") {
+            None => {}
+            Some(Value::Object(x)) => routes = x.clone(),
+            Some(Value::Array(x)) => {
+                anonymous = true;
+                routes.insert(String::new(), Value::Array(x.clone()));
+            }
+            Some(_) => return Err("The \"routes\" value must be an object or an array".to_owned()),
+        };
+
+        let resolve = |pass: &str| -> Result<(), String> {
+            // Variables are resolved per request
+            if pass.contains('$') {
+                return Ok(());
+            }
+            let segments: Vec<&str> = pass.split('/').collect();
+            let found = match segments.as_slice() {
+                ["routes"] => anonymous,
+                ["routes", name] => !anonymous && routes.contains_key(*name),
+                ["applications", name] | ["applications", name, _] => {
+                    applications.contains_key(*name)
+                }
+                ["upstreams", name] => upstreams.contains_key(*name),
+                _ => false,
+            };
+            match found {
+                true => Ok(()),
+                false => Err(format!(
+                    "Request \"pass\" points to invalid location {:?}",
+                    pass
+                )),
+            }
+        };
+
+        for (socket, listener) in &listeners {
+            let pass = listener
+                .get("pass")
+                .and_then(|x| x.as_str())
+                .ok_or(format!("The listener {:?} has no \"pass\" string", socket))?;
+            resolve(pass)?;
+            if let Some(tls) = listener.get("tls") {
+                let names: Vec<&Value> = match tls.get("certificate") {
+                    Some(x @ Value::String(_)) => vec![x],
+                    Some(Value::Array(x)) => x.iter().collect(),
+                    _ => return Err(format!("The listener {:?} has no certificate", socket)),
+                };
+                for name in names {
+                    let name = name.as_str().unwrap_or_default();
+                    if !certificates.contains_key(name) {
+                        return Err(format!("Certificate {:?} is not found", name));
+                    }
+                }
+            }
+        }
+        for (name, steps) in &routes {
+            let steps = steps
+                .as_array()
+                .ok_or(format!("The route {:?} must be an array", name))?;
+            for step in steps {
+                let action = step
+                    .get("action")
+                    .and_then(|x| x.as_object())
+                    .ok_or(format!("A step of route {:?} has no action", name))?;
+                if let Some(pass) = action.get("pass") {
+                    resolve(pass.as_str().unwrap_or_default())?;
+                }
+            }
+        }
+        for (name, app) in &applications {
+            if app.get("type").and_then(|x| x.as_str()).is_none() {
+                return Err(format!("The application {:?} has no type", name));
+            }
+        }
+        for (name, upstream) in &upstreams {
+            if upstream
+                .get("servers")
+                .and_then(|x| x.as_object())
+                .is_none()
+            {
+                return Err(format!("The upstream {:?} has no servers", name));
+            }
+        }
+        Ok(())
+    }
+    /**
+     * Returns the certificate informations as listed by unitd.
+     */
+    fn inspect(bundle: &str) -> Result<Value, String> {
+        let chain = X509::stack_from_pem(bundle.as_bytes()).map_err(|e| e.to_string())?;
+        if chain.is_empty() {
+            return Err("No certificate in the bundle".to_owned());
+        }
+        let key = PKey::private_key_from_pem(bundle.as_bytes()).map_err(|e| e.to_string())?;
+        let key = match key.id() {
+            openssl::pkey::Id::RSA => format!("RSA ({} bits)", key.bits()),
+            openssl::pkey::Id::EC => format!("ECDSA ({} bits)", key.bits()),
+            _ => format!("{} bits", key.bits()),
+        };
+        let common_name = |name: &openssl::x509::X509NameRef| -> String {
+            name.entries_by_nid(Nid::COMMONNAME)
+                .next()
+                .and_then(|x| x.data().as_utf8().ok())
+                .map(|x| x.to_string())
+                .unwrap_or_default()
+        };
+        let chain: Vec<Value> = chain
+            .iter()
+            .map(|cert| {
+                let alt_names: Vec<String> = cert
+                    .subject_alt_names()
+                    .map(|x| {
+                        x.iter()
+                            .filter_map(|x| x.dnsname().map(|x| x.to_owned()))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                json!({
+                  "subject": {
+                    "common_name": common_name(cert.subject_name()),
+                    "alt_names": alt_names
+                  },
+                  "issuer": { "common_name": common_name(cert.issuer_name()) },
+                  "validity": {
+                    "since": cert.not_before().to_string(),
+                    "until": cert.not_after().to_string()
+                  }
+                })
+            })
+            .collect();
+        Ok(json!({ "key": key, "chain": chain }))
+    }
+    /**
+     * Apply a change to a copy of the configuration
+     * and keep it if it is valid.
+     */
+    fn update(&self, change: impl FnOnce(&mut Value) -> Option<()>) -> Value {
+        let mut state = self.state.lock().unwrap();
+        let mut config = state.config.clone();
+        if change(&mut config).is_none() {
+            return error("Value doesn't exist.", None);
+        }
+        match MemoryClient::validate(&config, &state.certificates) {
+            Ok(_) => {
+                state.config = config;
+                success("Reconfiguration done.")
+            }
+            Err(e) => error("Invalid configuration.", Some(e)),
+        }
+    }
+}
+
+#[async_trait]
+impl UnitClient for MemoryClient {
+    async fn get_config(&self) -> Result<Value> {
+        Ok(self.state.lock().unwrap().config.clone())
+    }
+    async fn put_config(&self, path: &[String], value: &Value) -> Result<Value> {
+        Ok(self.update(|config| {
+            if path.is_empty() {
+                *config = value.clone();
+                return Some(());
+            }
+            match parent(config, path)? {
+                (Value::Object(x), key) => {
+                    x.insert(key.to_owned(), value.clone());
+                }
+                (Value::Array(x), index) => {
+                    *x.get_mut(index.parse::<usize>().ok()?)? = value.clone();
+                }
+                _ => return None,
+            };
+            Some(())
+        }))
+    }
+    async fn delete_config(&self, path: &[String]) -> Result<Value> {
+        Ok(self.update(|config| {
+            if path.is_empty() {
+                *config = empty_config();
+                return Some(());
+            }
+            match parent(config, path)? {
+                (Value::Object(x), key) => {
+                    x.remove(key)?;
+                }
+                (Value::Array(x), index) => {
+                    let index = index.parse::<usize>().ok()?;
+                    if index >= x.len() {
+                        return None;
+                    }
+                    x.remove(index);
+                }
+                _ => return None,
+            };
+            Some(())
+        }))
+    }
+    async fn get_certificates(&self) -> Result<Value> {
+        let state = self.state.lock().unwrap();
+        Ok(Value::Object(state.certificates.clone()))
+    }
+    async fn put_certificate(&self, name: &str, bundle: &str) -> Result<Value> {
+        let mut state = self.state.lock().unwrap();
+        if state.certificates.contains_key(name) {
+            return Ok(error("Certificate already exists.", None));
+        }
+        match MemoryClient::inspect(bundle) {
+            Ok(info) => {
+                state.certificates.insert(name.to_owned(), info);
+                Ok(success("Certificate chain uploaded."))
+            }
+            Err(e) => Ok(error("Invalid certificate.", Some(e))),
+        }
+    }
+    async fn delete_certificate(&self, name: &str) -> Result<Value> {
+        let mut state = self.state.lock().unwrap();
+        if !state.certificates.contains_key(name) {
+            return Ok(error("Certificate doesn't exist.", None));
+        }
+        let mut remaining = state.certificates.clone();
+        remaining.remove(name);
+        if MemoryClient::validate(&state.config, &remaining).is_err() {
+            return Ok(error("Certificate is used in the configuration.", None));
+        }
+        state.certificates = remaining;
+        Ok(success("Certificate deleted."))
+    }
+    async fn get_status(&self) -> Result<Value> {
+        let state = self.state.lock().unwrap();
+        let mut applications = Map::new();
+        if let Some(Value::Object(x)) = state.config.get("applications") {
+            for name in x.keys() {
+                applications.insert(
+                    name.to_owned(),
+                    json!({
+                      "processes": { "running": 0, "starting": 0, "idle": 0 },
+                      "requests": { "active": 0 }
+                    }),
+                );
+            }
+        }
+        Ok(json!({
+          "connections": { "accepted": 0, "active": 0, "idle": 0, "closed": 0 },
+          "requests": { "total": 0 },
+          "applications": applications
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssl::Fake as FakeCertificate;
+
+    #[tokio::test]
+    async fn reject_invalid_configuration() -> Result<()> {
+        let client = MemoryClient::default();
+        let listener = json!({ "pass": "routes/jucenit_[*:80]" });
+        let path = vec!["listeners".to_owned(), "*:80".to_owned()];
+
+        // Missing route
+        let res = client.put_config(&path, &listener).await?;
+        assert!(res.get("error").is_some());
+
+        let route = json!([{ "action": { "proxy": "http://127.0.0.1:8888" } }]);
+        let route_path = vec!["routes".to_owned(), "jucenit_[*:80]".to_owned()];
+        let res = client.put_config(&route_path, &route).await?;
+        assert!(res.get("success").is_some());
+        let res = client.put_config(&path, &listener).await?;
+        assert!(res.get("success").is_some());
+
+        // Route in use
+        let res = client.delete_config(&route_path).await?;
+        assert!(res.get("error").is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn store_certificates() -> Result<()> {
+        let client = MemoryClient::default();
+        let res = client.put_certificate("example.com", "garbage").await?;
+        assert!(res.get("error").is_some());
+
+        let bundle = FakeCertificate::get("example.com")?;
+        let res = client.put_certificate("example.com", &bundle).await?;
+        assert!(res.get("success").is_some());
+        let certificates = client.get_certificates().await?;
+        assert_eq!(
+            certificates["example.com"]["chain"][0]["subject"]["alt_names"],
+            json!(["example.com"])
+        );
+
+        // Certificate in use
+        let config = json!({
+          "listeners": { "*:443": { "pass": "routes", "tls": { "certificate": "example.com" } } },
+          "routes": []
+        });
+        client.put_config(&[], &config).await?;
+        let res = client.delete_certificate("example.com").await?;
+        assert!(res.get("error").is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+//!
+//! Transport to the nginx-unit control api.
+//!
+//! The http client is used by default,
+//! the in-memory one by tests and library users without a running unitd.
+//!
+
+mod http;
+mod memory;
+
+pub use http::HttpClient;
+pub use memory::MemoryClient;
+
+use async_trait::async_trait;
+use serde_json::Value;
+// Global vars
+use once_cell::sync::Lazy;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+// Error Handling
+use miette::Result;
+
+/**
+* Nginx-unit control api operations used by jucenit.
+* Config paths are segments under "/config" (ex: ["routes", "jucenit_[*:80]", "0"]),
+* write operations return the unit json response ({"success": ..} or {"error": ..}).
+*/
+// The async_trait expansion trips double_must_use
+#[allow(clippy::double_must_use)]
+#[async_trait]
+pub trait UnitClient: Send + Sync {
+    async fn get_config(&self) -> Result<Value>;
+    async fn put_config(&self, path: &[String], value: &Value) -> Result<Value>;
+    async fn delete_config(&self, path: &[String]) -> Result<Value>;
+    // Certificate name to its key and chain informations
+    async fn get_certificates(&self) -> Result<Value>;
+    async fn put_certificate(&self, name: &str, bundle: &str) -> Result<Value>;
+    async fn delete_certificate(&self, name: &str) -> Result<Value>;
+    async fn get_status(&self) -> Result<Value>;
+}
+
+pub static CLIENT: Lazy<Arc<Mutex<Arc<dyn UnitClient>>>> = Lazy::new(|| {
+    // Tests don't need a running unitd.
+    #[cfg(test)]
+    let client: Arc<dyn UnitClient> = Arc::new(MemoryClient::default());
+    #[cfg(not(test))]
+    let client: Arc<dyn UnitClient> = Arc::new(HttpClient);
+    Arc::new(Mutex::new(client))
+});
+
+/**
+* Returns the client in use.
+*/
+pub async fn client() -> Arc<dyn UnitClient> {
+    CLIENT.lock().await.clone()
+}
+/**
+* Replace the client in use (ex: with a custom transport).
+*/
+pub async fn set_client(client: Arc<dyn UnitClient>) {
+    *CLIENT.lock().await = client;
+}
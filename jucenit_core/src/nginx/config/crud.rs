@@ -3,9 +3,11 @@ use serde::{Deserialize, Serialize};
 use std::env::temp_dir;
 use tokio::task::spawn_local;
 // Global vars
-use crate::nginx::SETTINGS;
+use crate::metrics::METRICS;
+use crate::nginx::client::client;
 // Error Handling
-use miette::{Error, IntoDiagnostic, Result, WrapErr};
+use crate::error::JucenitError;
+use miette::{IntoDiagnostic, Result, WrapErr};
 // exec
 use crate::nginx::certificate::CertificateStore;
 use crate::ssl::Fake as FakeCertificate;
@@ -17,20 +19,43 @@ use futures::executor::block_on;
 // Config file
 use crate::cast::Config as ConfigFile;
 
+use super::patch::operations;
 use http::uri::Uri;
 use std::env;
+// Global vars
+use once_cell::sync::Lazy;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/**
+* Held while the in place configuration is written.
+*/
+pub static CONFIG_LOCK: Lazy<Arc<Mutex<()>>> = Lazy::new(|| Arc::new(Mutex::new(())));
+/**
+* Listener sockets temporarily served by jucenit itself (tls-alpn-01 challenges),
+* left out of the rendered configuration until they are handed back.
+*/
+pub static HANDED_OVER: Lazy<Arc<Mutex<Vec<String>>>> = Lazy::new(|| Arc::new(Mutex::new(vec![])));
 
 // Common structs to file config and unit config
+// Unknown fields are captured by the flattened raw_params.
 #[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
-#[serde(deny_unknown_fields)]
 pub struct Action {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(flatten)]
     pub raw_params: Option<serde_json::Value>,
 }
+impl Action {
+    /**
+     * Return the upstream name if the action passes requests to one.
+     */
+    pub fn upstream(&self) -> Option<String> {
+        let pass = self.raw_params.as_ref()?.get("pass")?.as_str()?;
+        Some(pass.strip_prefix("upstreams/")?.to_owned())
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
-#[serde(deny_unknown_fields)]
 pub struct Match {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub host: Option<String>,
@@ -46,12 +71,171 @@ pub struct ListenerOpts {
     pub pass: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tls: Option<Tls>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forwarded: Option<Forwarded>,
+}
+
+/**
+* Trusted proxies.
+* Replace the client ip and protocol with the ones
+* found in headers of requests coming from trusted sources.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Forwarded {
+    // Header name (ex: "X-Forwarded-For")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ip: Option<String>,
+    // Header name (ex: "X-Forwarded-Proto")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
+    // Trusted addresses and ranges (ex: "10.0.0.0/8")
+    pub source: Source,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recursive: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Source {
+    One(String),
+    Many(Vec<String>),
+}
+impl Default for Source {
+    fn default() -> Self {
+        Source::Many(vec![])
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub struct Tls {
     pub certificate: Vec<String>,
+    // OpenSSL SSL_CONF_cmd() commands (protocols, ciphers...)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conf_commands: Option<IndexMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<Session>,
+}
+
+/**
+* Tls session cache and tickets.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Session {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tickets: Option<Tickets>,
+}
+
+/**
+* Session tickets can be toggled,
+* or set with one or several base64 encoded keys.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Tickets {
+    Enabled(bool),
+    Key(String),
+    Keys(Vec<String>),
+}
+
+/**
+* A group of load balanced servers.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Upstream {
+    // Server ip socket (ex: "10.0.0.1:80") to options
+    pub servers: IndexMap<String, Server>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Server {
+    // Relative share of requests, defaults to 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight: Option<serde_json::Number>,
+}
+
+/**
+* Global server settings.
+* Unlisted nginx-unit options are passed through as is.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct Settings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpSettings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(flatten)]
+    pub raw_params: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct HttpSettings {
+    // Body size limit in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_body_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_buffer_size: Option<u64>,
+    // Timeouts in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_read_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub header_read_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub send_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discard_unsafe_fields: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_route: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_version: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "static")]
+    pub static_: Option<StaticSettings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(flatten)]
+    pub raw_params: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct StaticSettings {
+    // Mime type to file extensions (ex: "text/plain" = [".log", "README"])
+    pub mime_types: IndexMap<String, Vec<String>>,
+}
+
+/**
+* Access log destination and format.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct AccessLog {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<LogFormat>,
+    // Only log requests matching the condition (ex: "`${status >= 400}`")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "if")]
+    pub if_: Option<String>,
+}
+
+/**
+* A custom string format with variables (ex: "$remote_addr $request_line"),
+* or a json format where every field is a string with variables.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum LogFormat {
+    Custom(String),
+    Json(IndexMap<String, String>),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
@@ -65,8 +249,15 @@ pub struct Route {
 pub struct Config {
     pub listeners: IndexMap<String, ListenerOpts>,
     pub routes: IndexMap<String, Vec<Route>>,
+    #[serde(default)]
+    pub applications: IndexMap<String, serde_json::Value>,
+    #[serde(default)]
+    pub upstreams: IndexMap<String, Upstream>,
     pub settings: Option<serde_json::Value>,
     pub access_log: Option<serde_json::Value>,
+    // Route name to the uuid of the unit behind every route step
+    #[serde(skip)]
+    pub steps: IndexMap<String, Vec<String>>,
 }
 impl Default for Config {
     fn default() -> Self {
@@ -87,43 +278,85 @@ impl Default for Config {
         Config {
             routes,
             listeners,
+            applications: IndexMap::new(),
+            upstreams: IndexMap::new(),
             settings,
             access_log,
+            steps: IndexMap::new(),
         }
     }
 }
 
 impl Config {
     /**
-     * Replace the in place configuration.
+     * Replace the in place configuration,
+     * or only its jucenit objects in merge mode.
+     *
+     * Only the changed paths are sent to nginx-unit,
+     * the whole configuration is sent if that fails.
+     * If it is rejected too, the previous configuration is restored.
      */
     pub async fn set(&self) -> Result<Config> {
-        let settings = SETTINGS.lock().await.clone();
-        let client = reqwest::Client::new();
-        let res = client
-            .put(settings.get_url() + "/config")
-            .body(serde_json::to_string(&self).into_diagnostic()?)
-            .send()
-            .await
-            .into_diagnostic()?
-            .json::<serde_json::Value>()
-            .await
-            .into_diagnostic()?;
-
-        // Response conversion from Json to Rust type.
+        let _lock = CONFIG_LOCK.lock().await;
+        let body = self.render().await?;
+        let live = Config::get_value().await?;
+        let patched: Result<usize> = async {
+            let ops = operations(&body, &live)?;
+            for op in &ops {
+                op.send().await?;
+            }
+            Ok(ops.len())
+        }
+        .await;
+        match patched {
+            Ok(0) => {}
+            Ok(count) => println!("nginx-server: {} configuration paths updated", count),
+            Err(_) => {
+                if let Err(e) = self.put(&body).await {
+                    // Undo the paths already updated
+                    let res = client().await.put_config(&[], &live).await;
+                    if let Err(e) = res.and_then(Config::check_response) {
+                        println!("{:?}", e);
+                    }
+                    return Err(e);
+                }
+            }
+        };
+        METRICS.lock().await.last_apply = Some(chrono::Utc::now().timestamp());
+        Ok(self.clone())
+    }
+    /**
+     * Replace the whole in place configuration.
+     */
+    async fn put(&self, body: &serde_json::Value) -> Result<()> {
+        let res = client().await.put_config(&[], body).await?;
+        if let Some(success) = res.get("success") {
+            println!("nginx-server: {}", success);
+        }
+        if res.get("error").is_some() {
+            return Err(self.rejection(&res).await?.into());
+        }
+        Config::check_response(res)
+    }
+    /**
+     * Response conversion from Json to Rust type.
+     */
+    pub fn check_response(res: serde_json::Value) -> Result<()> {
         match res {
             serde_json::Value::Object(res) => {
-                if let Some(success) = res.get("success") {
-                    println!("nginx-server: {}", success);
-                } else if let Some(error) = res.get("error") {
-                    return Err(Error::msg(error.to_string()));
-                } else {
+                if let Some(error) = res.get("error") {
+                    let message = match res.get("detail") {
+                        Some(detail) => format!("{}: {}", error, detail),
+                        None => error.to_string(),
+                    };
+                    return Err(JucenitError::UnitApi(message).into());
+                } else if !res.contains_key("success") {
                     let message = format!(
                         "Unexpected error returned from nginx-server:\n 
                             {:#?}",
                         res
                     );
-                    return Err(Error::msg(message));
+                    return Err(JucenitError::UnitApi(message).into());
                 }
             }
             _ => {
@@ -132,32 +365,33 @@ impl Config {
                     {}",
                     res
                 );
-                return Err(Error::msg(message));
+                return Err(JucenitError::UnitApi(message).into());
             }
         };
-        return Ok(self.clone());
+        Ok(())
     }
 
     /**
      * Get the nginx-unit configuration as a rust struct.
      */
     pub async fn get() -> Result<Config> {
-        let settings = SETTINGS.lock().await.clone();
-        let config = reqwest::get(settings.get_url() + "/config")
-            .await
-            .into_diagnostic()?
-            .json::<Config>()
-            .await
-            .into_diagnostic()?;
-        Ok(config)
+        let config = client().await.get_config().await?;
+        serde_json::from_value(config).into_diagnostic()
+    }
+    /**
+     * Get the nginx-unit configuration as is,
+     * including objects jucenit can't represent.
+     */
+    pub async fn get_value() -> Result<serde_json::Value> {
+        client().await.get_config().await
     }
 }
-
 #[cfg(test)]
 mod tests {
 
     use crate::cast::Config as ConfigFile;
     use crate::nginx::{Config as NginxConfig, Nginx};
+    use serial_test::serial;
     use std::path::PathBuf;
     // Error handling
     use miette::{IntoDiagnostic, Result};
@@ -194,4 +428,45 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    #[serial]
+    async fn restore_rejected_config() -> Result<()> {
+        ConfigFile::from_toml_str(
+            "
+        [[application]]
+        name = 'alpha'
+        type = 'external'
+        executable = '/usr/bin/alpha'
+        processes = 1
+        ",
+        )?
+        .set()
+        .await?;
+
+        // The alpha update goes through, the zulu application (no type) is rejected.
+        ConfigFile::from_toml_str(
+            "
+        [[application]]
+        name = 'alpha'
+        type = 'external'
+        executable = '/usr/bin/alpha'
+        processes = 2
+
+        [[application]]
+        name = 'zulu'
+        processes = 1
+        ",
+        )?
+        .push_to_db()
+        .await?;
+        assert!(NginxConfig::pull().await?.set().await.is_err());
+
+        let live = NginxConfig::get_value().await?;
+        assert_eq!(live["applications"]["alpha"]["processes"], 1);
+        assert!(live["applications"].get("zulu").is_none());
+
+        ConfigFile::default().set().await?;
+        Ok(())
+    }
 }
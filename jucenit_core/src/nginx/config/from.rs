@@ -1,10 +1,13 @@
+use crate::cast::Tls as TlsOpts;
+use crate::ssl;
 use crate::{
-    nginx::config::crud::{Action, ListenerOpts, Match, Tls},
+    nginx::config::crud::{Action, Forwarded, ListenerOpts, Match, Tls},
     CertificateStore, ConfigFile, ConfigUnit,
 };
 // Database / Sea orm
 // use indexmap::IndexMap;
 use crate::database::entity::{prelude::*, *};
+use crate::database::parse_column;
 use indexmap::IndexMap;
 use migration::{Migrator, MigratorTrait};
 use sea_orm::{prelude::*, sea_query::OnConflict, ActiveValue, InsertResult, MockDatabase};
@@ -12,6 +15,7 @@ use sea_orm::{Database, DatabaseConnection};
 // Logging
 use tracing::{debug, Level};
 // Error Handling
+use crate::error::JucenitError;
 use miette::{Error, IntoDiagnostic, Result, WrapErr};
 use uuid::{uuid, Uuid};
 
@@ -20,33 +24,81 @@ use super::Route;
 // impl From<&entity::prelude::Listener> for ListenerOpts {
 impl ListenerOpts {
     pub async fn from(e: &listener::Model) -> Result<(String, ListenerOpts)> {
-        // Bulk add certificates to listeners
-        let certs: Vec<String> = CertificateStore::get_all_valid()
+        // Bulk add certificates to listeners,
+        // by the name of the host or of its group, for every key type.
+        // Certificates uploaded by hand are kept,
+        // the issued ones no longer covering a group are left out.
+        let acme = ssl::Acme::pull().await?;
+        let issued = CertificateStore::issued().await?;
+        let mut names: Vec<String> = vec![];
+        for name in ssl::certificate_names().await? {
+            for (store_name, _) in acme.certificates_for(&name).await? {
+                names.push(store_name);
+            }
+        }
+        let mut certs: Vec<String> = CertificateStore::get_all_valid()
             .await?
             .into_keys()
-            .into_iter()
+            .filter(|x| names.contains(x) || !issued.contains(x))
             .collect();
+        // Stable order to compare configurations
+        certs.sort();
 
         let tls: Option<Tls>;
         if certs.is_empty() || e.ip_socket.ends_with(":80") {
             tls = None
+        } else if let Some(opts) = &e.tls {
+            let opts: TlsOpts = parse_column(&format!("tls of listener {:?}", e.ip_socket), opts)?;
+            tls = Some(Tls::from(&opts, certs))
         } else {
-            tls = Some(Tls { certificate: certs })
+            tls = Some(Tls {
+                certificate: certs,
+                ..Default::default()
+            })
         }
 
+        let forwarded: Option<Forwarded> = e
+            .forwarded
+            .as_ref()
+            .map(|x| parse_column(&format!("forwarded of listener {:?}", e.ip_socket), x))
+            .transpose()?;
+
         let tuples = (
             e.ip_socket.to_owned(),
             ListenerOpts {
                 pass: format!("routes/jucenit_[{}]", e.ip_socket),
                 tls,
+                forwarded,
             },
         );
         Ok(tuples)
     }
 }
 
+impl Tls {
+    /**
+     * Expand the jucenit tls options into nginx-unit ones.
+     * Explicit conf_commands take precedence over the preset ones.
+     */
+    pub fn from(e: &TlsOpts, certificate: Vec<String>) -> Tls {
+        let mut conf_commands = e
+            .preset
+            .as_ref()
+            .map(|x| x.conf_commands())
+            .unwrap_or_default();
+        if let Some(commands) = &e.conf_commands {
+            conf_commands.extend(commands.clone());
+        }
+        Tls {
+            certificate,
+            conf_commands: (!conf_commands.is_empty()).then_some(conf_commands),
+            session: e.session.clone(),
+        }
+    }
+}
+
 impl Match {
-    pub fn from(e: &ng_match::Model, h: Option<host::Model>) -> Match {
+    pub fn from(e: &ng_match::Model, h: Option<host::Model>) -> Result<Match> {
         let mut host: Option<String> = None;
         if let Some(h) = h {
             host = Some(h.domain);
@@ -56,24 +108,32 @@ impl Match {
             raw_params: e
                 .raw_params
                 .clone()
-                .map(|x| serde_json::from_str(&x).unwrap()),
+                .map(|x| serde_json::from_str(&x))
+                .transpose()
+                .map_err(|err| {
+                    JucenitError::Database(format!("Malformed match {:?}: {}", e.uuid, err))
+                })?,
         };
-        match_
+        Ok(match_)
     }
 }
 impl Action {
-    pub fn from(e: &action::Model) -> Action {
+    pub fn from(e: &action::Model) -> Result<Action> {
         let action = Action {
-            raw_params: serde_json::from_str(&e.raw_params).unwrap(),
+            raw_params: serde_json::from_str(&e.raw_params).map_err(|err| {
+                JucenitError::Database(format!("Malformed action {}: {}", e.id, err))
+            })?,
         };
-        action
+        Ok(action)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::cast::Tls as TlsOpts;
     use crate::{
-        nginx::config::crud::{Action, ListenerOpts, Match},
+        nginx::config::crud::{Action, ListenerOpts, Match, Tls},
+        nginx::config::TlsPreset,
         ConfigFile, ConfigUnit,
     };
     use serde_json::json;
@@ -90,12 +150,14 @@ mod tests {
             id: 4,
             ip_socket: "*:8082".to_owned(),
             tls: None,
+            forwarded: None,
         };
         let expect = (
             "*:8082".to_owned(),
             ListenerOpts {
                 pass: "routes/jucenit_[*:8082]".to_owned(),
                 tls: None,
+                forwarded: None,
             },
         );
         let res = ListenerOpts::from(&listener).await?;
@@ -103,10 +165,25 @@ mod tests {
         Ok(())
     }
     #[test]
+    fn convert_tls_options() -> Result<()> {
+        let opts = TlsOpts {
+            preset: Some(TlsPreset::Modern),
+            conf_commands: Some([("groups".to_owned(), "X25519".to_owned())].into()),
+            ..Default::default()
+        };
+        let res = Tls::from(&opts, vec!["example.com".to_owned()]);
+        let commands = res.conf_commands.unwrap();
+        assert_eq!(commands["minprotocol"], "TLSv1.3");
+        assert_eq!(commands["groups"], "X25519");
+        assert_eq!(res.certificate, vec!["example.com".to_owned()]);
+        Ok(())
+    }
+    #[test]
     fn convert_match() -> Result<()> {
         let host = host::ActiveModel {
             id: ActiveValue::Set(9),
             domain: ActiveValue::Set("example.com".to_owned()),
+            certificate_group: ActiveValue::Set(None),
         };
         let action = action::ActiveModel {
             id: ActiveValue::Set(2),
@@ -119,6 +196,8 @@ mod tests {
             raw_params: ActiveValue::Set(None),
             // raw_params: ActiveValue::Set("{}".to_owned()),
             action_id: ActiveValue::Set(2),
+            source: ActiveValue::Set(None),
+            acme: ActiveValue::Set(None),
         };
 
         let expect = Match {
@@ -128,7 +207,7 @@ mod tests {
         let res = Match::from(
             &match_.try_into_model().into_diagnostic()?,
             Some(host.try_into_model().into_diagnostic()?),
-        );
+        )?;
         assert_eq!(expect, res);
         Ok(())
     }
@@ -137,12 +216,11 @@ mod tests {
         let action = action::ActiveModel {
             id: ActiveValue::Set(2),
             raw_params: ActiveValue::Set(json!("{}").to_string()),
-            ..Default::default()
         };
         let expect = Action {
             raw_params: Some(json!("{}")),
         };
-        let res = Action::from(&action.try_into_model().into_diagnostic()?);
+        let res = Action::from(&action.try_into_model().into_diagnostic()?)?;
         assert_eq!(expect, res);
         Ok(())
     }
@@ -0,0 +1,360 @@
+use super::{Config, HANDED_OVER};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+// Database
+use crate::database::entity::{prelude::*, *};
+use crate::database::{connect_db, parse_column};
+use sea_orm::{prelude::*, sea_query::OnConflict, ActiveValue};
+// Error Handling
+use crate::error::JucenitError;
+use miette::{IntoDiagnostic, Result};
+
+/**
+* How jucenit shares the nginx-unit configuration.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigMode {
+    // Jucenit owns the whole configuration
+    #[default]
+    Replace,
+    // Only replace jucenit objects and keep the others
+    Merge,
+}
+impl ConfigMode {
+    pub async fn pull() -> Result<ConfigMode> {
+        let db = connect_db().await?;
+        let option = GlobalOption::find()
+            .filter(global_option::Column::Name.eq("mode"))
+            .one(&db)
+            .await
+            .map_err(JucenitError::from)?;
+        match option {
+            Some(option) => parse_column(&format!("option {:?}", option.name), &option.raw_params),
+            None => Ok(ConfigMode::default()),
+        }
+    }
+}
+
+/**
+* Server wide options declared to jucenit,
+* and every application and upstream it ever declared
+* (to remove them once undeclared).
+* The undeclared ones are left to whoever set them.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ownership {
+    pub settings: bool,
+    pub access_log: bool,
+    pub applications: Vec<String>,
+    pub upstreams: Vec<String>,
+}
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+struct Declared {
+    #[serde(default)]
+    applications: Vec<String>,
+    #[serde(default)]
+    upstreams: Vec<String>,
+}
+impl Ownership {
+    pub async fn pull() -> Result<Ownership> {
+        let db = connect_db().await?;
+        let options = GlobalOption::find()
+            .all(&db)
+            .await
+            .map_err(JucenitError::from)?;
+        let names: Vec<String> = options.iter().map(|x| x.name.clone()).collect();
+        let declared: Declared = match options.iter().find(|x| x.name == "declared") {
+            Some(option) => parse_column(&format!("option {:?}", option.name), &option.raw_params)?,
+            None => Declared::default(),
+        };
+        Ok(Ownership {
+            settings: names.contains(&"settings".to_owned()),
+            access_log: names.contains(&"access_log".to_owned()),
+            applications: declared.applications,
+            upstreams: declared.upstreams,
+        })
+    }
+    /**
+     * Remember declared applications and upstreams.
+     */
+    pub async fn declare<C: ConnectionTrait>(
+        db: &C,
+        applications: &[String],
+        upstreams: &[String],
+    ) -> Result<()> {
+        if applications.is_empty() && upstreams.is_empty() {
+            return Ok(());
+        }
+        let option = GlobalOption::find()
+            .filter(global_option::Column::Name.eq("declared"))
+            .one(db)
+            .await
+            .map_err(JucenitError::from)?;
+        let mut declared: Declared = match option {
+            Some(option) => parse_column(&format!("option {:?}", option.name), &option.raw_params)?,
+            None => Declared::default(),
+        };
+        for (list, names) in [
+            (&mut declared.applications, applications),
+            (&mut declared.upstreams, upstreams),
+        ] {
+            list.extend(names.iter().cloned());
+            list.sort();
+            list.dedup();
+        }
+        let option = global_option::ActiveModel {
+            name: ActiveValue::Set("declared".to_owned()),
+            raw_params: ActiveValue::Set(serde_json::to_string(&declared).into_diagnostic()?),
+            ..Default::default()
+        };
+        GlobalOption::insert(option)
+            .on_conflict(
+                OnConflict::column(global_option::Column::Name)
+                    .update_column(global_option::Column::RawParams)
+                    .to_owned(),
+            )
+            .exec(db)
+            .await
+            .map_err(JucenitError::from)?;
+        Ok(())
+    }
+}
+
+/**
+* A listener socket used by both jucenit and another client.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub listener: String,
+    // The unmanaged listener destination
+    pub pass: Option<String>,
+}
+
+fn is_managed_route(name: &str) -> bool {
+    name.starts_with("jucenit_[")
+}
+fn is_managed_listener(listener: &Value) -> bool {
+    listener
+        .get("pass")
+        .and_then(|x| x.as_str())
+        .and_then(|x| x.strip_prefix("routes/"))
+        .map(is_managed_route)
+        .unwrap_or(false)
+}
+
+impl Config {
+    /**
+     * Returns the configuration to put in place,
+     * according to the configured mode.
+     */
+    pub async fn render(&self) -> Result<Value> {
+        let mut body = match ConfigMode::pull().await? {
+            ConfigMode::Replace => serde_json::to_value(self).into_diagnostic()?,
+            ConfigMode::Merge => {
+                let live = Config::get_value().await?;
+                let ownership = Ownership::pull().await?;
+                let (merged, conflicts) = self.merge(&live, &ownership)?;
+                for conflict in conflicts {
+                    println!(
+                        "nginx-server: listener {:?} is not managed by jucenit (passes to {:?}), left as is",
+                        conflict.listener,
+                        conflict.pass.unwrap_or_default()
+                    );
+                }
+                merged
+            }
+        };
+        // Sockets handed over to a challenge responder
+        if let Some(Value::Object(listeners)) = body.get_mut("listeners") {
+            for socket in HANDED_OVER.lock().await.iter() {
+                listeners.remove(socket);
+            }
+        }
+        Ok(body)
+    }
+    /**
+     * Replace the jucenit owned objects of a live configuration:
+     * - listeners passing to "jucenit_[...]" routes and those routes,
+     * - applications and upstreams declared now or before,
+     * - declared settings and access_log.
+     *
+     * Other objects are kept.
+     * Listener sockets already used by other routes are reported and left untouched.
+     */
+    pub fn merge(&self, live: &Value, ownership: &Ownership) -> Result<(Value, Vec<Conflict>)> {
+        let expected = serde_json::to_value(self).into_diagnostic()?;
+        let mut conflicts: Vec<Conflict> = vec![];
+        let mut merged = live.as_object().cloned().unwrap_or_default();
+
+        // Routes
+        let mut routes = match merged.get("routes") {
+            None => Map::new(),
+            Some(Value::Object(x)) => x.clone(),
+            Some(Value::Array(x)) if x.is_empty() => Map::new(),
+            Some(_) => {
+                let message = "The live routes are an array, name them to merge jucenit routes";
+                return Err(JucenitError::Config(message.to_owned()).into());
+            }
+        };
+        routes.retain(|k, _| !is_managed_route(k));
+        if let Some(Value::Object(x)) = expected.get("routes") {
+            routes.extend(x.clone());
+        }
+        merged.insert("routes".to_owned(), Value::Object(routes));
+
+        // Listeners
+        let mut listeners = match merged.get("listeners") {
+            Some(Value::Object(x)) => x.clone(),
+            _ => Map::new(),
+        };
+        listeners.retain(|_, v| !is_managed_listener(v));
+        if let Some(Value::Object(x)) = expected.get("listeners") {
+            for (socket, listener) in x {
+                match listeners.get(socket) {
+                    Some(unmanaged) => conflicts.push(Conflict {
+                        listener: socket.to_owned(),
+                        pass: unmanaged
+                            .get("pass")
+                            .and_then(|x| x.as_str())
+                            .map(|x| x.to_owned()),
+                    }),
+                    None => {
+                        listeners.insert(socket.to_owned(), listener.clone());
+                    }
+                }
+            }
+        }
+        merged.insert("listeners".to_owned(), Value::Object(listeners));
+
+        // Applications and upstreams
+        for (key, declared) in [
+            ("applications", &ownership.applications),
+            ("upstreams", &ownership.upstreams),
+        ] {
+            let mut objects = match merged.get(key) {
+                Some(Value::Object(x)) => x.clone(),
+                _ => Map::new(),
+            };
+            // Upstreams generated for units
+            objects.retain(|k, _| !is_managed_route(k) && !declared.contains(k));
+            if let Some(Value::Object(x)) = expected.get(key) {
+                objects.extend(x.clone());
+            }
+            merged.insert(key.to_owned(), Value::Object(objects));
+        }
+
+        // Server wide options
+        for (key, owned) in [
+            ("settings", ownership.settings),
+            ("access_log", ownership.access_log),
+        ] {
+            if owned || !merged.contains_key(key) {
+                match expected.get(key) {
+                    Some(Value::Null) | None => {
+                        merged.remove(key);
+                    }
+                    Some(x) => {
+                        merged.insert(key.to_owned(), x.clone());
+                    }
+                }
+            }
+        }
+
+        Ok((Value::Object(merged), conflicts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nginx::config::{Action, ListenerOpts, Route};
+
+    #[test]
+    fn merge_live_config() -> Result<()> {
+        let live = serde_json::json!({
+          "listeners": {
+            "*:8000": { "pass": "applications/wiki" },
+            "*:80": { "pass": "routes/main" },
+            "*:9000": { "pass": "routes/jucenit_[*:9000]" }
+          },
+          "routes": {
+            "main": [{ "action": { "share": "/www$uri" } }],
+            "jucenit_[*:9000]": [{ "action": { "proxy": "http://127.0.0.1:9999" } }]
+          },
+          "applications": {
+            "wiki": { "type": "python", "path": "/www/wiki", "module": "wsgi" }
+          },
+          "settings": { "http": { "idle_timeout": 30 } }
+        });
+
+        let mut config = Config::default();
+        for socket in ["*:443", "*:80"] {
+            config.listeners.insert(
+                socket.to_owned(),
+                ListenerOpts {
+                    pass: format!("routes/jucenit_[{}]", socket),
+                    ..Default::default()
+                },
+            );
+            config.routes.insert(
+                format!("jucenit_[{}]", socket),
+                vec![Route {
+                    action: Some(Action {
+                        raw_params: Some(serde_json::json!({ "proxy": "http://127.0.0.1:8888" })),
+                    }),
+                    ..Default::default()
+                }],
+            );
+        }
+        let (merged, conflicts) = config.merge(&live, &Ownership::default())?;
+
+        // Unmanaged objects are kept
+        assert_eq!(merged["listeners"]["*:8000"]["pass"], "applications/wiki");
+        assert_eq!(merged["routes"]["main"], live["routes"]["main"]);
+        assert_eq!(merged["applications"], live["applications"]);
+        assert_eq!(merged["settings"], live["settings"]);
+        // Stale jucenit objects are removed
+        assert!(merged["listeners"].get("*:9000").is_none());
+        assert!(merged["routes"].get("jucenit_[*:9000]").is_none());
+        // Jucenit objects are added
+        assert_eq!(
+            merged["listeners"]["*:443"]["pass"],
+            "routes/jucenit_[*:443]"
+        );
+        assert!(merged["routes"].get("jucenit_[*:443]").is_some());
+        // Shared sockets are reported
+        assert_eq!(
+            conflicts,
+            vec![Conflict {
+                listener: "*:80".to_owned(),
+                pass: Some("routes/main".to_owned())
+            }]
+        );
+        assert_eq!(merged["listeners"]["*:80"]["pass"], "routes/main");
+        Ok(())
+    }
+    #[test]
+    fn remove_undeclared_objects() -> Result<()> {
+        let live = serde_json::json!({
+          "applications": {
+            "wiki": { "type": "python", "path": "/www/wiki", "module": "wsgi" },
+            "blog": { "type": "php", "root": "/www/blog" }
+          },
+          "upstreams": {
+            "pool": { "servers": { "127.0.0.1:8001": {} } }
+          }
+        });
+        // Jucenit declared "blog" and "pool" before, the file no longer does.
+        let ownership = Ownership {
+            applications: vec!["blog".to_owned()],
+            upstreams: vec!["pool".to_owned()],
+            ..Default::default()
+        };
+        let (merged, _) = Config::default().merge(&live, &ownership)?;
+        assert!(merged["applications"].get("wiki").is_some());
+        assert!(merged["applications"].get("blog").is_none());
+        assert!(merged["upstreams"].get("pool").is_none());
+        Ok(())
+    }
+}
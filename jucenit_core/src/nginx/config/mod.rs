@@ -1,5 +1,12 @@
 mod crud;
 mod from;
+mod merge;
+mod patch;
+mod rejection;
+mod tls;
 
 // Reexports
 pub use crud::*;
+pub use merge::{ConfigMode, Conflict, Ownership};
+pub use patch::{operations, Operation};
+pub use tls::*;
@@ -0,0 +1,190 @@
+use super::Config;
+use serde_json::Value;
+// Unit api
+use crate::nginx::client::client;
+// Error Handling
+use crate::error::JucenitError;
+use miette::Result;
+
+/**
+* A path level change to the nginx-unit configuration.
+* Paths are segments under "/config" (ex: ["routes", "jucenit_[*:80]", "0"]).
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    Put { path: Vec<String>, value: Value },
+    Delete { path: Vec<String> },
+}
+impl Operation {
+    pub fn path(&self) -> &Vec<String> {
+        match self {
+            Operation::Put { path, .. } => path,
+            Operation::Delete { path } => path,
+        }
+    }
+    /**
+     * Send the operation to nginx-unit.
+     */
+    pub async fn send(&self) -> Result<()> {
+        let client = client().await;
+        let res = match self {
+            Operation::Put { path, value } => client.put_config(path, value).await?,
+            Operation::Delete { path } => client.delete_config(path).await?,
+        };
+        Config::check_response(res)?;
+        Ok(())
+    }
+}
+
+/**
+* Returns the operations turning the live configuration into the expected one,
+* in an order nginx-unit validates step by step:
+* - add and change applications and upstreams, then routes,
+* - then listeners,
+* - then remove routes, then applications and upstreams.
+*/
+pub fn operations(expected: &Value, live: &Value) -> Result<Vec<Operation>> {
+    let (expected, live) = match (expected, live) {
+        (Value::Object(expected), Value::Object(live)) => (expected, live),
+        _ => {
+            return Err(
+                JucenitError::Config("Can't compare non object configurations".to_owned()).into(),
+            )
+        }
+    };
+    // What may be pointed to comes first
+    let rank = |key: &String| match key.as_str() {
+        "routes" => 1,
+        "listeners" => 2,
+        _ => 0,
+    };
+    let mut keys: Vec<&String> = expected.keys().collect();
+    keys.extend(live.keys().filter(|x| !expected.contains_key(*x)));
+    keys.sort_by_key(|x| rank(x));
+
+    let mut puts: Vec<Operation> = vec![];
+    let mut deletes: Vec<Operation> = vec![];
+    for key in keys {
+        let mut res = vec![];
+        let path = vec![key.to_owned()];
+        match (expected.get(key), live.get(key)) {
+            (Some(x), Some(y)) => diff(path, x, y, &mut res),
+            (Some(x), None) => res.push(Operation::Put {
+                path,
+                value: x.clone(),
+            }),
+            (None, Some(_)) => res.push(Operation::Delete { path }),
+            (None, None) => {}
+        }
+        for op in res {
+            match op {
+                Operation::Delete { .. } if key != "listeners" => deletes.push(op),
+                _ => puts.push(op),
+            }
+        }
+    }
+    deletes.reverse();
+    Ok(puts.into_iter().chain(deletes).collect())
+}
+
+fn diff(path: Vec<String>, expected: &Value, live: &Value, res: &mut Vec<Operation>) {
+    if expected == live {
+        return;
+    }
+    match (expected, live) {
+        (Value::Object(x), Value::Object(y)) => {
+            for (k, v) in x {
+                let mut path = path.clone();
+                path.push(k.to_owned());
+                match y.get(k) {
+                    Some(w) => diff(path, v, w, res),
+                    None => res.push(Operation::Put {
+                        path,
+                        value: v.clone(),
+                    }),
+                }
+            }
+            for k in y.keys() {
+                if !x.contains_key(k) {
+                    let mut path = path.clone();
+                    path.push(k.to_owned());
+                    res.push(Operation::Delete { path });
+                }
+            }
+        }
+        // Route steps are replaced one by one when their count is unchanged.
+        (Value::Array(x), Value::Array(y)) if x.len() == y.len() => {
+            for (i, (v, w)) in x.iter().zip(y).enumerate() {
+                if v != w {
+                    let mut path = path.clone();
+                    path.push(i.to_string());
+                    res.push(Operation::Put {
+                        path,
+                        value: v.clone(),
+                    });
+                }
+            }
+        }
+        _ => res.push(Operation::Put {
+            path,
+            value: expected.clone(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nginx::client::HttpClient;
+
+    #[test]
+    fn compute_operations() -> Result<()> {
+        let live = serde_json::json!({
+          "listeners": {
+            "*:80": { "pass": "routes/jucenit_[*:80]" },
+            "*:8080": { "pass": "routes/jucenit_[*:8080]" }
+          },
+          "routes": {
+            "jucenit_[*:80]": [
+              { "match": { "host": "a.com" }, "action": { "proxy": "http://127.0.0.1:8001" } },
+              { "match": { "host": "b.com" }, "action": { "proxy": "http://127.0.0.1:8002" } }
+            ],
+            "jucenit_[*:8080]": [{ "action": { "proxy": "http://127.0.0.1:8003" } }]
+          }
+        });
+        let expected = serde_json::json!({
+          "listeners": {
+            "*:80": { "pass": "routes/jucenit_[*:80]" },
+            "*:443": { "pass": "routes/jucenit_[*:443]" }
+          },
+          "routes": {
+            "jucenit_[*:80]": [
+              { "match": { "host": "a.com" }, "action": { "proxy": "http://127.0.0.1:8001" } },
+              { "match": { "host": "b.com" }, "action": { "proxy": "http://127.0.0.1:9002" } }
+            ],
+            "jucenit_[*:443]": [{ "action": { "proxy": "http://127.0.0.1:8004" } }]
+          }
+        });
+        let ops = operations(&expected, &live)?;
+        let ops: Vec<String> = ops
+            .iter()
+            .map(|x| match x {
+                Operation::Put { .. } => format!("PUT /config{}", HttpClient::encode(x.path())),
+                Operation::Delete { .. } => {
+                    format!("DELETE /config{}", HttpClient::encode(x.path()))
+                }
+            })
+            .collect();
+        assert_eq!(
+            ops,
+            vec![
+                "PUT /config/routes/jucenit_%5B%2A%3A443%5D",
+                "PUT /config/routes/jucenit_%5B%2A%3A80%5D/1",
+                "PUT /config/listeners/%2A%3A443",
+                "DELETE /config/listeners/%2A%3A8080",
+                "DELETE /config/routes/jucenit_%5B%2A%3A8080%5D",
+            ]
+        );
+        Ok(())
+    }
+}
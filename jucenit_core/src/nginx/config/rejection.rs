@@ -0,0 +1,148 @@
+use super::Config;
+use crate::error::JucenitError;
+use crate::error::UnitError;
+use miette::{NamedSource, SourceSpan};
+use serde_json::Value;
+// Database
+use crate::database::connect_db;
+use crate::database::entity::{prelude::*, *};
+use sea_orm::prelude::*;
+// Error Handling
+use miette::{IntoDiagnostic, Result};
+
+impl Config {
+    /**
+     * Returns the uuids of the units behind a configuration path
+     * (ex: "routes/jucenit_[*:443]/3/action").
+     */
+    pub fn locate(&self, location: &str) -> Vec<String> {
+        let segments: Vec<&str> = location.trim_matches('/').split('/').collect();
+        let mut uuids: Vec<String> = match segments.as_slice() {
+            ["routes", name, index, ..] => index
+                .parse::<usize>()
+                .ok()
+                .and_then(|x| self.steps.get(*name)?.get(x).cloned())
+                .into_iter()
+                .collect(),
+            ["routes", name] => self.steps.get(*name).cloned().unwrap_or_default(),
+            ["listeners", socket, ..] => self
+                .steps
+                .get(&format!("jucenit_[{}]", socket))
+                .cloned()
+                .unwrap_or_default(),
+            // Upstreams generated from the proxy shorthand
+            ["upstreams", name, ..] => name
+                .strip_prefix("jucenit_[")
+                .and_then(|x| x.strip_suffix(']'))
+                .map(|x| x.to_owned())
+                .into_iter()
+                .collect(),
+            _ => vec![],
+        };
+        uuids.dedup();
+        uuids
+    }
+    /**
+     * Build a report from a unit error response,
+     * pointing to the offending unit and its source file when known.
+     */
+    pub async fn rejection(&self, res: &Value) -> Result<UnitError> {
+        let error = match res.get("error") {
+            Some(Value::String(x)) => x.to_owned(),
+            Some(x) => x.to_string(),
+            None => res.to_string(),
+        };
+        let location = res.get("location").and_then(|x| x.as_str());
+        let uuids = location.map(|x| self.locate(x)).unwrap_or_default();
+
+        let mut help: Vec<String> = vec![];
+        if let Some(detail) = res.get("detail").and_then(|x| x.as_str()) {
+            help.push(detail.to_owned());
+        }
+        if let Some(location) = location {
+            help.push(format!("at /config/{}", location.trim_matches('/')));
+        }
+        if !uuids.is_empty() {
+            help.push(format!("declared by unit {}", uuids.join(", ")));
+        }
+
+        let mut report = UnitError {
+            error,
+            help: (!help.is_empty()).then(|| help.join("\n")),
+            uuid: None,
+            at: None,
+            src: None,
+        };
+        // Only point to a file when a single unit is to blame
+        if let [uuid] = uuids.as_slice() {
+            report.uuid = Some(uuid.to_owned());
+            let db = connect_db().await?;
+            let source = NgMatch::find()
+                .filter(ng_match::Column::Uuid.eq(uuid))
+                .one(&db)
+                .await
+                .map_err(JucenitError::from)?
+                .and_then(|x| x.source);
+            if let Some(path) = source {
+                if let Ok(content) = std::fs::read_to_string(&path) {
+                    if let Some(offset) = content.find(uuid.as_str()) {
+                        report.at = Some(SourceSpan::new(offset.into(), uuid.len()));
+                        report.src = Some(NamedSource::new(path, content));
+                    }
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cast::Config as ConfigFile;
+    use crate::nginx::Config as NginxConfig;
+    use std::io::Write;
+    // Error Handling
+    use miette::{IntoDiagnostic, Result};
+
+    #[tokio::test]
+    async fn point_to_offending_unit() -> Result<()> {
+        let toml = "
+        [[unit]]
+        uuid = '6f0ae8a4-b8a4-4a0e-a2a0-5bb2fa2f4a3e'
+        listeners = ['*:8443']
+        [unit.match]
+        hosts = ['first.example.com']
+        [unit.action]
+        proxy = 'http://127.0.0.1:8001'
+
+        [[unit]]
+        uuid = '2c7d8c4f-94e4-4d3b-9d49-1a7b1b5a9a8c'
+        listeners = ['*:8443']
+        [unit.match]
+        hosts = ['second.example.com']
+        [unit.action]
+        proxy = 'http://127.0.0.1:8002'
+        ";
+        std::fs::create_dir_all("/tmp/jucenit").into_diagnostic()?;
+        let path = "/tmp/jucenit/test_rejection.toml";
+        let mut file = std::fs::File::create(path).into_diagnostic()?;
+        file.write_all(toml.as_bytes()).into_diagnostic()?;
+
+        let config = ConfigFile::load(path)?;
+        config.push_to_db().await?;
+        let nginx = NginxConfig::pull().await?;
+
+        let res = serde_json::json!({
+          "error": "Invalid configuration.",
+          "detail": "The \"proxy\" address is invalid.",
+          "location": "routes/jucenit_[*:8443]/1/action"
+        });
+        let report = nginx.rejection(&res).await?;
+        let uuid = nginx.steps["jucenit_[*:8443]"][1].clone();
+        assert_eq!(report.uuid, Some(uuid.clone()));
+        let at = report.at.unwrap();
+        assert_eq!(&toml[at.offset()..at.offset() + at.len()], uuid);
+        println!("{:?}", miette::Report::new(report));
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+/**
+* Named hardening presets for listeners tls options.
+* They expand into the OpenSSL conf_commands that restrict
+* protocols and ciphers.
+*
+* Follows the Mozilla server side tls recommendations:
+* https://wiki.mozilla.org/Security/Server_Side_TLS
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsPreset {
+    /// TLSv1.3 only, for services with modern clients.
+    Modern,
+    /// TLSv1.2 and TLSv1.3, for general-purpose servers.
+    Intermediate,
+}
+
+static CIPHERSUITES: &str =
+    "TLS_AES_128_GCM_SHA256:TLS_AES_256_GCM_SHA384:TLS_CHACHA20_POLY1305_SHA256";
+static GROUPS: &str = "X25519:prime256v1:secp384r1";
+// TLSv1.2 ciphers
+static INTERMEDIATE_CIPHERS: &str = "ECDHE-ECDSA-AES128-GCM-SHA256:ECDHE-RSA-AES128-GCM-SHA256:\
+ECDHE-ECDSA-AES256-GCM-SHA384:ECDHE-RSA-AES256-GCM-SHA384:\
+ECDHE-ECDSA-CHACHA20-POLY1305:ECDHE-RSA-CHACHA20-POLY1305:\
+DHE-RSA-AES128-GCM-SHA256:DHE-RSA-AES256-GCM-SHA384:DHE-RSA-CHACHA20-POLY1305";
+
+impl TlsPreset {
+    /**
+     * Return the OpenSSL commands the preset expands into.
+     */
+    pub fn conf_commands(&self) -> IndexMap<String, String> {
+        let commands = match self {
+            TlsPreset::Modern => vec![
+                ("minprotocol", "TLSv1.3"),
+                ("ciphersuites", CIPHERSUITES),
+                ("groups", GROUPS),
+            ],
+            TlsPreset::Intermediate => vec![
+                ("minprotocol", "TLSv1.2"),
+                ("cipherstring", INTERMEDIATE_CIPHERS),
+                ("ciphersuites", CIPHERSUITES),
+                ("groups", GROUPS),
+            ],
+        };
+        commands
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TlsPreset;
+
+    #[test]
+    fn expand_presets() {
+        let modern = TlsPreset::Modern.conf_commands();
+        assert_eq!(modern.get("minprotocol"), Some(&"TLSv1.3".to_owned()));
+        assert!(modern.get("cipherstring").is_none());
+
+        let intermediate = TlsPreset::Intermediate.conf_commands();
+        assert_eq!(intermediate.get("minprotocol"), Some(&"TLSv1.2".to_owned()));
+        assert!(intermediate["cipherstring"].starts_with("ECDHE-ECDSA-AES128-GCM-SHA256:"));
+    }
+}
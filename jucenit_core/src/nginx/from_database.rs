@@ -1,10 +1,10 @@
-use crate::nginx::config::{Action, ListenerOpts, Match, Route};
+use crate::nginx::config::{Action, ListenerOpts, Match, Route, Upstream as NginxUpstream};
 // Database
 use crate::{ConfigFile, ConfigUnit, NginxConfig};
 // Sea orm
 // use indexmap::IndexMap;
-use crate::database::connect_db;
 use crate::database::entity::{prelude::*, *};
+use crate::database::{connect_db, parse_column};
 use migration::{MatchHost, MatchListener, Migrator, MigratorTrait};
 use sea_orm::{
     prelude::*, query::*, sea_query::OnConflict, ActiveValue, InsertResult, MockDatabase,
@@ -13,6 +13,7 @@ use sea_orm::{Database, DatabaseConnection};
 // Logging
 use tracing::{debug, Level};
 // Error Handling
+use crate::error::JucenitError;
 use miette::{Error, IntoDiagnostic, Result, WrapErr};
 
 impl NginxConfig {
@@ -24,13 +25,39 @@ impl NginxConfig {
         let db = connect_db().await?;
         let mut nginx_config = NginxConfig::default();
 
+        // Upstreams
+        // Servers failing health checks are left out of rotation.
+        let upstreams: Vec<(upstream::Model, Vec<upstream_server::Model>)> = Upstream::find()
+            .find_with_related(UpstreamServer)
+            .all(&db)
+            .await
+            .map_err(JucenitError::from)?;
+        let mut unavailable: Vec<String> = vec![];
+        for (upstream, servers) in upstreams {
+            let mut nginx_upstream = NginxUpstream::default();
+            for server in servers.iter().filter(|x| !x.down) {
+                let raw_params = parse_column(
+                    &format!("upstream server {:?}", server.address),
+                    &server.raw_params,
+                )?;
+                nginx_upstream
+                    .servers
+                    .insert(server.address.clone(), raw_params);
+            }
+            if nginx_upstream.servers.is_empty() && !servers.is_empty() {
+                unavailable.push(upstream.name);
+                continue;
+            }
+            nginx_config.upstreams.insert(upstream.name, nginx_upstream);
+        }
+
         // Select related listeners and match
         // And add them to config struct
         let listeners: Vec<(listener::Model, Vec<ng_match::Model>)> = Listener::find()
             .find_with_related(NgMatch)
             .all(&db)
             .await
-            .into_diagnostic()?;
+            .map_err(JucenitError::from)?;
         for (listener, matches) in listeners {
             // Append listeners and routes to nginx configuration
             let (ip_socket, listener) = ListenerOpts::from(&listener).await?;
@@ -48,16 +75,24 @@ impl NginxConfig {
                 )
                 .all(&db)
                 .await
-                .into_diagnostic()?;
+                .map_err(JucenitError::from)?;
 
             for (match_, hosts) in &matches {
                 let action = match_
                     .find_related(Action)
                     .one(&db)
                     .await
-                    .into_diagnostic()?;
+                    .map_err(JucenitError::from)?;
                 // Convert to nginx struct
-                let action = action.clone().map(|x| Action::from(&x));
+                let mut action = action.as_ref().map(Action::from).transpose()?;
+                // Answer with a maintenance error when every server is down
+                if let Some(name) = action.as_ref().and_then(|x| x.upstream()) {
+                    if unavailable.contains(&name) {
+                        action = Some(Action {
+                            raw_params: Some(serde_json::json!({ "return": 503 })),
+                        });
+                    }
+                }
                 let route_name = format!("jucenit_[{}]", ip_socket);
                 let route = nginx_config.routes.get_mut(&route_name);
                 let route = route.unwrap();
@@ -65,22 +100,68 @@ impl NginxConfig {
                 if hosts.is_empty() {
                     route.push(Route {
                         action: action.clone(),
-                        match_: Match::from(&match_, None),
+                        match_: Match::from(match_, None)?,
                     });
                 } else {
                     for host in hosts {
                         route.push(Route {
                             action: action.clone(),
-                            match_: Match::from(&match_, Some(host.to_owned())),
+                            match_: Match::from(match_, Some(host.to_owned()))?,
                         });
                     }
                 }
+                let steps = nginx_config.steps.entry(route_name).or_default();
+                steps.extend(vec![match_.uuid.clone(); hosts.len().max(1)]);
+            }
+        }
+
+        // Applications
+        let applications = Application::find()
+            .all(&db)
+            .await
+            .map_err(JucenitError::from)?;
+        for app in applications {
+            let raw_params = parse_column(&format!("application {:?}", app.name), &app.raw_params)?;
+            nginx_config.applications.insert(app.name, raw_params);
+        }
+
+        // Merge declared server wide options into the default ones
+        let options = GlobalOption::find()
+            .all(&db)
+            .await
+            .map_err(JucenitError::from)?;
+        for option in options {
+            let value: serde_json::Value =
+                parse_column(&format!("option {:?}", option.name), &option.raw_params)?;
+            let section = match option.name.as_str() {
+                "settings" => &mut nginx_config.settings,
+                "access_log" => &mut nginx_config.access_log,
+                _ => continue,
+            };
+            match section {
+                Some(section) => merge_json(section, &value),
+                None => *section = Some(value),
             }
         }
         Ok(nginx_config)
     }
 }
 
+/**
+* Recursively merge json objects.
+* Values from the patch take precedence.
+*/
+fn merge_json(base: &mut serde_json::Value, patch: &serde_json::Value) {
+    match (base, patch) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(patch)) => {
+            for (k, v) in patch {
+                merge_json(base.entry(k).or_insert(serde_json::Value::Null), v);
+            }
+        }
+        (base, patch) => *base = patch.clone(),
+    }
+}
+
 #[cfg(test)]
 mod test {
 
@@ -104,6 +185,69 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn merge_settings() -> Result<()> {
+        let mut settings = serde_json::json!({
+          "http": {
+            "log_route": true
+          }
+        });
+        let patch = serde_json::json!({
+          "http": {
+            "max_body_size": 1024,
+            "static": { "mime_types": { "text/plain": [".log"] } }
+          }
+        });
+        merge_json(&mut settings, &patch);
+        assert_eq!(settings["http"]["log_route"], true);
+        assert_eq!(settings["http"]["max_body_size"], 1024);
+        assert_eq!(
+            settings["http"]["static"]["mime_types"]["text/plain"][0],
+            ".log"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn convert_unavailable_upstream() -> Result<()> {
+        set_testing_config().await?;
+        let toml = "
+        [[unit]]
+        uuid = '5d1e4c5e-7f0a-4be1-9a36-3c8d1c1ef3a0'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['lb.example.com']
+        [unit.action]
+        proxy = 'http://127.0.0.1:8222'
+        [unit.health_check]
+        probe = 'tcp'
+        ";
+        let config = ConfigFile::from_toml_str(toml)?;
+        config.push_to_db().await?;
+
+        // Fail every server
+        let db = connect_db().await?;
+        UpstreamServer::update_many()
+            .col_expr(upstream_server::Column::Down, Expr::value(true))
+            .exec(&db)
+            .await
+            .map_err(JucenitError::from)?;
+
+        let nginx_config = NginxConfig::pull().await?;
+        let name = config.unit[0].upstream_name();
+        assert!(!nginx_config.upstreams.contains_key(&name));
+        let route = &nginx_config.routes["jucenit_[*:443]"];
+        let route = route
+            .iter()
+            .find(|x| x.match_.host == Some("lb.example.com".to_owned()))
+            .unwrap();
+        assert_eq!(
+            route.action.clone().unwrap().raw_params,
+            Some(serde_json::json!({ "return": 503 }))
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     async fn convert() -> Result<()> {
         set_testing_config().await?;
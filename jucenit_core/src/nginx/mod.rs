@@ -1,10 +1,14 @@
 pub mod certificate;
+pub mod client;
 pub mod config;
 pub mod from_database;
 pub mod options;
+pub mod status;
 
 // Reexports
 pub use certificate::CertificateStore;
+pub use client::{HttpClient, MemoryClient, UnitClient};
 pub use config::Config;
 pub use from_database::*;
 pub use options::{Nginx, SETTINGS};
+pub use status::Status;
@@ -7,7 +7,7 @@ use tokio::sync::Mutex;
 // Error Handling
 use miette::{Error, IntoDiagnostic, Result};
 // Structs
-use super::{CertificateStore, Config};
+use super::{CertificateStore, Config, Status};
 
 pub static SETTINGS: Lazy<Arc<Mutex<Settings>>> =
     Lazy::new(|| Arc::new(Mutex::new(Settings::default())));
@@ -33,11 +33,11 @@ impl Default for Settings {
 impl Settings {
     pub fn get_url(&self) -> String {
         if let Some(url) = &self.url {
-            return url.to_owned();
+            url.to_owned()
         } else if let Some(socket) = &self.socket {
-            return socket.to_owned();
+            socket.to_owned()
         } else {
-            return Settings::default().url.unwrap();
+            Settings::default().url.unwrap()
         }
     }
 }
@@ -48,7 +48,7 @@ impl Settings {
 pub struct Nginx {
     pub config: Config,
     pub certificates: serde_json::Value,
-    pub status: serde_json::Value,
+    pub status: Status,
     #[serde(skip)]
     pub settings: Settings,
 }
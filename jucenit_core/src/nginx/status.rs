@@ -0,0 +1,161 @@
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+// Global vars
+use crate::nginx::client::client;
+// Error Handling
+use miette::{IntoDiagnostic, Result};
+// Config file
+use crate::cast::Config as ConfigFile;
+
+/**
+* Nginx-unit usage statistics.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct Status {
+    #[serde(default)]
+    pub connections: Connections,
+    #[serde(default)]
+    pub requests: Requests,
+    #[serde(default)]
+    pub applications: IndexMap<String, ApplicationStatus>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Connections {
+    pub accepted: u64,
+    pub active: u64,
+    pub idle: u64,
+    pub closed: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Requests {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct ApplicationStatus {
+    pub processes: Processes,
+    pub requests: Requests,
+    // Jucenit units passing requests to the application,
+    // unit uuid to hosts.
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    pub units: IndexMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Processes {
+    pub running: u64,
+    pub starting: u64,
+    pub idle: u64,
+}
+
+impl Status {
+    /**
+     * Get the nginx-unit usage statistics.
+     */
+    pub async fn get() -> Result<Status> {
+        let status = client().await.get_status().await?;
+        serde_json::from_value(status).into_diagnostic()
+    }
+    /**
+     * Attribute applications usage to the units passing requests to them.
+     */
+    pub fn attribute(&mut self, config: &ConfigFile) {
+        for unit in &config.unit {
+            let name = unit.action.as_ref().and_then(|x| x.application());
+            if let Some(app) = name.and_then(|x| self.applications.get_mut(&x)) {
+                let hosts = unit.match_.hosts.clone().unwrap_or_default();
+                app.units.insert(unit.uuid.clone(), hosts);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let c = &self.connections;
+        writeln!(
+            f,
+            "connections: {} active, {} idle, {} accepted, {} closed",
+            c.active, c.idle, c.accepted, c.closed
+        )?;
+        writeln!(f, "requests: {} total", self.requests.total.unwrap_or(0))?;
+        if self.applications.is_empty() {
+            return Ok(());
+        }
+        writeln!(f, "applications:")?;
+        for (name, app) in &self.applications {
+            let p = &app.processes;
+            writeln!(
+                f,
+                "  {}: {} running, {} starting, {} idle processes, {} active requests",
+                name,
+                p.running,
+                p.starting,
+                p.idle,
+                app.requests.active.unwrap_or(0)
+            )?;
+            for (uuid, hosts) in &app.units {
+                writeln!(f, "    unit {} {:?}", uuid, hosts)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    // Error Handling
+    use miette::{IntoDiagnostic, Result};
+
+    #[test]
+    fn attribute_application_usage() -> Result<()> {
+        let json = r#"{
+          "connections": { "accepted": 1067, "active": 13, "idle": 4, "closed": 1050 },
+          "requests": { "total": 1307 },
+          "applications": {
+            "blog": {
+              "processes": { "running": 14, "starting": 0, "idle": 4 },
+              "requests": { "active": 10 }
+            }
+          }
+        }"#;
+        let mut status: Status = serde_json::from_str(json).into_diagnostic()?;
+
+        let toml = "
+        [[application]]
+        name = 'blog'
+        type = 'python 3.11'
+        path = '/home/website/blog'
+
+        [[unit]]
+        uuid = '0b9e7a43-6b32-4f4e-9d59-0c2b1f8c2a51'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['blog.example.com']
+        [unit.action]
+        pass = 'applications/blog'
+        ";
+        let config = ConfigFile::from_toml_str(toml)?;
+        status.attribute(&config);
+
+        let blog = &status.applications["blog"];
+        assert_eq!(blog.processes.running, 14);
+        assert_eq!(
+            blog.units["0b9e7a43-6b32-4f4e-9d59-0c2b1f8c2a51"],
+            vec!["blog.example.com".to_owned()]
+        );
+        println!("{}", status);
+        Ok(())
+    }
+}
@@ -0,0 +1,480 @@
+//!
+//! Turn labelled containers into units,
+//! from the Docker (or Podman compatible) api.
+//!
+//! Labels:
+//! - jucenit.host: comma separated hosts (mandatory)
+//! - jucenit.port: container port to proxy requests to (mandatory)
+//! - jucenit.listeners: comma separated listeners, defaults to "*:443"
+//! - jucenit.uri: uri pattern to match
+//!
+
+use crate::cast::{Action, Config as ConfigFile, Match, Unit as ConfigUnit};
+use crate::http::get_unix;
+use crate::nginx::Config as NginxConfig;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use uuid::Uuid;
+// Database
+use crate::database::entity::{prelude::*, *};
+use crate::database::{connect_db, parse_column};
+use sea_orm::{prelude::*, sea_query::OnConflict, ActiveValue};
+// Error Handling
+use crate::error::JucenitError;
+use miette::{IntoDiagnostic, Result};
+
+// Global option keeping the uuids of the units pushed by the provider
+const OWNED_OPTION: &str = "provider_units";
+
+#[derive(Debug, Clone)]
+pub struct ContainerProvider {
+    // Docker or Podman api socket
+    pub socket: String,
+    pub interval: Duration,
+}
+impl Default for ContainerProvider {
+    fn default() -> Self {
+        ContainerProvider {
+            socket: "/var/run/docker.sock".to_owned(),
+            interval: Duration::from_secs(5),
+        }
+    }
+}
+
+// Api structs
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct Container {
+    pub id: String,
+    #[serde(default)]
+    pub names: Vec<String>,
+    #[serde(default)]
+    pub labels: Option<IndexMap<String, String>>,
+    #[serde(default)]
+    pub state: String,
+    #[serde(default)]
+    pub ports: Option<Vec<Port>>,
+    #[serde(default)]
+    pub network_settings: Option<NetworkSettings>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct Port {
+    pub private_port: u16,
+    pub public_port: Option<u16>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct NetworkSettings {
+    #[serde(default)]
+    pub networks: IndexMap<String, Network>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Network {
+    #[serde(rename = "IPAddress", default)]
+    pub ip_address: String,
+}
+
+impl Container {
+    fn name(&self) -> String {
+        self.names
+            .first()
+            .map(|x| x.trim_start_matches('/').to_owned())
+            .unwrap_or(self.id.clone())
+    }
+    fn label(&self, key: &str) -> Option<String> {
+        self.labels.as_ref()?.get(key).cloned()
+    }
+    /**
+     * Deterministic unit uuid derived from the container name,
+     * so units survive container recreation.
+     */
+    pub fn uuid(&self) -> String {
+        let name = format!("jucenit:container:{}", self.name());
+        Uuid::new_v5(&Uuid::NAMESPACE_URL, name.as_bytes()).to_string()
+    }
+    /**
+     * Returns the container unit if it has routing labels.
+     */
+    pub fn to_unit(&self) -> Result<Option<ConfigUnit>> {
+        let (hosts, port) = match (self.label("jucenit.host"), self.label("jucenit.port")) {
+            (Some(hosts), Some(port)) => (hosts, port),
+            _ => return Ok(None),
+        };
+        let port: u16 = port.parse().map_err(|_| {
+            let message = format!(
+                "Container {:?} has an invalid jucenit.port label {:?}",
+                self.name(),
+                port
+            );
+            JucenitError::Config(message)
+        })?;
+
+        // Prefer the published port, rootless containers are not reachable by ip.
+        let published = self
+            .ports
+            .iter()
+            .flatten()
+            .find(|x| x.private_port == port)
+            .and_then(|x| x.public_port);
+        let ip = self.network_settings.as_ref().and_then(|x| {
+            x.networks
+                .values()
+                .map(|x| x.ip_address.clone())
+                .find(|x| !x.is_empty())
+        });
+        let proxy = match (published, ip) {
+            (Some(public_port), _) => format!("http://127.0.0.1:{}", public_port),
+            (None, Some(ip)) => format!("http://{}:{}", ip, port),
+            (None, None) => {
+                let message = format!(
+                    "Container {:?} port {} is neither published nor reachable on a network",
+                    self.name(),
+                    port
+                );
+                return Err(JucenitError::Config(message).into());
+            }
+        };
+
+        let split = |x: String| -> Vec<String> {
+            x.split(',')
+                .map(|x| x.trim().to_owned())
+                .filter(|x| !x.is_empty())
+                .collect()
+        };
+        let unit = ConfigUnit {
+            uuid: self.uuid(),
+            listeners: self
+                .label("jucenit.listeners")
+                .map(split)
+                .unwrap_or(vec!["*:443".to_owned()]),
+            match_: Match {
+                hosts: Some(split(hosts)),
+                raw_params: match self.label("jucenit.uri") {
+                    Some(uri) => Some(serde_json::json!({ "uri": uri })),
+                    None => Some(serde_json::json!({})),
+                },
+            },
+            action: Some(Action {
+                raw_params: Some(serde_json::json!({ "proxy": proxy })),
+            }),
+            ..Default::default()
+        };
+        Ok(Some(unit))
+    }
+}
+
+impl ContainerProvider {
+    pub async fn containers(&self) -> Result<Vec<Container>> {
+        let body = get_unix(&self.socket, "/containers/json?all=true").await?;
+        serde_json::from_str(&body).into_diagnostic()
+    }
+    /**
+     * Returns the units of running containers,
+     * and the uuids of every labelled container.
+     */
+    pub async fn units(&self) -> Result<(Vec<ConfigUnit>, Vec<String>)> {
+        let mut units = vec![];
+        let mut managed = vec![];
+        for container in self.containers().await? {
+            if container.label("jucenit.host").is_none() {
+                continue;
+            }
+            managed.push(container.uuid());
+            if container.state != "running" {
+                continue;
+            }
+            match container.to_unit() {
+                Ok(Some(unit)) => units.push(unit),
+                Ok(None) => {}
+                Err(e) => println!("{:?}", e),
+            };
+        }
+        Ok((units, managed))
+    }
+    /**
+     * Returns the uuids of the units pushed by the provider,
+     * including those of containers removed since.
+     */
+    pub async fn owned() -> Result<Vec<String>> {
+        let db = connect_db().await?;
+        let option = GlobalOption::find()
+            .filter(global_option::Column::Name.eq(OWNED_OPTION))
+            .one(&db)
+            .await
+            .map_err(JucenitError::from)?;
+        match option {
+            Some(option) => parse_column(&format!("option {:?}", option.name), &option.raw_params),
+            None => Ok(vec![]),
+        }
+    }
+    async fn set_owned(uuids: &[String]) -> Result<()> {
+        let db = connect_db().await?;
+        let option = global_option::ActiveModel {
+            name: ActiveValue::Set(OWNED_OPTION.to_owned()),
+            raw_params: ActiveValue::Set(serde_json::to_string(uuids).into_diagnostic()?),
+            ..Default::default()
+        };
+        GlobalOption::insert(option)
+            .on_conflict(
+                OnConflict::column(global_option::Column::Name)
+                    .update_column(global_option::Column::RawParams)
+                    .to_owned(),
+            )
+            .exec(&db)
+            .await
+            .map_err(JucenitError::from)?;
+        Ok(())
+    }
+    /**
+     * Push the units of running containers
+     * and remove the ones of stopped or removed containers.
+     */
+    pub async fn sync(&self, previous: &mut Option<ConfigFile>) -> Result<()> {
+        let (units, mut managed) = self.units().await?;
+        // Units of labelled containers pushed by a previous run
+        let last = match previous {
+            Some(previous) => previous.clone(),
+            None => {
+                managed.extend(Self::owned().await?);
+                ConfigFile {
+                    unit: ConfigFile::pull()
+                        .await?
+                        .unit
+                        .into_iter()
+                        .filter(|x| managed.contains(&x.uuid))
+                        .collect(),
+                    ..Default::default()
+                }
+            }
+        };
+        let config = ConfigFile {
+            unit: units,
+            ..Default::default()
+        };
+        let changed = serde_json::to_value(&config).into_diagnostic()?
+            != serde_json::to_value(&last).into_diagnostic()?;
+        if changed {
+            config.push_diff_to_db(&last).await?;
+        }
+        // The database holds the units,
+        // diff the next containers against them even if nginx-unit rejects them.
+        let uuids: Vec<String> = config.unit.iter().map(|x| x.uuid.clone()).collect();
+        Self::set_owned(&uuids).await?;
+        *previous = Some(config);
+        if changed {
+            NginxConfig::pull().await?.set().await?;
+        }
+        Ok(())
+    }
+    /**
+     * Poll containers and add or remove their units
+     * as they start and stop.
+     */
+    pub async fn watch(&self) -> Result<()> {
+        let mut previous: Option<ConfigFile> = None;
+        loop {
+            if let Err(e) = self.sync(&mut previous).await {
+                println!("{:?}", e);
+            }
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Request, Response};
+    use serial_test::serial;
+    use tokio::net::UnixListener;
+
+    /**
+     * Answer every request with the same containers list.
+     */
+    async fn stub_api(socket: &str, containers: serde_json::Value) -> Result<()> {
+        let _ = std::fs::remove_file(socket);
+        std::fs::create_dir_all("/tmp/jucenit").into_diagnostic()?;
+        let listener = UnixListener::bind(socket).into_diagnostic()?;
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let _ = Request::read(&mut stream).await;
+                let res = Response::json("200 OK", &containers);
+                let _ = res.write(&mut stream).await;
+            }
+        });
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn get_units_from_labels() -> Result<()> {
+        let containers = serde_json::json!([
+          {
+            "Id": "8dfafdbc3a40",
+            "Names": ["/blog"],
+            "State": "running",
+            "Labels": { "jucenit.host": "blog.example.com, www.example.com", "jucenit.port": "80" },
+            "Ports": [{ "PrivatePort": 80, "PublicPort": 8080, "Type": "tcp" }],
+            "NetworkSettings": { "Networks": { "bridge": { "IPAddress": "172.17.0.2" } } }
+          },
+          {
+            "Id": "9cd87474be90",
+            "Names": ["/api"],
+            "State": "running",
+            "Labels": { "jucenit.host": "api.example.com", "jucenit.port": "3000", "jucenit.listeners": "*:80" },
+            "Ports": [],
+            "NetworkSettings": { "Networks": { "bridge": { "IPAddress": "172.17.0.3" } } }
+          },
+          {
+            "Id": "3176a2479c92",
+            "Names": ["/stopped"],
+            "State": "exited",
+            "Labels": { "jucenit.host": "stopped.example.com", "jucenit.port": "80" },
+            "Ports": [],
+            "NetworkSettings": { "Networks": { "bridge": { "IPAddress": "" } } }
+          },
+          {
+            "Id": "4cb07b47f9fb",
+            "Names": ["/db"],
+            "State": "running",
+            "Labels": {}
+          }
+        ]);
+        let provider = ContainerProvider {
+            socket: "/tmp/jucenit/test_containers.sock".to_owned(),
+            ..Default::default()
+        };
+        stub_api(&provider.socket, containers).await?;
+
+        let (units, managed) = provider.units().await?;
+        assert_eq!(units.len(), 2);
+        assert_eq!(managed.len(), 3);
+
+        let blog = &units[0];
+        assert_eq!(
+            blog.match_.hosts,
+            Some(vec![
+                "blog.example.com".to_owned(),
+                "www.example.com".to_owned()
+            ])
+        );
+        assert_eq!(blog.listeners, vec!["*:443".to_owned()]);
+        assert_eq!(
+            blog.action.clone().unwrap().proxy(),
+            Some("http://127.0.0.1:8080".to_owned())
+        );
+        let api = &units[1];
+        assert_eq!(api.listeners, vec!["*:80".to_owned()]);
+        assert_eq!(
+            api.action.clone().unwrap().proxy(),
+            Some("http://172.17.0.3:3000".to_owned())
+        );
+
+        // Deterministic uuids
+        let (units, _) = provider.units().await?;
+        assert_eq!(units[0].uuid, blog.uuid);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn remove_units_of_removed_containers() -> Result<()> {
+        let containers = serde_json::json!([
+          {
+            "Id": "8dfafdbc3a40",
+            "Names": ["/gone"],
+            "State": "running",
+            "Labels": { "jucenit.host": "gone.example.com", "jucenit.port": "80" },
+            "Ports": [{ "PrivatePort": 80, "PublicPort": 8081, "Type": "tcp" }]
+          }
+        ]);
+        let provider = ContainerProvider {
+            socket: "/tmp/jucenit/test_gone_containers.sock".to_owned(),
+            ..Default::default()
+        };
+        stub_api(&provider.socket, containers).await?;
+        ConfigFile::default().set().await?;
+        provider.sync(&mut None).await?;
+        let uuid = provider.units().await?.0[0].uuid.clone();
+        assert!(ConfigFile::pull()
+            .await?
+            .unit
+            .iter()
+            .any(|x| x.uuid == uuid));
+
+        // The container is removed while jucenit is down.
+        let provider = ContainerProvider {
+            socket: "/tmp/jucenit/test_no_containers.sock".to_owned(),
+            ..Default::default()
+        };
+        stub_api(&provider.socket, serde_json::json!([])).await?;
+        provider.sync(&mut None).await?;
+        assert!(!ConfigFile::pull()
+            .await?
+            .unit
+            .iter()
+            .any(|x| x.uuid == uuid));
+        assert!(ContainerProvider::owned().await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn remove_units_after_rejected_update() -> Result<()> {
+        let containers = serde_json::json!([
+          {
+            "Id": "5e1f0c9a7b21",
+            "Names": ["/rejected"],
+            "State": "running",
+            "Labels": { "jucenit.host": "rejected.example.com", "jucenit.port": "80" },
+            "Ports": [{ "PrivatePort": 80, "PublicPort": 8082, "Type": "tcp" }]
+          }
+        ]);
+        let provider = ContainerProvider {
+            socket: "/tmp/jucenit/test_rejected_containers.sock".to_owned(),
+            ..Default::default()
+        };
+        stub_api(&provider.socket, containers).await?;
+        // An application nginx-unit rejects (no type) fails every update.
+        ConfigFile::default().set().await?;
+        ConfigFile::from_toml_str(
+            "
+        [[application]]
+        name = 'broken'
+        processes = 1
+        ",
+        )?
+        .push_to_db()
+        .await?;
+
+        let mut previous = None;
+        assert!(provider.sync(&mut previous).await.is_err());
+        let uuid = provider.units().await?.0[0].uuid.clone();
+        assert!(ConfigFile::pull()
+            .await?
+            .unit
+            .iter()
+            .any(|x| x.uuid == uuid));
+
+        // The container stops.
+        let provider = ContainerProvider {
+            socket: "/tmp/jucenit/test_stopped_containers.sock".to_owned(),
+            ..Default::default()
+        };
+        stub_api(&provider.socket, serde_json::json!([])).await?;
+        let _ = provider.sync(&mut previous).await;
+        assert!(!ConfigFile::pull()
+            .await?
+            .unit
+            .iter()
+            .any(|x| x.uuid == uuid));
+        assert!(ContainerProvider::owned().await?.is_empty());
+        ConfigFile::default().set().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,332 @@
+//!
+//! Detect and repair drift between what jucenit pushed
+//! and the live nginx-unit state:
+//! configuration edited through the control api,
+//! or certificates lost with the unit state directory.
+//!
+
+use crate::database::entity::{prelude::*, *};
+use crate::database::{connect_db, parse_column};
+use crate::nginx::certificate::RENEWAL_LOCK;
+use crate::nginx::{CertificateStore, Config as NginxConfig};
+use crate::ssl;
+use sea_orm::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+// Error Handling
+use crate::error::JucenitError;
+use miette::{IntoDiagnostic, Result};
+
+/**
+* Reconciliation settings.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Reconcile {
+    #[serde(default)]
+    pub mode: ReconcileMode,
+    // Seconds between checks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReconcileMode {
+    // Re-apply the expected state
+    #[default]
+    Apply,
+    // Only report differences
+    Report,
+}
+
+/**
+* A difference between the expected and the live configuration.
+* Paths are segments under "/config" (ex: ["routes", "jucenit_[*:443]"]).
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigDrift {
+    Missing {
+        path: Vec<String>,
+        expected: serde_json::Value,
+    },
+    Unexpected {
+        path: Vec<String>,
+    },
+    Changed {
+        path: Vec<String>,
+        expected: serde_json::Value,
+    },
+}
+impl fmt::Display for ConfigDrift {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigDrift::Missing { path, .. } => write!(f, "missing /config/{}", path.join("/")),
+            ConfigDrift::Unexpected { path } => {
+                write!(f, "unexpected /config/{}", path.join("/"))
+            }
+            ConfigDrift::Changed { path, .. } => write!(f, "changed /config/{}", path.join("/")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Drift {
+    pub config: Vec<ConfigDrift>,
+    // Hosts without a valid certificate in the store
+    pub certificates: Vec<String>,
+}
+impl Drift {
+    pub fn is_empty(&self) -> bool {
+        self.config.is_empty() && self.certificates.is_empty()
+    }
+    /**
+     * Compare the expected and live state.
+     */
+    pub async fn check() -> Result<Drift> {
+        let mut drift = Drift::default();
+
+        let acme = ssl::Acme::pull().await?;
+        let valid = CertificateStore::get_all_valid().await?;
+        for name in ssl::certificate_names().await? {
+            let certificates = acme.certificates_for(&name).await?;
+            if certificates.iter().any(|(x, _)| !valid.contains_key(x)) {
+                drift.certificates.push(name);
+            }
+        }
+
+        // In merge mode, unmanaged objects are expected too.
+        let expected = NginxConfig::pull().await?.render().await?;
+        let live = NginxConfig::get_value().await?;
+        diff(&mut vec![], &expected, &live, &mut drift.config);
+        Ok(drift)
+    }
+    /**
+     * Restore missing certificates, issue the ones never kept,
+     * and replace the live configuration.
+     */
+    pub async fn apply(&self) -> Result<()> {
+        if !self.certificates.is_empty() {
+            let _lock = RENEWAL_LOCK.lock().await;
+            CertificateStore::restore().await?;
+            let acme = ssl::Acme::pull().await?;
+            let valid = CertificateStore::get_all_valid().await?;
+            for name in &self.certificates {
+                let certificates = acme.certificates_for(name).await?;
+                if certificates.iter().any(|(x, _)| !valid.contains_key(x)) {
+                    CertificateStore::descrete_update(name).await?;
+                }
+            }
+        }
+        // Listeners tls options depend on the fresh certificates.
+        if !self.config.is_empty() || !self.certificates.is_empty() {
+            NginxConfig::pull().await?.set().await?;
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no drift");
+        }
+        for x in &self.config {
+            writeln!(f, "config: {}", x)?;
+        }
+        for x in &self.certificates {
+            writeln!(f, "certificate: missing for {}", x)?;
+        }
+        Ok(())
+    }
+}
+
+impl Reconcile {
+    /**
+     * Read the reconciliation settings from database.
+     */
+    pub async fn pull() -> Result<Reconcile> {
+        let db = connect_db().await?;
+        let option = GlobalOption::find()
+            .filter(global_option::Column::Name.eq("reconcile"))
+            .one(&db)
+            .await
+            .map_err(JucenitError::from)?;
+        match option {
+            Some(option) => parse_column(&format!("option {:?}", option.name), &option.raw_params),
+            None => Ok(Reconcile::default()),
+        }
+    }
+    /**
+     * Check for drift and apply or report according to the mode.
+     */
+    pub async fn run(&self) -> Result<Drift> {
+        let drift = Drift::check().await?;
+        if !drift.is_empty() {
+            print!("{}", drift);
+            if self.mode == ReconcileMode::Apply {
+                drift.apply().await?;
+            }
+        }
+        Ok(drift)
+    }
+    /**
+     * Periodically reconcile.
+     */
+    pub async fn watch() -> Result<()> {
+        loop {
+            let reconcile = match Reconcile::pull().await {
+                Ok(x) => x,
+                // Don't guess the mode, retry later.
+                Err(e) => {
+                    println!("{:?}", e);
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                    continue;
+                }
+            };
+            if let Err(e) = reconcile.run().await {
+                println!("{:?}", e);
+            }
+            let interval = Duration::from_secs(reconcile.interval.unwrap_or(60));
+            tokio::time::sleep(interval).await;
+        }
+    }
+}
+
+/**
+* Recursively compare json objects.
+*/
+fn diff(
+    path: &mut Vec<String>,
+    expected: &serde_json::Value,
+    live: &serde_json::Value,
+    res: &mut Vec<ConfigDrift>,
+) {
+    match (expected, live) {
+        (serde_json::Value::Object(expected), serde_json::Value::Object(live)) => {
+            for (k, v) in expected {
+                path.push(k.to_owned());
+                match live.get(k) {
+                    Some(x) => diff(path, v, x, res),
+                    None => res.push(ConfigDrift::Missing {
+                        path: path.clone(),
+                        expected: v.clone(),
+                    }),
+                }
+                path.pop();
+            }
+            for k in live.keys() {
+                if !expected.contains_key(k) {
+                    let mut path = path.clone();
+                    path.push(k.to_owned());
+                    res.push(ConfigDrift::Unexpected { path });
+                }
+            }
+        }
+        (expected, live) => {
+            if expected != live {
+                res.push(ConfigDrift::Changed {
+                    path: path.clone(),
+                    expected: expected.clone(),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ConfigFile;
+    use serial_test::serial;
+    // Error Handling
+    use miette::Result;
+
+    #[test]
+    fn diff_configs() -> Result<()> {
+        let expected = serde_json::json!({
+          "listeners": { "*:443": { "pass": "routes/jucenit_[*:443]" } },
+          "routes": { "jucenit_[*:443]": [{ "action": { "proxy": "http://127.0.0.1:8888" } }] }
+        });
+        let live = serde_json::json!({
+          "listeners": { "*:8080": { "pass": "routes/jucenit_[*:443]" } },
+          "routes": { "jucenit_[*:443]": [{ "action": { "proxy": "http://127.0.0.1:9999" } }] }
+        });
+        let mut res = vec![];
+        diff(&mut vec![], &expected, &live, &mut res);
+        let res: Vec<String> = res.iter().map(|x| x.to_string()).collect();
+        assert_eq!(
+            res,
+            vec![
+                "missing /config/listeners/*:443",
+                "unexpected /config/listeners/*:8080",
+                "changed /config/routes/jucenit_[*:443]",
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn reconcile_live_config() -> Result<()> {
+        let toml = "
+        [[unit]]
+        uuid = 'd3630938-5851-43ab-a523-84e0c6af9eb1'
+        listeners = ['*:80']
+        [unit.match]
+        hosts = ['example.com']
+        [unit.action]
+        proxy = 'http://127.0.0.1:8888'
+        ";
+        ConfigFile::from_toml_str(toml)?.set().await?;
+
+        // Edit the live configuration behind jucenit's back
+        let mut live = NginxConfig::get().await?;
+        live.listeners.clear();
+        live.routes.clear();
+        live.set().await?;
+
+        let drift = Drift::check().await?;
+        assert!(drift
+            .config
+            .iter()
+            .any(|x| x.to_string() == "missing /config/routes/jucenit_[*:80]"));
+
+        // Certificates issuance is covered by the ssl tests
+        let drift = Drift {
+            certificates: vec![],
+            ..drift
+        };
+        drift.apply().await?;
+        assert!(Drift::check().await?.config.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn restore_lost_certificates() -> Result<()> {
+        let toml = "
+        [[unit]]
+        uuid = 'd3630938-5851-43ab-a523-84e0c6af9eb2'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['kept.example.com']
+        [unit.action]
+        proxy = 'http://127.0.0.1:8888'
+        ";
+        ConfigFile::from_toml_str(toml)?.set().await?;
+        ssl::set_certificate_groups(&ssl::certificate_groups().await?).await?;
+        let dns = "kept.example.com";
+        CertificateStore::save(dns, &ssl::Fake::get(dns)?).await?;
+
+        // The unit state directory is wiped.
+        CertificateStore::clean().await?;
+        let drift = Drift::check().await?;
+        assert!(drift.certificates.contains(&dns.to_owned()));
+
+        // The kept certificate is restored instead of ordered.
+        drift.apply().await?;
+        assert!(Drift::check().await?.certificates.is_empty());
+        Ok(())
+    }
+}
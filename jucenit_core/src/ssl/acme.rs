@@ -0,0 +1,744 @@
+use acme2::{Directory, DirectoryBuilder};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+// Database
+use crate::database::entity::{prelude::*, *};
+use crate::database::{connect_db, parse_column};
+use sea_orm::prelude::*;
+// Error Handling
+use crate::error::JucenitError;
+use miette::{IntoDiagnostic, Result};
+// Directory
+use super::pebble::{PEBBLE_CA_PATH, PEBBLE_URL};
+// Dns challenge
+use super::dns::DnsSettings;
+use super::group::group_hosts;
+
+// Renew when a third of the certificate lifetime remains
+pub const DEFAULT_RENEWAL_RATIO: f64 = 1.0 / 3.0;
+
+/**
+* Certificate issuance and renewal settings.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Acme {
+    // Fraction of the lifetime left when renewing (ex: 0.33)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub renewal_ratio: Option<f64>,
+    // Follow the CA suggested renewal window when advertised (default: true)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ari: Option<bool>,
+    // Account contacts for expiry and incident notices (ex: ["admin@example.com"])
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub contacts: Vec<String>,
+    // External account binding, required by some CAs (ex: ZeroSSL),
+    // only sent to the server wide directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eab: Option<Eab>,
+    // Certificate authority (default: letsencrypt)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub directory: Option<AcmeDirectory>,
+    // Domain validation method (default: http-01)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub challenge: Option<ChallengeType>,
+    // Dns provider for the dns-01 challenge
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns: Option<DnsSettings>,
+    // Hosts covered by a certificate (default: host)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grouping: Option<Grouping>,
+    // Certificate key, or keys for a certificate each (default: ecdsa-p256)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_type: Option<KeyTypes>,
+}
+
+/**
+* Per unit acme settings, overriding the server wide ones
+* for the unit hosts.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct UnitAcme {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub directory: Option<AcmeDirectory>,
+    // Account settings of the unit directory,
+    // shared by every unit using that directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contacts: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eab: Option<Eab>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub challenge: Option<ChallengeType>,
+    // Share one certificate with the units of the same group (ex: "example")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub certificate_group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_type: Option<KeyTypes>,
+}
+
+/**
+* Certificate private key algorithm.
+* RSA 4096 stays the default, the key jucenit always issued, ECDSA is opt-in.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyType {
+    EcdsaP256,
+    EcdsaP384,
+    Rsa2048,
+    #[default]
+    Rsa4096,
+}
+impl KeyType {
+    pub fn name(&self) -> &str {
+        match self {
+            KeyType::EcdsaP256 => "ecdsa-p256",
+            KeyType::EcdsaP384 => "ecdsa-p384",
+            KeyType::Rsa2048 => "rsa2048",
+            KeyType::Rsa4096 => "rsa4096",
+        }
+    }
+    pub fn generate(&self) -> Result<PKey<Private>> {
+        let ec = |nid: Nid| -> Result<PKey<Private>> {
+            let group = EcGroup::from_curve_name(nid).into_diagnostic()?;
+            let key = EcKey::generate(&group).into_diagnostic()?;
+            PKey::from_ec_key(key).into_diagnostic()
+        };
+        let rsa = |bits: u32| -> Result<PKey<Private>> {
+            let key = Rsa::generate(bits).into_diagnostic()?;
+            PKey::from_rsa(key).into_diagnostic()
+        };
+        match self {
+            KeyType::EcdsaP256 => ec(Nid::X9_62_PRIME256V1),
+            KeyType::EcdsaP384 => ec(Nid::SECP384R1),
+            KeyType::Rsa2048 => rsa(2048),
+            KeyType::Rsa4096 => rsa(4096),
+        }
+    }
+}
+/**
+* One key type, or several to issue a certificate per key
+* (ex: ECDSA for modern clients and RSA for older ones).
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum KeyTypes {
+    One(KeyType),
+    Many(Vec<KeyType>),
+}
+impl KeyTypes {
+    pub fn to_vec(&self) -> Vec<KeyType> {
+        let mut res: Vec<KeyType> = vec![];
+        let list = match self {
+            KeyTypes::One(x) => vec![x.to_owned()],
+            KeyTypes::Many(x) => x.to_owned(),
+        };
+        for x in list {
+            if !res.contains(&x) {
+                res.push(x);
+            }
+        }
+        if res.is_empty() {
+            res.push(KeyType::default());
+        }
+        res
+    }
+}
+
+/**
+* Issue a certificate per host,
+* or one multi-domain certificate per unit.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Grouping {
+    #[default]
+    Host,
+    Unit,
+}
+
+/**
+* The longest a tls-alpn-01 challenge may keep the tls port.
+*/
+pub const TLS_ALPN_TIMEOUT: Duration = Duration::from_secs(90);
+
+/**
+* How the CA checks a host is ours.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub enum ChallengeType {
+    // A file served on port 80
+    #[default]
+    #[serde(rename = "http-01")]
+    Http01,
+    // A certificate served on port 443, for hosts without port 80.
+    // Nginx-unit can't negotiate "acme-tls/1", so the whole 443 listener
+    // is taken down while the CA validates, for up to TLS_ALPN_TIMEOUT:
+    // every https host is unreachable meanwhile.
+    #[serde(rename = "tls-alpn-01")]
+    TlsAlpn01,
+    // A TXT record, for wildcards and hosts unreachable from the CA
+    #[serde(rename = "dns-01")]
+    Dns01,
+}
+impl ChallengeType {
+    /**
+     * Warn about the https outage when tls-alpn-01 is selected.
+     */
+    pub fn warn(&self, scope: &str) {
+        if self == &ChallengeType::TlsAlpn01 {
+            println!(
+                "acme: {} uses tls-alpn-01, the 443 listener is taken down for up to {}s while a host is validated",
+                scope,
+                TLS_ALPN_TIMEOUT.as_secs()
+            );
+        }
+    }
+    pub fn name(&self) -> &str {
+        match self {
+            ChallengeType::Http01 => "http-01",
+            ChallengeType::TlsAlpn01 => "tls-alpn-01",
+            ChallengeType::Dns01 => "dns-01",
+        }
+    }
+}
+
+/**
+* An ACME directory, from a preset name
+* or an url with an optional root certificate to trust (ex: private CA).
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum AcmeDirectory {
+    Preset(DirectoryPreset),
+    Custom {
+        url: String,
+        // Pem file path
+        #[serde(skip_serializing_if = "Option::is_none")]
+        root_ca: Option<String>,
+    },
+}
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DirectoryPreset {
+    Letsencrypt,
+    LetsencryptStaging,
+    Zerossl,
+    Buypass,
+    // Local testing server
+    Pebble,
+}
+impl Default for AcmeDirectory {
+    fn default() -> Self {
+        // Development builds are tested against pebble.
+        #[cfg(debug_assertions)]
+        return AcmeDirectory::Preset(DirectoryPreset::Pebble);
+        #[cfg(not(debug_assertions))]
+        return AcmeDirectory::Preset(DirectoryPreset::Letsencrypt);
+    }
+}
+
+/**
+* External account binding credentials provided by the CA.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Eab {
+    pub kid: String,
+    // Base64url encoded
+    pub hmac_key: String,
+}
+impl Eab {
+    pub fn key(&self) -> Result<PKey<Private>> {
+        let mut encoded = self
+            .hmac_key
+            .trim_end_matches('=')
+            .replace('-', "+")
+            .replace('_', "/");
+        while !encoded.len().is_multiple_of(4) {
+            encoded.push('=');
+        }
+        let key = openssl::base64::decode_block(&encoded).map_err(|_| {
+            let message = format!("Invalid eab hmac key for kid {:?}", self.kid);
+            JucenitError::Config(message)
+        })?;
+        PKey::hmac(&key).into_diagnostic()
+    }
+}
+
+impl Acme {
+    /**
+     * Read the acme settings from database.
+     */
+    pub async fn pull() -> Result<Acme> {
+        let db = connect_db().await?;
+        let option = GlobalOption::find()
+            .filter(global_option::Column::Name.eq("acme"))
+            .one(&db)
+            .await
+            .map_err(JucenitError::from)?;
+        match option {
+            Some(option) => parse_column(&format!("option {:?}", option.name), &option.raw_params),
+            None => Ok(Acme::default()),
+        }
+    }
+    pub fn ratio(&self) -> Result<f64> {
+        match self.renewal_ratio {
+            None => Ok(DEFAULT_RENEWAL_RATIO),
+            Some(x) if x > 0.0 && x < 1.0 => Ok(x),
+            Some(x) => {
+                let message = format!("Renewal ratio must be between 0 and 1, got {}", x);
+                Err(JucenitError::Config(message).into())
+            }
+        }
+    }
+    pub fn directory(&self) -> AcmeDirectory {
+        self.directory.clone().unwrap_or_default()
+    }
+    pub fn challenge(&self) -> ChallengeType {
+        self.challenge.clone().unwrap_or_default()
+    }
+    pub fn key_types(&self) -> Vec<KeyType> {
+        self.key_type
+            .as_ref()
+            .map(|x| x.to_vec())
+            .unwrap_or(vec![KeyType::default()])
+    }
+    /**
+     * Returns the certificates to issue for a group, by store name, with their key type.
+     * The first key type certificate is named after the group,
+     * the others are suffixed with their key type (ex: "example.com_rsa2048").
+     */
+    pub async fn certificates_for(&self, name: &str) -> Result<Vec<(String, KeyType)>> {
+        let hosts = group_hosts(name).await?;
+        let units = Acme::units_for(&hosts).await?;
+        let key_types = agree(
+            name,
+            "key types",
+            units
+                .into_iter()
+                .filter_map(|x| x.key_type.map(|x| x.to_vec())),
+        )?;
+        let key_types = key_types.unwrap_or(self.key_types());
+        let res = key_types
+            .into_iter()
+            .enumerate()
+            .map(|(i, x)| match i {
+                0 => (name.to_owned(), x),
+                _ => (format!("{}_{}", name, x.name()), x),
+            })
+            .collect();
+        Ok(res)
+    }
+    pub fn grouping(&self) -> Grouping {
+        self.grouping.clone().unwrap_or_default()
+    }
+    /**
+     * Returns the acme settings of the units matching some hosts.
+     */
+    async fn units_for(hosts: &[String]) -> Result<Vec<UnitAcme>> {
+        let db = connect_db().await?;
+        let hosts = Host::find()
+            .filter(host::Column::Domain.is_in(hosts))
+            .find_with_related(NgMatch)
+            .all(&db)
+            .await
+            .map_err(JucenitError::from)?;
+        let mut res: Vec<UnitAcme> = vec![];
+        for (_, matches) in hosts {
+            for match_ in matches {
+                if let Some(acme) = &match_.acme {
+                    res.push(parse_column(
+                        &format!("acme of unit {:?}", match_.uuid),
+                        acme,
+                    )?);
+                }
+            }
+        }
+        Ok(res)
+    }
+    /**
+     * Returns the acme settings of every unit.
+     */
+    async fn units() -> Result<Vec<UnitAcme>> {
+        let db = connect_db().await?;
+        let matches = NgMatch::find()
+            .filter(ng_match::Column::Acme.is_not_null())
+            .all(&db)
+            .await
+            .map_err(JucenitError::from)?;
+        let mut res: Vec<UnitAcme> = vec![];
+        for match_ in matches {
+            if let Some(acme) = &match_.acme {
+                res.push(parse_column(
+                    &format!("acme of unit {:?}", match_.uuid),
+                    acme,
+                )?);
+            }
+        }
+        Ok(res)
+    }
+    /**
+     * Returns the account contacts and external account binding of a directory:
+     * the ones of the units using it, or the server wide ones.
+     * The server wide binding is only sent to the server wide directory.
+     */
+    pub async fn account_for(
+        &self,
+        directory: &AcmeDirectory,
+    ) -> Result<(Vec<String>, Option<Eab>)> {
+        let units: Vec<UnitAcme> = Acme::units()
+            .await?
+            .into_iter()
+            .filter(|x| x.directory.as_ref().unwrap_or(&self.directory()) == directory)
+            .collect();
+        let url = directory.url().await;
+        let contacts = agree(
+            &url,
+            "contacts",
+            units.iter().filter_map(|x| x.contacts.clone()),
+        )?;
+        let contacts = match contacts {
+            Some(contacts) => contact_urls(&contacts),
+            None => self.contacts(),
+        };
+        let eab = agree(
+            &url,
+            "external account bindings",
+            units.iter().filter_map(|x| x.eab.clone()),
+        )?;
+        let eab = match eab {
+            Some(eab) => Some(eab),
+            None if directory == &self.directory() => self.eab.clone(),
+            None => None,
+        };
+        Ok((contacts, eab))
+    }
+    /**
+     * Returns the directory to get a certificate from:
+     * the one of the units matching its hosts, or the server wide one.
+     */
+    pub async fn directory_for(&self, name: &str) -> Result<AcmeDirectory> {
+        let hosts = group_hosts(name).await?;
+        let units = Acme::units_for(&hosts).await?;
+        let directory = agree(
+            name,
+            "directories",
+            units.into_iter().filter_map(|x| x.directory),
+        )?;
+        Ok(directory.unwrap_or(self.directory()))
+    }
+    /**
+     * Returns the challenge to prove a host ownership with.
+     */
+    pub async fn challenge_for(&self, dns: &str) -> Result<ChallengeType> {
+        let units = Acme::units_for(&[dns.to_owned()]).await?;
+        let challenge = agree(
+            dns,
+            "challenges",
+            units.into_iter().filter_map(|x| x.challenge),
+        )?;
+        let challenge = challenge.unwrap_or(self.challenge());
+        // Only dns-01 proves ownership of a wildcard
+        if dns.starts_with("*.") && challenge != ChallengeType::Dns01 {
+            let message = format!("Wildcard {:?} requires the dns-01 challenge", dns);
+            return Err(JucenitError::Config(message).into());
+        }
+        Ok(challenge)
+    }
+    /**
+     * Returns the dns-01 challenge settings.
+     */
+    pub fn dns(&self) -> Result<DnsSettings> {
+        let message = "The dns-01 challenge requires [acme.dns] settings".to_owned();
+        Ok(self.dns.clone().ok_or(JucenitError::Config(message))?)
+    }
+    pub fn ari(&self) -> bool {
+        self.ari.unwrap_or(true)
+    }
+    /**
+     * Returns contacts as urls (ex: "mailto:admin@example.com").
+     */
+    pub fn contacts(&self) -> Vec<String> {
+        contact_urls(&self.contacts)
+    }
+}
+
+fn contact_urls(contacts: &[String]) -> Vec<String> {
+    contacts
+        .iter()
+        .map(|x| match x.contains(':') {
+            true => x.to_owned(),
+            false => format!("mailto:{}", x),
+        })
+        .collect()
+}
+
+/**
+* Units sharing a host must declare the same setting.
+*/
+pub(super) fn agree<T: PartialEq + std::fmt::Debug>(
+    dns: &str,
+    name: &str,
+    values: impl Iterator<Item = T>,
+) -> Result<Option<T>> {
+    let mut res: Vec<T> = vec![];
+    for value in values {
+        if !res.contains(&value) {
+            res.push(value);
+        }
+    }
+    if res.len() > 1 {
+        let message = format!(
+            "Units matching {:?} declare different acme {}: {:?}",
+            dns, name, res
+        );
+        return Err(JucenitError::Config(message).into());
+    }
+    Ok(res.pop())
+}
+
+impl AcmeDirectory {
+    /**
+     * Returns the directory url and the root certificate to trust.
+     */
+    pub async fn endpoint(&self) -> (String, Option<String>) {
+        match self {
+            AcmeDirectory::Preset(preset) => match preset {
+                DirectoryPreset::Letsencrypt => (
+                    "https://acme-v02.api.letsencrypt.org/directory".to_owned(),
+                    None,
+                ),
+                DirectoryPreset::LetsencryptStaging => (
+                    "https://acme-staging-v02.api.letsencrypt.org/directory".to_owned(),
+                    None,
+                ),
+                DirectoryPreset::Zerossl => ("https://acme.zerossl.com/v2/DV90".to_owned(), None),
+                DirectoryPreset::Buypass => {
+                    ("https://api.buypass.com/acme/directory".to_owned(), None)
+                }
+                DirectoryPreset::Pebble => (
+                    PEBBLE_URL.lock().await.clone(),
+                    Some(PEBBLE_CA_PATH.to_owned()),
+                ),
+            },
+            AcmeDirectory::Custom { url, root_ca } => (url.to_owned(), root_ca.to_owned()),
+        }
+    }
+    pub async fn url(&self) -> String {
+        self.endpoint().await.0
+    }
+    /**
+     * Returns an http client trusting the directory.
+     */
+    pub async fn http_client(&self) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(path) = self.endpoint().await.1 {
+            let raw = tokio::fs::read(&path).await.map_err(|e| {
+                let message = format!("Couldn't read root certificate at {:?}: {}", path, e);
+                JucenitError::Config(message)
+            })?;
+            let cert = reqwest::Certificate::from_pem(&raw).into_diagnostic()?;
+            builder = builder.add_root_certificate(cert);
+        }
+        builder.build().into_diagnostic()
+    }
+    pub async fn build(&self) -> Result<Arc<Directory>> {
+        let dir = DirectoryBuilder::new(self.url().await)
+            .http_client(self.http_client().await?)
+            .build()
+            .await
+            .map_err(JucenitError::from)?;
+        Ok(dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_contacts() {
+        let acme = Acme {
+            contacts: vec![
+                "admin@example.com".to_owned(),
+                "mailto:ops@example.com".to_owned(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            acme.contacts(),
+            vec!["mailto:admin@example.com", "mailto:ops@example.com"]
+        );
+    }
+    #[test]
+    fn read_directories() -> Result<()> {
+        let toml = "
+        [acme]
+        directory = 'letsencrypt-staging'
+        ";
+        let config = crate::ConfigFile::from_toml_str(toml)?;
+        assert_eq!(
+            config.acme.unwrap().directory(),
+            AcmeDirectory::Preset(DirectoryPreset::LetsencryptStaging)
+        );
+        let toml = "
+        [acme.directory]
+        url = 'https://ca.internal/acme/directory'
+        root_ca = '/etc/ssl/internal-ca.pem'
+        ";
+        let config = crate::ConfigFile::from_toml_str(toml)?;
+        assert_eq!(
+            config.acme.unwrap().directory(),
+            AcmeDirectory::Custom {
+                url: "https://ca.internal/acme/directory".to_owned(),
+                root_ca: Some("/etc/ssl/internal-ca.pem".to_owned()),
+            }
+        );
+        Ok(())
+    }
+    #[tokio::test]
+    async fn override_directory_per_unit() -> Result<()> {
+        let toml = "
+        [[unit]]
+        uuid = '8d0d3c1e-2b4f-4a57-9e0b-6f1c2d3e4a5b'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['zerossl.example.com']
+        [unit.action]
+        proxy = 'http://127.0.0.1:8001'
+        [unit.acme]
+        directory = 'zerossl'
+        ";
+        let config = crate::ConfigFile::from_toml_str(toml)?;
+        config.push_to_db().await?;
+
+        let acme = Acme::default();
+        assert_eq!(
+            acme.directory_for("zerossl.example.com").await?,
+            AcmeDirectory::Preset(DirectoryPreset::Zerossl)
+        );
+        assert_eq!(
+            acme.directory_for("other.example.com").await?,
+            acme.directory()
+        );
+        Ok(())
+    }
+    #[tokio::test]
+    async fn bind_accounts_per_directory() -> Result<()> {
+        let toml = "
+        [[unit]]
+        uuid = '8d0d3c1e-2b4f-4a57-9e0b-6f1c2d3e4a5c'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['bound.example.com']
+        [unit.action]
+        proxy = 'http://127.0.0.1:8001'
+        [unit.acme]
+        directory = 'zerossl'
+        contacts = ['zerossl@example.com']
+        [unit.acme.eab]
+        kid = 'zerossl-kid'
+        hmac_key = 'c2VjcmV0'
+        ";
+        let config = crate::ConfigFile::from_toml_str(toml)?;
+        config.push_to_db().await?;
+
+        let acme = Acme {
+            directory: Some(AcmeDirectory::Preset(DirectoryPreset::Buypass)),
+            contacts: vec!["admin@example.com".to_owned()],
+            eab: Some(Eab {
+                kid: "buypass-kid".to_owned(),
+                hmac_key: "c2VjcmV0".to_owned(),
+            }),
+            ..Default::default()
+        };
+        let (contacts, eab) = acme
+            .account_for(&AcmeDirectory::Preset(DirectoryPreset::Zerossl))
+            .await?;
+        assert_eq!(contacts, vec!["mailto:zerossl@example.com"]);
+        assert_eq!(eab.unwrap().kid, "zerossl-kid");
+
+        let (contacts, eab) = acme.account_for(&acme.directory()).await?;
+        assert_eq!(contacts, vec!["mailto:admin@example.com"]);
+        assert_eq!(eab.unwrap().kid, "buypass-kid");
+
+        // The server wide binding isn't sent to other directories.
+        let (_, eab) = acme
+            .account_for(&AcmeDirectory::Preset(DirectoryPreset::LetsencryptStaging))
+            .await?;
+        assert!(eab.is_none());
+        Ok(())
+    }
+    #[test]
+    fn generate_keys() -> Result<()> {
+        let key = KeyType::EcdsaP256.generate()?;
+        assert_eq!(key.id(), openssl::pkey::Id::EC);
+        assert_eq!(key.bits(), 256);
+        let key = KeyType::EcdsaP384.generate()?;
+        assert_eq!(key.bits(), 384);
+        let key = KeyType::Rsa2048.generate()?;
+        assert_eq!(key.id(), openssl::pkey::Id::RSA);
+        assert_eq!(key.bits(), 2048);
+        Ok(())
+    }
+    #[tokio::test]
+    async fn name_certificates_per_key_type() -> Result<()> {
+        let toml = "
+        [[unit]]
+        uuid = '3e2b8c4d-5f6a-4b7c-8d9e-0f1a2b3c4d5e'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['legacy.example.com']
+        [unit.action]
+        proxy = 'http://127.0.0.1:8001'
+        [unit.acme]
+        key_type = ['ecdsa-p256', 'rsa2048']
+        ";
+        let config = crate::ConfigFile::from_toml_str(toml)?;
+        config.push_to_db().await?;
+
+        let acme = Acme {
+            key_type: Some(KeyTypes::One(KeyType::Rsa4096)),
+            ..Default::default()
+        };
+        assert_eq!(
+            acme.certificates_for("legacy.example.com").await?,
+            vec![
+                ("legacy.example.com".to_owned(), KeyType::EcdsaP256),
+                ("legacy.example.com_rsa2048".to_owned(), KeyType::Rsa2048),
+            ]
+        );
+        assert_eq!(
+            acme.certificates_for("other.example.com").await?,
+            vec![("other.example.com".to_owned(), KeyType::Rsa4096)]
+        );
+        Ok(())
+    }
+    #[test]
+    fn default_key_type_is_rsa() -> Result<()> {
+        assert_eq!(KeyType::default(), KeyType::Rsa4096);
+        assert_eq!(Acme::default().key_types(), vec![KeyType::Rsa4096]);
+        Ok(())
+    }
+    #[test]
+    fn decode_eab_key() -> Result<()> {
+        let eab = Eab {
+            kid: "kid-1".to_owned(),
+            hmac_key: "zWNDZM6eQGHWpSRTPal5eIUYFTu7EajVIoguysqZ9wG44nMEtx3MUAsUDkMTQ12W".to_owned(),
+        };
+        eab.key()?;
+        let eab = Eab {
+            hmac_key: "not base64!".to_owned(),
+            ..eab
+        };
+        assert!(eab.key().is_err());
+        Ok(())
+    }
+}
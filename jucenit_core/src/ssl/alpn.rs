@@ -0,0 +1,162 @@
+//!
+//! A minimal tls server answering tls-alpn-01 challenges.
+//!
+//! Nginx-unit can't negotiate the "acme-tls/1" protocol (RFC 8737),
+//! so the tls port is handed over to this responder for the length of a challenge.
+//!
+use openssl::pkey::PKey;
+use openssl::ssl::{select_next_proto, AlpnError, SslAcceptor, SslMethod};
+use openssl::x509::X509;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+// Error Handling
+use crate::error::JucenitError;
+use miette::{IntoDiagnostic, Result};
+
+// Wire format protocol list, a length prefixed "acme-tls/1"
+pub static ACME_TLS_ALPN: &[u8] = b"\x0aacme-tls/1";
+
+#[derive(Debug)]
+pub struct TlsAlpnResponder {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+impl TlsAlpnResponder {
+    /**
+     * Serve the challenge certificate bundle to clients asking for "acme-tls/1".
+     * The socket may still be held by the previous listener for a while,
+     * binding is retried.
+     */
+    pub async fn serve(addr: &str, bundle: &str) -> Result<Self> {
+        let cert = X509::from_pem(bundle.as_bytes()).into_diagnostic()?;
+        let key = PKey::private_key_from_pem(bundle.as_bytes()).into_diagnostic()?;
+        let mut builder =
+            SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).into_diagnostic()?;
+        builder.set_certificate(&cert).into_diagnostic()?;
+        builder.set_private_key(&key).into_diagnostic()?;
+        builder.set_alpn_select_callback(|_, client| {
+            select_next_proto(ACME_TLS_ALPN, client).ok_or(AlpnError::ALERT_FATAL)
+        });
+        let acceptor = Arc::new(builder.build());
+
+        let mut retries = 10;
+        let listener = loop {
+            match TcpListener::bind(addr) {
+                Ok(listener) => break listener,
+                Err(e) if e.kind() == ErrorKind::AddrInUse && retries > 0 => {
+                    retries -= 1;
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                }
+                Err(e) => {
+                    let message = format!(
+                        "Couldn't serve the tls-alpn-01 challenge on {}: {}",
+                        addr, e
+                    );
+                    return Err(JucenitError::Acme(message).into());
+                }
+            }
+        };
+        listener.set_nonblocking(true).into_diagnostic()?;
+        let local = listener.local_addr().into_diagnostic()?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let handle = thread::spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let acceptor = acceptor.clone();
+                        thread::spawn(move || {
+                            let _ = stream.set_nonblocking(false);
+                            let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+                            let _ = stream.set_write_timeout(Some(Duration::from_secs(5)));
+                            // The validator only needs the handshake.
+                            if let Ok(mut stream) = acceptor.accept(stream) {
+                                let _ = stream.shutdown();
+                            }
+                        });
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(50));
+                    }
+                    Err(e) => println!("{:?}", e),
+                }
+            }
+        });
+        Ok(TlsAlpnResponder {
+            addr: local,
+            stop,
+            handle: Some(handle),
+        })
+    }
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+impl Drop for TlsAlpnResponder {
+    /**
+     * Close the socket.
+     */
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssl::letsencrypt::make_jucenit_tls_alpn_challenge_config;
+    use openssl::ssl::{SslConnector, SslVerifyMode};
+    use std::net::TcpStream;
+
+    fn handshake(addr: SocketAddr, protocols: &[u8]) -> Result<(Option<Vec<u8>>, X509)> {
+        let mut builder = SslConnector::builder(SslMethod::tls()).into_diagnostic()?;
+        builder.set_verify(SslVerifyMode::NONE);
+        builder.set_alpn_protos(protocols).into_diagnostic()?;
+        let connector = builder.build();
+        let stream = TcpStream::connect(addr).into_diagnostic()?;
+        let stream = connector
+            .connect("alpn.example.com", stream)
+            .into_diagnostic()?;
+        let protocol = stream.ssl().selected_alpn_protocol().map(|x| x.to_vec());
+        let cert = stream
+            .ssl()
+            .peer_certificate()
+            .ok_or(JucenitError::Acme("No peer certificate".to_owned()))?;
+        Ok((protocol, cert))
+    }
+
+    #[tokio::test]
+    async fn negotiate_acme_tls() -> Result<()> {
+        let bundle =
+            make_jucenit_tls_alpn_challenge_config("alpn.example.com", "token.thumbprint")?;
+        let responder = TlsAlpnResponder::serve("127.0.0.1:0", &bundle).await?;
+        let addr = responder.local_addr();
+
+        let (protocol, cert) = tokio::task::spawn_blocking(move || handshake(addr, ACME_TLS_ALPN))
+            .await
+            .into_diagnostic()??;
+        assert_eq!(protocol, Some(b"acme-tls/1".to_vec()));
+        let text = String::from_utf8(cert.to_text().into_diagnostic()?).into_diagnostic()?;
+        assert!(text.contains("DNS:alpn.example.com"));
+        assert!(text.contains("1.3.6.1.5.5.7.1.31: critical"));
+
+        // Other protocols are refused
+        let res = tokio::task::spawn_blocking(move || handshake(addr, b"\x02h2"))
+            .await
+            .into_diagnostic()?;
+        assert!(res.is_err());
+
+        drop(responder);
+        assert!(TcpStream::connect(addr).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+// Error Handling
+use crate::error::JucenitError;
+use miette::{IntoDiagnostic, Result};
+// Crate structs
+use super::DnsProvider;
+
+/**
+* A user script managing the records with any dns provider api.
+* Called with the action, the record name and its value:
+* `<command> set _acme-challenge.example.com <value>`
+* `<command> del _acme-challenge.example.com <value>`
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct ExecHook {
+    // Executable path (ex: "/etc/jucenit/dns-hook.sh")
+    pub command: String,
+}
+
+impl ExecHook {
+    async fn run(&self, action: &str, name: &str, value: &str) -> Result<()> {
+        let output = Command::new(&self.command)
+            .args([action, name, value])
+            .output()
+            .await
+            .map_err(|e| {
+                let message = format!("Couldn't run dns hook {:?}: {}", self.command, e);
+                JucenitError::Config(message)
+            })?;
+        if !output.status.success() {
+            let message = format!(
+                "Dns hook {:?} failed to {} {:?}: {}",
+                self.command,
+                action,
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            return Err(JucenitError::Acme(message).into());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DnsProvider for ExecHook {
+    async fn set_txt(&self, name: &str, value: &str) -> Result<()> {
+        self.run("set", name, value).await
+    }
+    async fn del_txt(&self, name: &str, value: &str) -> Result<()> {
+        self.run("del", name, value).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[tokio::test]
+    async fn call_hook() -> Result<()> {
+        let dir = std::env::temp_dir().join("jucenit_dns_hook");
+        tokio::fs::create_dir_all(&dir).await.into_diagnostic()?;
+        let script = dir.join("hook.sh");
+        let log = dir.join("hook.log");
+        let content = format!("#!/bin/sh\necho \"$@\" >> {}\n", log.display());
+        tokio::fs::write(&script, content).await.into_diagnostic()?;
+        let permissions = std::fs::Permissions::from_mode(0o755);
+        tokio::fs::set_permissions(&script, permissions)
+            .await
+            .into_diagnostic()?;
+        tokio::fs::remove_file(&log).await.ok();
+
+        let hook = ExecHook {
+            command: script.display().to_string(),
+        };
+        hook.set_txt("_acme-challenge.example.com", "token").await?;
+        hook.del_txt("_acme-challenge.example.com", "token").await?;
+        let log = tokio::fs::read_to_string(&log).await.into_diagnostic()?;
+        assert_eq!(
+            log,
+            "set _acme-challenge.example.com token\ndel _acme-challenge.example.com token\n"
+        );
+
+        let hook = ExecHook {
+            command: "/bin/false".to_owned(),
+        };
+        assert!(hook
+            .set_txt("_acme-challenge.example.com", "token")
+            .await
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+//!
+//! Dns records management for the dns-01 challenge.
+//!
+//! Records are set through a provider, from the configuration
+//! (rfc2136 or exec hook) or set by library users,
+//! and polled on resolvers until propagated.
+//!
+
+mod exec;
+mod rfc2136;
+pub mod wire;
+
+pub use exec::ExecHook;
+pub use rfc2136::Rfc2136;
+pub use wire::TsigAlgorithm;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+// Global vars
+use once_cell::sync::Lazy;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+// Error Handling
+use crate::error::JucenitError;
+use miette::Result;
+
+/**
+* Sets and removes the TXT records proving a domain ownership.
+*/
+// The async_trait expansion trips double_must_use
+#[allow(clippy::double_must_use)]
+#[async_trait]
+pub trait DnsProvider: Send + Sync {
+    async fn set_txt(&self, name: &str, value: &str) -> Result<()>;
+    async fn del_txt(&self, name: &str, value: &str) -> Result<()>;
+    // Servers polled for propagation when no resolver is configured
+    fn servers(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+type SharedDnsProvider = Arc<dyn DnsProvider>;
+// A provider set by library users, preferred over the configured one
+pub static DNS_PROVIDER: Lazy<Arc<Mutex<Option<SharedDnsProvider>>>> =
+    Lazy::new(|| Arc::new(Mutex::new(None)));
+
+/**
+* Replace the configured provider (ex: with a dns hosting api client).
+*/
+pub async fn set_dns_provider(provider: Arc<dyn DnsProvider>) {
+    *DNS_PROVIDER.lock().await = Some(provider);
+}
+
+/**
+* Dns-01 challenge settings.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct DnsSettings {
+    #[serde(flatten)]
+    pub provider: DnsProviderConfig,
+    // Resolvers to poll for the records (default: the provider servers)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub resolvers: Vec<String>,
+    // Seconds to wait for the records to propagate (default: 120)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub propagation_timeout: Option<u64>,
+}
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "provider", rename_all = "lowercase")]
+pub enum DnsProviderConfig {
+    Rfc2136(Rfc2136),
+    Exec(ExecHook),
+}
+
+impl DnsSettings {
+    /**
+     * Returns the provider in use.
+     */
+    pub async fn provider(&self) -> Arc<dyn DnsProvider> {
+        if let Some(provider) = DNS_PROVIDER.lock().await.clone() {
+            return provider;
+        }
+        match &self.provider {
+            DnsProviderConfig::Rfc2136(x) => Arc::new(x.clone()),
+            DnsProviderConfig::Exec(x) => Arc::new(x.clone()),
+        }
+    }
+    /**
+     * Wait for every resolver to serve the record.
+     * Without resolvers (ex: exec hook), the hook is trusted to wait.
+     */
+    pub async fn wait_propagation(
+        &self,
+        provider: &Arc<dyn DnsProvider>,
+        name: &str,
+        value: &str,
+    ) -> Result<()> {
+        let resolvers = match self.resolvers.is_empty() {
+            true => provider.servers(),
+            false => self.resolvers.clone(),
+        };
+        let timeout = Duration::from_secs(self.propagation_timeout.unwrap_or(120));
+        let start = tokio::time::Instant::now();
+        let mut pending = resolvers;
+        loop {
+            let mut next = vec![];
+            for resolver in pending {
+                match resolve_txt(&resolver, name).await {
+                    Ok(records) if records.iter().any(|x| x == value) => {}
+                    _ => next.push(resolver),
+                }
+            }
+            pending = next;
+            if pending.is_empty() {
+                return Ok(());
+            }
+            if start.elapsed() > timeout {
+                let message = format!(
+                    "Txt record {:?} not propagated to {:?} after {}s",
+                    name,
+                    pending,
+                    timeout.as_secs()
+                );
+                return Err(JucenitError::Acme(message).into());
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+}
+
+/**
+* Returns the TXT records of a name as served by a resolver.
+*/
+pub async fn resolve_txt(server: &str, name: &str) -> Result<Vec<String>> {
+    let query = wire::query_txt(wire::message_id(), name)?;
+    let res = wire::exchange(server, &query).await?;
+    wire::txt_answers(&res)
+}
+
+/**
+* Returns the record name to prove a host ownership with,
+* the same for a wildcard and its base domain.
+*/
+pub fn challenge_name(dns: &str) -> String {
+    format!("_acme-challenge.{}", dns.trim_start_matches("*."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_dns_settings() -> Result<()> {
+        let toml = "
+        [acme]
+        challenge = 'dns-01'
+        [acme.dns]
+        provider = 'rfc2136'
+        server = '10.0.0.53'
+        zone = 'example.com'
+        key_name = 'jucenit'
+        key_secret = 'c2VjcmV0'
+        resolvers = ['1.1.1.1', '8.8.8.8']
+        ";
+        let config = crate::ConfigFile::from_toml_str(toml)?;
+        let dns = config.acme.unwrap().dns.unwrap();
+        assert_eq!(dns.resolvers, vec!["1.1.1.1", "8.8.8.8"]);
+        match dns.provider {
+            DnsProviderConfig::Rfc2136(x) => assert_eq!(x.zone, "example.com"),
+            _ => panic!("Expected an rfc2136 provider"),
+        };
+
+        let toml = "
+        [acme.dns]
+        provider = 'exec'
+        command = '/etc/jucenit/dns-hook.sh'
+        ";
+        let config = crate::ConfigFile::from_toml_str(toml)?;
+        let dns = config.acme.unwrap().dns.unwrap();
+        assert_eq!(
+            dns.provider,
+            DnsProviderConfig::Exec(ExecHook {
+                command: "/etc/jucenit/dns-hook.sh".to_owned()
+            })
+        );
+        Ok(())
+    }
+    #[test]
+    fn name_wildcard_challenges() {
+        assert_eq!(
+            challenge_name("*.example.com"),
+            "_acme-challenge.example.com"
+        );
+        assert_eq!(
+            challenge_name("www.example.com"),
+            "_acme-challenge.www.example.com"
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+// Error Handling
+use crate::error::JucenitError;
+use miette::{IntoDiagnostic, Result};
+// Crate structs
+use super::wire::{self, TsigAlgorithm, TsigKey};
+use super::DnsProvider;
+
+/**
+* Dynamic updates (RFC 2136) to an authoritative server,
+* signed with a TSIG key (ex: bind9 "update-policy", knot "acl").
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct Rfc2136 {
+    // Primary server address (ex: "10.0.0.53" or "10.0.0.53:5353")
+    pub server: String,
+    // Zone holding the records (ex: "example.com")
+    pub zone: String,
+    // Tsig key (ex: "jucenit")
+    pub key_name: String,
+    // Base64 encoded
+    pub key_secret: String,
+    // Default: hmac-sha256
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub algorithm: Option<TsigAlgorithm>,
+    // Record ttl in seconds (default: 60)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u32>,
+}
+
+impl Rfc2136 {
+    fn key(&self) -> Result<TsigKey> {
+        let secret = openssl::base64::decode_block(&self.key_secret).map_err(|_| {
+            let message = format!("Invalid tsig secret for key {:?}", self.key_name);
+            JucenitError::Config(message)
+        })?;
+        Ok(TsigKey {
+            name: self.key_name.to_owned(),
+            algorithm: self.algorithm.clone().unwrap_or_default(),
+            secret,
+        })
+    }
+    async fn update(&self, name: &str, value: &str, add: bool) -> Result<()> {
+        let id = wire::message_id();
+        let ttl = self.ttl.unwrap_or(60);
+        let mut msg = wire::update_txt(id, &self.zone, name, value, ttl, add)?;
+        let now = || -> Result<u64> {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .into_diagnostic()?;
+            Ok(now.as_secs())
+        };
+        let key = self.key()?;
+        let mac = wire::sign(&mut msg, &key, now()?)?;
+
+        let res = wire::exchange(&self.server, &msg).await?;
+        match wire::rcode(&res)? {
+            // Don't trust an unauthenticated success.
+            0 => wire::verify(&res, &key, &mac, now()?),
+            code => {
+                let message = format!(
+                    "Dns server {} rejected the update of {:?}: {}",
+                    self.server,
+                    name,
+                    wire::rcode_name(code)
+                );
+                Err(JucenitError::Acme(message).into())
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Rfc2136 {
+    async fn set_txt(&self, name: &str, value: &str) -> Result<()> {
+        self.update(name, value, true).await
+    }
+    async fn del_txt(&self, name: &str, value: &str) -> Result<()> {
+        self.update(name, value, false).await
+    }
+    fn servers(&self) -> Vec<String> {
+        vec![self.server.to_owned()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * Requires a local authoritative server for "example.com"
+     * accepting updates signed with the "jucenit" key.
+     * ex: bind9 with `update-policy { grant jucenit zonesub TXT; };`
+     */
+    #[tokio::test]
+    async fn update_local_server() -> Result<()> {
+        let provider = Rfc2136 {
+            server: "127.0.0.1".to_owned(),
+            zone: "example.com".to_owned(),
+            key_name: "jucenit".to_owned(),
+            key_secret: "c2VjcmV0LXRzaWcta2V5LWZvci1qdWNlbml0LXRlc3Rz".to_owned(),
+            ..Default::default()
+        };
+        let name = "_acme-challenge.example.com";
+        provider.set_txt(name, "jucenit-test").await?;
+        let records = super::super::resolve_txt("127.0.0.1", name).await?;
+        assert!(records.contains(&"jucenit-test".to_owned()));
+
+        provider.del_txt(name, "jucenit-test").await?;
+        let records = super::super::resolve_txt("127.0.0.1", name).await?;
+        assert!(!records.contains(&"jucenit-test".to_owned()));
+        Ok(())
+    }
+}
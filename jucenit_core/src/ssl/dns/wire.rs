@@ -0,0 +1,611 @@
+//!
+//! A minimal DNS message implementation (RFC 1035),
+//! limited to TXT queries and dynamic updates (RFC 2136)
+//! signed with TSIG (RFC 8945).
+//! Messages are exchanged over udp, and over tcp
+//! when a response is truncated.
+//!
+
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+// Error Handling
+use crate::error::JucenitError;
+use miette::{IntoDiagnostic, Result};
+
+const TYPE_TXT: u16 = 16;
+const TYPE_SOA: u16 = 6;
+const TYPE_TSIG: u16 = 250;
+const CLASS_IN: u16 = 1;
+const CLASS_NONE: u16 = 254;
+const CLASS_ANY: u16 = 255;
+// Seconds of clock skew tolerated by the server
+const FUDGE: u16 = 300;
+
+/**
+* Hmac algorithm of a TSIG key.
+*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TsigAlgorithm {
+    HmacSha1,
+    #[default]
+    HmacSha256,
+    HmacSha512,
+}
+impl TsigAlgorithm {
+    pub fn name(&self) -> &str {
+        match self {
+            TsigAlgorithm::HmacSha1 => "hmac-sha1",
+            TsigAlgorithm::HmacSha256 => "hmac-sha256",
+            TsigAlgorithm::HmacSha512 => "hmac-sha512",
+        }
+    }
+    fn digest(&self) -> MessageDigest {
+        match self {
+            TsigAlgorithm::HmacSha1 => MessageDigest::sha1(),
+            TsigAlgorithm::HmacSha256 => MessageDigest::sha256(),
+            TsigAlgorithm::HmacSha512 => MessageDigest::sha512(),
+        }
+    }
+}
+
+/**
+* A shared secret to sign updates with.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TsigKey {
+    pub name: String,
+    pub algorithm: TsigAlgorithm,
+    pub secret: Vec<u8>,
+}
+
+fn push_u16(buf: &mut Vec<u8>, x: u16) {
+    buf.extend_from_slice(&x.to_be_bytes());
+}
+fn push_u32(buf: &mut Vec<u8>, x: u32) {
+    buf.extend_from_slice(&x.to_be_bytes());
+}
+/**
+* Encode a domain name as a sequence of labels, in lowercase
+* as required for TSIG names.
+*/
+fn push_name(buf: &mut Vec<u8>, name: &str) -> Result<()> {
+    for label in name.split('.').filter(|x| !x.is_empty()) {
+        if label.len() > 63 {
+            let message = format!("Dns label too long in {:?}", name);
+            return Err(JucenitError::Config(message).into());
+        }
+        buf.push(label.len() as u8);
+        buf.extend(label.to_lowercase().as_bytes());
+    }
+    buf.push(0);
+    Ok(())
+}
+fn push_txt(buf: &mut Vec<u8>, value: &str) -> Result<()> {
+    if value.len() > 255 {
+        let message = format!("Txt record value too long: {:?}", value);
+        return Err(JucenitError::Config(message).into());
+    }
+    push_u16(buf, value.len() as u16 + 1);
+    buf.push(value.len() as u8);
+    buf.extend(value.as_bytes());
+    Ok(())
+}
+fn header(id: u16, flags: u16, counts: [u16; 4]) -> Vec<u8> {
+    let mut buf = vec![];
+    push_u16(&mut buf, id);
+    push_u16(&mut buf, flags);
+    for count in counts {
+        push_u16(&mut buf, count);
+    }
+    buf
+}
+
+/**
+* A recursive TXT query.
+*/
+pub fn query_txt(id: u16, name: &str) -> Result<Vec<u8>> {
+    let mut buf = header(id, 0x0100, [1, 0, 0, 0]);
+    push_name(&mut buf, name)?;
+    push_u16(&mut buf, TYPE_TXT);
+    push_u16(&mut buf, CLASS_IN);
+    Ok(buf)
+}
+
+/**
+* An update adding (or deleting) a single TXT record in a zone.
+*/
+pub fn update_txt(
+    id: u16,
+    zone: &str,
+    name: &str,
+    value: &str,
+    ttl: u32,
+    add: bool,
+) -> Result<Vec<u8>> {
+    // Opcode UPDATE, one zone and one update record
+    let mut buf = header(id, 5 << 11, [1, 0, 1, 0]);
+    push_name(&mut buf, zone)?;
+    push_u16(&mut buf, TYPE_SOA);
+    push_u16(&mut buf, CLASS_IN);
+
+    push_name(&mut buf, name)?;
+    push_u16(&mut buf, TYPE_TXT);
+    match add {
+        true => {
+            push_u16(&mut buf, CLASS_IN);
+            push_u32(&mut buf, ttl);
+        }
+        // Delete an RR from an RRset
+        false => {
+            push_u16(&mut buf, CLASS_NONE);
+            push_u32(&mut buf, 0);
+        }
+    }
+    push_txt(&mut buf, value)?;
+    Ok(buf)
+}
+
+/**
+* Compute the mac of a message and its TSIG variables.
+* Responses are chained to the request mac.
+*/
+fn digest(
+    key: &TsigKey,
+    request_mac: Option<&[u8]>,
+    msg: &[u8],
+    time: &[u8],
+    fudge: u16,
+    error: u16,
+    other: &[u8],
+) -> Result<Vec<u8>> {
+    let mut variables = vec![];
+    push_name(&mut variables, &key.name)?;
+    push_u16(&mut variables, CLASS_ANY);
+    push_u32(&mut variables, 0);
+    push_name(&mut variables, key.algorithm.name())?;
+    variables.extend(time);
+    push_u16(&mut variables, fudge);
+    push_u16(&mut variables, error);
+    push_u16(&mut variables, other.len() as u16);
+    variables.extend(other);
+
+    let pkey = PKey::hmac(&key.secret).into_diagnostic()?;
+    let mut signer = Signer::new(key.algorithm.digest(), &pkey).into_diagnostic()?;
+    if let Some(request_mac) = request_mac {
+        signer
+            .update(&(request_mac.len() as u16).to_be_bytes())
+            .into_diagnostic()?;
+        signer.update(request_mac).into_diagnostic()?;
+    }
+    signer.update(msg).into_diagnostic()?;
+    signer.update(&variables).into_diagnostic()?;
+    signer.sign_to_vec().into_diagnostic()
+}
+
+/**
+* Append a TSIG record to a message.
+* Returns the mac, to verify the response with.
+*/
+pub fn sign(msg: &mut Vec<u8>, key: &TsigKey, time: u64) -> Result<Vec<u8>> {
+    sign_with(msg, key, time, None)
+}
+fn sign_with(
+    msg: &mut Vec<u8>,
+    key: &TsigKey,
+    time: u64,
+    request_mac: Option<&[u8]>,
+) -> Result<Vec<u8>> {
+    if msg.len() < 12 {
+        return Err(JucenitError::Acme("Dns message too short to sign".to_owned()).into());
+    }
+    let time = &time.to_be_bytes()[2..];
+    let mac = digest(key, request_mac, msg, time, FUDGE, 0, &[])?;
+
+    let mut rdata = vec![];
+    push_name(&mut rdata, key.algorithm.name())?;
+    rdata.extend(time);
+    push_u16(&mut rdata, FUDGE);
+    push_u16(&mut rdata, mac.len() as u16);
+    rdata.extend(&mac);
+    rdata.extend_from_slice(&msg[0..2]);
+    push_u16(&mut rdata, 0);
+    push_u16(&mut rdata, 0);
+
+    push_name(msg, &key.name)?;
+    push_u16(msg, TYPE_TSIG);
+    push_u16(msg, CLASS_ANY);
+    push_u32(msg, 0);
+    push_u16(msg, rdata.len() as u16);
+    msg.extend(rdata);
+
+    let count = u16::from_be_bytes([msg[10], msg[11]]) + 1;
+    msg[10..12].copy_from_slice(&count.to_be_bytes());
+    Ok(mac)
+}
+
+fn read_u16(msg: &[u8], pos: usize) -> Result<u16> {
+    match msg.get(pos..pos + 2) {
+        Some(x) => Ok(u16::from_be_bytes([x[0], x[1]])),
+        None => Err(JucenitError::Acme("Truncated dns message".to_owned()).into()),
+    }
+}
+/**
+* Returns the position after a (possibly compressed) name.
+*/
+fn skip_name(msg: &[u8], mut pos: usize) -> Result<usize> {
+    loop {
+        let len = *msg
+            .get(pos)
+            .ok_or(JucenitError::Acme("Truncated dns message".to_owned()))?;
+        match len {
+            0 => return Ok(pos + 1),
+            x if x & 0xC0 == 0xC0 => return Ok(pos + 2),
+            x => pos += 1 + x as usize,
+        }
+    }
+}
+
+fn truncated() -> JucenitError {
+    JucenitError::Acme("Truncated dns message".to_owned())
+}
+
+/**
+* Verify the TSIG record closing a response to a signed request.
+*/
+pub fn verify(res: &[u8], key: &TsigKey, request_mac: &[u8], now: u64) -> Result<()> {
+    let questions = read_u16(res, 4)?;
+    let records =
+        read_u16(res, 6)? as usize + read_u16(res, 8)? as usize + read_u16(res, 10)? as usize;
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(res, pos)? + 4;
+    }
+    // The TSIG record is the last one
+    let mut last = None;
+    for _ in 0..records {
+        let start = pos;
+        pos = skip_name(res, pos)?;
+        let kind = read_u16(res, pos)?;
+        let len = read_u16(res, pos + 8)? as usize;
+        last = Some((start, kind, pos + 10, len));
+        pos += 10 + len;
+    }
+    let (start, rdata, len) = match last {
+        Some((start, TYPE_TSIG, rdata, len)) if read_u16(res, 10)? > 0 => (start, rdata, len),
+        _ => return Err(JucenitError::Acme("Unsigned dns response".to_owned()).into()),
+    };
+    let rdata = res.get(rdata..rdata + len).ok_or(truncated())?;
+
+    let algorithm_end = skip_name(rdata, 0)?;
+    let time = rdata
+        .get(algorithm_end..algorithm_end + 6)
+        .ok_or(truncated())?;
+    let mut pos = algorithm_end + 6;
+    let fudge = read_u16(rdata, pos)?;
+    let size = read_u16(rdata, pos + 2)? as usize;
+    pos += 4;
+    let mac = rdata.get(pos..pos + size).ok_or(truncated())?;
+    pos += size;
+    let original_id = rdata.get(pos..pos + 2).ok_or(truncated())?;
+    let error = read_u16(rdata, pos + 2)?;
+    let other_len = read_u16(rdata, pos + 4)? as usize;
+    let other = rdata.get(pos + 6..pos + 6 + other_len).ok_or(truncated())?;
+
+    if error != 0 {
+        let name = match error {
+            16 => "BADSIG",
+            17 => "BADKEY",
+            18 => "BADTIME",
+            22 => "BADTRUNC",
+            _ => "UNKNOWN",
+        };
+        let message = format!("Dns server rejected the tsig key {:?}: {}", key.name, name);
+        return Err(JucenitError::Acme(message).into());
+    }
+
+    // The response as it was before being signed
+    let mut unsigned = res[..start].to_vec();
+    unsigned[0..2].copy_from_slice(original_id);
+    let count = read_u16(res, 10)? - 1;
+    unsigned[10..12].copy_from_slice(&count.to_be_bytes());
+    let expected = digest(key, Some(request_mac), &unsigned, time, fudge, error, other)?;
+    if mac.len() != expected.len() || !openssl::memcmp::eq(mac, &expected) {
+        return Err(JucenitError::Acme("Invalid tsig signature on dns response".to_owned()).into());
+    }
+
+    let mut signed_at = [0; 8];
+    signed_at[2..].copy_from_slice(time);
+    if u64::from_be_bytes(signed_at).abs_diff(now) > fudge as u64 {
+        return Err(JucenitError::Acme("Expired tsig signature on dns response".to_owned()).into());
+    }
+    Ok(())
+}
+
+/**
+* Returns the response code of a message (0 is success).
+*/
+pub fn rcode(msg: &[u8]) -> Result<u16> {
+    Ok(read_u16(msg, 2)? & 0x000F)
+}
+pub fn rcode_name(rcode: u16) -> &'static str {
+    match rcode {
+        0 => "NOERROR",
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        8 => "NXRRSET",
+        9 => "NOTAUTH",
+        10 => "NOTZONE",
+        _ => "UNKNOWN",
+    }
+}
+
+/**
+* Returns the TXT records of a response answer section.
+*/
+pub fn txt_answers(msg: &[u8]) -> Result<Vec<String>> {
+    let questions = read_u16(msg, 4)?;
+    let answers = read_u16(msg, 6)?;
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(msg, pos)? + 4;
+    }
+    let mut res = vec![];
+    for _ in 0..answers {
+        pos = skip_name(msg, pos)?;
+        let kind = read_u16(msg, pos)?;
+        let len = read_u16(msg, pos + 8)? as usize;
+        let start = pos + 10;
+        let rdata = msg
+            .get(start..start + len)
+            .ok_or(JucenitError::Acme("Truncated dns message".to_owned()))?;
+        if kind == TYPE_TXT {
+            // Concatenate the character strings
+            let mut value = vec![];
+            let mut i = 0;
+            while i < rdata.len() {
+                let size = rdata[i] as usize;
+                value.extend(rdata.get(i + 1..i + 1 + size).unwrap_or_default());
+                i += 1 + size;
+            }
+            res.push(String::from_utf8_lossy(&value).to_string());
+        }
+        pos = start + len;
+    }
+    Ok(res)
+}
+
+/**
+* Parse a server address, on port 53 by default (ex: "127.0.0.1", "[::1]:5353").
+*/
+pub fn server_addr(server: &str) -> Result<SocketAddr> {
+    if let Ok(addr) = server.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    match server.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(ip) => Ok(SocketAddr::new(ip, 53)),
+        Err(_) => {
+            let message = format!("Invalid dns server address {:?}", server);
+            Err(JucenitError::Config(message).into())
+        }
+    }
+}
+
+/**
+* Send a message and wait for its response,
+* retried over tcp if the response doesn't fit a datagram.
+*/
+pub async fn exchange(server: &str, msg: &[u8]) -> Result<Vec<u8>> {
+    let res = exchange_udp(server, msg).await?;
+    if read_u16(&res, 2)? & 0x0200 != 0 {
+        return exchange_tcp(server, msg).await;
+    }
+    Ok(res)
+}
+async fn exchange_udp(server: &str, msg: &[u8]) -> Result<Vec<u8>> {
+    let addr = server_addr(server)?;
+    let local = match addr.is_ipv6() {
+        true => "[::]:0",
+        false => "0.0.0.0:0",
+    };
+    let socket = UdpSocket::bind(local).await.into_diagnostic()?;
+    socket.connect(addr).await.into_diagnostic()?;
+    socket.send(msg).await.into_diagnostic()?;
+
+    let mut buf = vec![0; 4096];
+    loop {
+        let len = tokio::time::timeout(Duration::from_secs(5), socket.recv(&mut buf))
+            .await
+            .map_err(|_| JucenitError::Acme(format!("Dns server {} timed out", server)))?
+            .into_diagnostic()?;
+        let res = &buf[..len];
+        // Ignore stray datagrams
+        if res.len() < 12 || res[0..2] != msg[0..2] {
+            continue;
+        }
+        return Ok(res.to_vec());
+    }
+}
+/**
+* Messages are prefixed with their length over tcp.
+*/
+async fn exchange_tcp(server: &str, msg: &[u8]) -> Result<Vec<u8>> {
+    let addr = server_addr(server)?;
+    let timeout = || JucenitError::Acme(format!("Dns server {} timed out", server));
+    let res: Result<Vec<u8>> = tokio::time::timeout(Duration::from_secs(5), async {
+        let mut stream = TcpStream::connect(addr).await.into_diagnostic()?;
+        let mut buf = (msg.len() as u16).to_be_bytes().to_vec();
+        buf.extend(msg);
+        stream.write_all(&buf).await.into_diagnostic()?;
+        let len = stream.read_u16().await.into_diagnostic()?;
+        let mut res = vec![0; len as usize];
+        stream.read_exact(&mut res).await.into_diagnostic()?;
+        Ok(res)
+    })
+    .await
+    .map_err(|_| timeout())?;
+    let res = res?;
+    if res.len() < 12 || res[0..2] != msg[0..2] {
+        let message = format!("Unexpected dns response from {}", server);
+        return Err(JucenitError::Acme(message).into());
+    }
+    Ok(res)
+}
+
+/**
+* Returns a random message id.
+*/
+pub fn message_id() -> u16 {
+    let mut buf = [0; 2];
+    openssl::rand::rand_bytes(&mut buf).ok();
+    u16::from_be_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * Build a response to a query with the given TXT values.
+     */
+    fn respond(query: &[u8], values: &[&str]) -> Vec<u8> {
+        let mut res = query.to_vec();
+        res[2..4].copy_from_slice(&0x8180_u16.to_be_bytes());
+        res[6..8].copy_from_slice(&(values.len() as u16).to_be_bytes());
+        for value in values {
+            // Pointer to the question name
+            res.extend([0xC0, 12]);
+            push_u16(&mut res, TYPE_TXT);
+            push_u16(&mut res, CLASS_IN);
+            push_u32(&mut res, 60);
+            push_txt(&mut res, value).unwrap();
+        }
+        res
+    }
+
+    #[test]
+    fn read_txt_answers() -> Result<()> {
+        let query = query_txt(42, "_acme-challenge.example.com")?;
+        assert_eq!(&query[12..28], b"\x0f_acme-challenge");
+        let res = respond(&query, &["token-1", "token-2"]);
+        assert_eq!(rcode(&res)?, 0);
+        assert_eq!(txt_answers(&res)?, vec!["token-1", "token-2"]);
+        Ok(())
+    }
+    #[test]
+    fn sign_update() -> Result<()> {
+        let key = TsigKey {
+            name: "jucenit".to_owned(),
+            algorithm: TsigAlgorithm::HmacSha256,
+            secret: b"secret".to_vec(),
+        };
+        let unsigned = update_txt(
+            7,
+            "example.com",
+            "_acme-challenge.example.com",
+            "x",
+            60,
+            true,
+        )?;
+        let mut msg = unsigned.clone();
+        let mac = sign(&mut msg, &key, 1_700_000_000)?;
+        assert_eq!(mac.len(), 32);
+        // One additional record
+        assert_eq!(read_u16(&msg, 10)?, 1);
+        assert_eq!(&msg[12..unsigned.len()], &unsigned[12..]);
+        // Mac size then original id, error and other length
+        let end = &msg[msg.len() - 40..];
+        assert_eq!(&end[0..2], &32_u16.to_be_bytes());
+        assert_eq!(&end[34..], &[0, 7, 0, 0, 0, 0]);
+        Ok(())
+    }
+    #[test]
+    fn verify_signed_response() -> Result<()> {
+        let key = TsigKey {
+            name: "jucenit".to_owned(),
+            algorithm: TsigAlgorithm::HmacSha256,
+            secret: b"secret".to_vec(),
+        };
+        let time = 1_700_000_000;
+        let mut msg = update_txt(
+            7,
+            "example.com",
+            "_acme-challenge.example.com",
+            "x",
+            60,
+            true,
+        )?;
+        let mac = sign(&mut msg, &key, time)?;
+
+        // Response: the zone section only, with the response flag
+        let mut res = header(7, 0xA800, [1, 0, 0, 0]);
+        push_name(&mut res, "example.com")?;
+        push_u16(&mut res, TYPE_SOA);
+        push_u16(&mut res, CLASS_IN);
+        let unsigned = res.clone();
+        sign_with(&mut res, &key, time + 1, Some(&mac))?;
+        verify(&res, &key, &mac, time + 2)?;
+
+        // Chained to another request
+        assert!(verify(&res, &key, &[0; 32], time + 2).is_err());
+        // Tampered with
+        let mut tampered = res.clone();
+        tampered[3] |= 0x05;
+        assert!(verify(&tampered, &key, &mac, time + 2).is_err());
+        // Out of the time window
+        assert!(verify(&res, &key, &mac, time + 1000).is_err());
+        // Not signed
+        assert!(verify(&unsigned, &key, &mac, time + 2).is_err());
+        Ok(())
+    }
+    #[tokio::test]
+    async fn retry_truncated_over_tcp() -> Result<()> {
+        let udp = UdpSocket::bind("127.0.0.1:0").await.into_diagnostic()?;
+        let addr = udp.local_addr().into_diagnostic()?;
+        let tcp = tokio::net::TcpListener::bind(addr)
+            .await
+            .into_diagnostic()?;
+        tokio::spawn(async move {
+            let mut buf = vec![0; 512];
+            let (len, peer) = udp.recv_from(&mut buf).await.unwrap();
+            // Header only, with the truncation flag
+            let mut res = buf[..12].to_vec();
+            res[2..4].copy_from_slice(&0x8380_u16.to_be_bytes());
+            res[4..12].copy_from_slice(&[0; 8]);
+            udp.send_to(&res, peer).await.unwrap();
+            let query = buf[..len].to_vec();
+
+            let (mut stream, _) = tcp.accept().await.unwrap();
+            let len = stream.read_u16().await.unwrap();
+            let mut buf = vec![0; len as usize];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, query);
+            let res = respond(&query, &["over-tcp"]);
+            let mut framed = (res.len() as u16).to_be_bytes().to_vec();
+            framed.extend(res);
+            stream.write_all(&framed).await.unwrap();
+        });
+
+        let query = query_txt(42, "_acme-challenge.example.com")?;
+        let res = exchange(&addr.to_string(), &query).await?;
+        assert_eq!(txt_answers(&res)?, vec!["over-tcp"]);
+        Ok(())
+    }
+    #[test]
+    fn parse_server_addresses() -> Result<()> {
+        assert_eq!(server_addr("127.0.0.1")?.port(), 53);
+        assert_eq!(server_addr("[::1]:5353")?.port(), 5353);
+        assert_eq!(server_addr("::1")?.port(), 53);
+        assert!(server_addr("ns.example.com").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+//!
+//! Group hosts into multi-domain (SAN) certificates.
+//!
+//! A certificate covers the hosts of a unit (grouping = "unit")
+//! or of every unit sharing a `certificate_group`,
+//! and is named after that group or its first host.
+//! The host to certificate mapping is stored in database
+//! when the certificate store is hydrated.
+//!
+
+use super::acme::{agree, Acme, Grouping, UnitAcme};
+use indexmap::IndexMap;
+use std::collections::HashMap;
+// Database
+use crate::database::entity::{prelude::*, *};
+use crate::database::{connect_db, parse_column};
+use sea_orm::{prelude::*, ActiveValue};
+// Error Handling
+use crate::error::JucenitError;
+use miette::{IntoDiagnostic, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Explicit(String),
+    // Lowest match id of the host units
+    Unit(i32),
+    Host(String),
+}
+
+/**
+* Returns the certificates to issue, by name, with the hosts they cover.
+*/
+pub async fn certificate_groups() -> Result<IndexMap<String, Vec<String>>> {
+    let acme = Acme::pull().await?;
+    let db = connect_db().await?;
+    let hosts = Host::find()
+        .find_with_related(NgMatch)
+        .all(&db)
+        .await
+        .map_err(JucenitError::from)?;
+
+    let mut groups: HashMap<Key, Vec<String>> = HashMap::new();
+    for (host, matches) in hosts {
+        let mut explicit = vec![];
+        for match_ in &matches {
+            if let Some(acme) = &match_.acme {
+                let acme: UnitAcme =
+                    parse_column(&format!("acme of unit {:?}", match_.uuid), acme)?;
+                explicit.extend(acme.certificate_group);
+            }
+        }
+        let explicit = agree(&host.domain, "certificate groups", explicit.into_iter())?;
+        let key = match (explicit, acme.grouping()) {
+            (Some(name), _) => Key::Explicit(name),
+            (None, Grouping::Unit) if !matches.is_empty() => {
+                Key::Unit(matches.iter().map(|x| x.id).min().unwrap_or_default())
+            }
+            _ => Key::Host(host.domain.clone()),
+        };
+        groups.entry(key).or_default().push(host.domain);
+    }
+
+    let mut res: IndexMap<String, Vec<String>> = IndexMap::new();
+    for (key, mut hosts) in groups {
+        hosts.sort();
+        let name = match key {
+            Key::Explicit(name) => name,
+            _ => hosts[0].clone(),
+        };
+        // Ex: a certificate_group named after a host of another group
+        if let Some(other) = res.get(&name) {
+            let message = format!(
+                "Certificate group {:?} of {:?} collides with the certificate of {:?}",
+                name, hosts, other
+            );
+            return Err(JucenitError::Config(message).into());
+        }
+        res.insert(name, hosts);
+    }
+    res.sort_keys();
+    Ok(res)
+}
+
+/**
+* Store the certificate name of every grouped host.
+*/
+pub async fn set_certificate_groups(groups: &IndexMap<String, Vec<String>>) -> Result<()> {
+    let db = connect_db().await?;
+    for (name, hosts) in groups {
+        for host in hosts {
+            let group = (host != name).then(|| name.to_owned());
+            Host::update_many()
+                .col_expr(host::Column::CertificateGroup, Expr::value(group))
+                .filter(host::Column::Domain.eq(host))
+                .exec(&db)
+                .await
+                .map_err(JucenitError::from)?;
+        }
+    }
+    Ok(())
+}
+
+/**
+* Returns the hosts covered by a certificate.
+*/
+pub async fn group_hosts(name: &str) -> Result<Vec<String>> {
+    let db = connect_db().await?;
+    let mut hosts: Vec<String> = Host::find()
+        .filter(host::Column::CertificateGroup.eq(name))
+        .all(&db)
+        .await
+        .map_err(JucenitError::from)?
+        .into_iter()
+        .map(|x| x.domain)
+        .collect();
+    // A group is named after its first host.
+    let named = Host::find()
+        .filter(host::Column::Domain.eq(name))
+        .filter(host::Column::CertificateGroup.is_null())
+        .one(&db)
+        .await
+        .map_err(JucenitError::from)?;
+    if named.is_some() || hosts.is_empty() {
+        hosts.push(name.to_owned());
+    }
+    hosts.sort();
+    Ok(hosts)
+}
+
+/**
+* Returns the name of the certificate covering a host.
+*/
+pub async fn certificate_name(dns: &str) -> Result<String> {
+    let db = connect_db().await?;
+    let host = Host::find()
+        .filter(host::Column::Domain.eq(dns))
+        .one(&db)
+        .await
+        .map_err(JucenitError::from)?;
+    Ok(host
+        .and_then(|x| x.certificate_group)
+        .unwrap_or(dns.to_owned()))
+}
+
+/**
+* Returns the names of the certificates covering the declared hosts.
+*/
+pub async fn certificate_names() -> Result<Vec<String>> {
+    let db = connect_db().await?;
+    let mut names: Vec<String> = Host::find()
+        .all(&db)
+        .await
+        .map_err(JucenitError::from)?
+        .into_iter()
+        .map(|x| x.certificate_group.unwrap_or(x.domain))
+        .collect();
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ConfigFile;
+    use serial_test::serial;
+
+    #[tokio::test]
+    #[serial]
+    async fn group_unit_hosts() -> Result<()> {
+        let toml = "
+        [acme]
+        grouping = 'unit'
+
+        [[unit]]
+        uuid = '1b7c6f0e-4f4a-4a3b-9d2e-7c1a5e8b9f01'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['www.alias.com', 'alias.com', 'alias.net']
+        [unit.action]
+        proxy = 'http://127.0.0.1:8001'
+
+        [[unit]]
+        uuid = '1b7c6f0e-4f4a-4a3b-9d2e-7c1a5e8b9f02'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['shop.grouped.com']
+        [unit.action]
+        proxy = 'http://127.0.0.1:8002'
+        [unit.acme]
+        certificate_group = 'grouped'
+
+        [[unit]]
+        uuid = '1b7c6f0e-4f4a-4a3b-9d2e-7c1a5e8b9f03'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['blog.grouped.com']
+        [unit.action]
+        proxy = 'http://127.0.0.1:8003'
+        [unit.acme]
+        certificate_group = 'grouped'
+        ";
+        let config = ConfigFile::from_toml_str(toml)?;
+        config.set().await?;
+
+        let groups = certificate_groups().await?;
+        assert_eq!(
+            groups["alias.com"],
+            vec!["alias.com", "alias.net", "www.alias.com"]
+        );
+        assert_eq!(
+            groups["grouped"],
+            vec!["blog.grouped.com", "shop.grouped.com"]
+        );
+
+        set_certificate_groups(&groups).await?;
+        assert_eq!(certificate_name("www.alias.com").await?, "alias.com");
+        assert_eq!(certificate_name("alias.com").await?, "alias.com");
+        assert_eq!(
+            group_hosts("alias.com").await?,
+            vec!["alias.com", "alias.net", "www.alias.com"]
+        );
+        assert_eq!(
+            group_hosts("grouped").await?,
+            vec!["blog.grouped.com", "shop.grouped.com"]
+        );
+        let names = certificate_names().await?;
+        assert!(names.contains(&"grouped".to_owned()));
+        assert!(!names.contains(&"www.alias.com".to_owned()));
+        Ok(())
+    }
+    #[tokio::test]
+    #[serial]
+    async fn reject_colliding_groups() -> Result<()> {
+        let toml = "
+        [[unit]]
+        uuid = '1b7c6f0e-4f4a-4a3b-9d2e-7c1a5e8b9f04'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['collide.com']
+        [unit.action]
+        proxy = 'http://127.0.0.1:8001'
+
+        [[unit]]
+        uuid = '1b7c6f0e-4f4a-4a3b-9d2e-7c1a5e8b9f05'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['shop.collide.com']
+        [unit.action]
+        proxy = 'http://127.0.0.1:8002'
+        [unit.acme]
+        certificate_group = 'collide.com'
+        ";
+        let config = ConfigFile::from_toml_str(toml)?;
+        config.set().await?;
+
+        let err = certificate_groups().await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<JucenitError>(),
+            Some(JucenitError::Config(x)) if x.contains("collides")
+        ));
+        Ok(())
+    }
+}
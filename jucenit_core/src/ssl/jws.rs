@@ -0,0 +1,171 @@
+//!
+//! Account requests left out by the acme2 client,
+//! signed by hand as flattened JWS (RFC 8555 section 6.2).
+//!
+
+use super::acme::AcmeDirectory;
+use super::renewal::base64_url;
+use acme2::{Account, Directory};
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::{hash, MessageDigest};
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey, Private};
+use openssl::sign::Signer;
+use serde_json::{json, Value};
+// Error Handling
+use crate::error::JucenitError;
+use miette::{IntoDiagnostic, Result};
+
+/**
+* Returns the jws algorithm of an account key.
+*/
+fn algorithm(key: &PKey<Private>) -> Result<&'static str> {
+    let alg = match key.id() {
+        Id::RSA => Some("RS256"),
+        Id::EC => match key.ec_key().into_diagnostic()?.group().curve_name() {
+            Some(Nid::X9_62_PRIME256V1) => Some("ES256"),
+            Some(Nid::SECP384R1) => Some("ES384"),
+            _ => None,
+        },
+        _ => None,
+    };
+    alg.ok_or(JucenitError::Acme("Unsupported account key type".to_owned()).into())
+}
+
+/**
+* Sign a protected header and payload with the account key.
+*/
+fn sign(key: &PKey<Private>, input: &[u8]) -> Result<Vec<u8>> {
+    let (digest, size) = match algorithm(key)? {
+        "RS256" => {
+            let mut signer = Signer::new(MessageDigest::sha256(), key).into_diagnostic()?;
+            signer.update(input).into_diagnostic()?;
+            return signer.sign_to_vec().into_diagnostic();
+        }
+        "ES256" => (MessageDigest::sha256(), 32),
+        _ => (MessageDigest::sha384(), 48),
+    };
+    let digest = hash(digest, input).into_diagnostic()?;
+    let ec = key.ec_key().into_diagnostic()?;
+    let sig = EcdsaSig::sign(&digest, &ec).into_diagnostic()?;
+    // Raw r || s instead of der
+    let mut res = sig.r().to_vec_padded(size).into_diagnostic()?;
+    res.extend(sig.s().to_vec_padded(size).into_diagnostic()?);
+    Ok(res)
+}
+
+/**
+* Returns a jws body for a request to an account url.
+*/
+fn body(key: &PKey<Private>, kid: &str, nonce: &str, payload: &Value) -> Result<Value> {
+    let protected = json!({ "alg": algorithm(key)?, "kid": kid, "nonce": nonce, "url": kid });
+    let protected = base64_url(protected.to_string().as_bytes());
+    let payload = base64_url(payload.to_string().as_bytes());
+    let signature = sign(key, format!("{}.{}", protected, payload).as_bytes())?;
+    Ok(json!({
+        "protected": protected,
+        "payload": payload,
+        "signature": base64_url(&signature),
+    }))
+}
+
+/**
+* Replace the contacts of an existing account (RFC 8555 section 7.3.2).
+*/
+pub async fn update_contacts(
+    directory: &AcmeDirectory,
+    dir: &Directory,
+    account: &Account,
+    contacts: &[String],
+) -> Result<()> {
+    let client = directory.http_client().await?;
+    let res = client
+        .head(&dir.new_nonce)
+        .send()
+        .await
+        .map_err(|e| JucenitError::Acme(format!("Couldn't get a nonce: {}", e)))?;
+    let nonce = res
+        .headers()
+        .get("replay-nonce")
+        .and_then(|x| x.to_str().ok())
+        .ok_or(JucenitError::Acme(
+            "The directory returned no nonce".to_owned(),
+        ))?
+        .to_owned();
+
+    let payload = json!({ "contact": contacts });
+    let body = body(&account.private_key(), &account.id, &nonce, &payload)?;
+    let res = client
+        .post(&account.id)
+        .header("Content-Type", "application/jose+json")
+        .body(body.to_string())
+        .send()
+        .await
+        .map_err(|e| JucenitError::Acme(format!("Couldn't update the account: {}", e)))?;
+    if !res.status().is_success() {
+        let status = res.status();
+        let text = res.text().await.unwrap_or_default();
+        let message = format!("Couldn't update the account contacts: {} {}", status, text);
+        return Err(JucenitError::Acme(message).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::rsa::Rsa;
+    use openssl::sign::Verifier;
+
+    fn decode(x: &str) -> Vec<u8> {
+        let mut x = x.replace('-', "+").replace('_', "/");
+        while !x.len().is_multiple_of(4) {
+            x.push('=');
+        }
+        openssl::base64::decode_block(&x).unwrap()
+    }
+
+    #[test]
+    fn sign_account_requests() -> Result<()> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).into_diagnostic()?;
+        let ec = PKey::from_ec_key(EcKey::generate(&group).into_diagnostic()?).into_diagnostic()?;
+        let rsa = PKey::from_rsa(Rsa::generate(2048).into_diagnostic()?).into_diagnostic()?;
+        let payload = json!({ "contact": ["mailto:admin@example.com"] });
+        let kid = "https://acme.example.com/acct/1";
+
+        for (key, alg) in [(ec, "ES256"), (rsa, "RS256")] {
+            let body = body(&key, kid, "nonce", &payload)?;
+            let protected: Value =
+                serde_json::from_slice(&decode(body["protected"].as_str().unwrap()))
+                    .into_diagnostic()?;
+            assert_eq!(protected["alg"], alg);
+            assert_eq!(protected["url"], kid);
+            let input = format!(
+                "{}.{}",
+                body["protected"].as_str().unwrap(),
+                body["payload"].as_str().unwrap()
+            );
+            let signature = decode(body["signature"].as_str().unwrap());
+            let valid = match alg {
+                "ES256" => {
+                    let r = openssl::bn::BigNum::from_slice(&signature[..32]).into_diagnostic()?;
+                    let s = openssl::bn::BigNum::from_slice(&signature[32..]).into_diagnostic()?;
+                    let sig = EcdsaSig::from_private_components(r, s).into_diagnostic()?;
+                    let digest =
+                        hash(MessageDigest::sha256(), input.as_bytes()).into_diagnostic()?;
+                    let ec = key.ec_key().into_diagnostic()?;
+                    sig.verify(&digest, &ec).into_diagnostic()?
+                }
+                _ => {
+                    let mut verifier =
+                        Verifier::new(MessageDigest::sha256(), &key).into_diagnostic()?;
+                    verifier.update(input.as_bytes()).into_diagnostic()?;
+                    verifier.verify(&signature).into_diagnostic()?
+                }
+            };
+            assert!(valid);
+        }
+        Ok(())
+    }
+}
@@ -1,18 +1,18 @@
-use acme2::{gen_rsa_private_key, Authorization};
+use acme2::Authorization;
 use acme2::{
     Account, AccountBuilder, AuthorizationStatus, Challenge, ChallengeStatus, Csr, Directory,
     DirectoryBuilder, OrderBuilder, OrderStatus,
 };
-use serde_json::json;
 use std::time::Duration;
 // Error Handling
-use miette::{ensure, Context, Error, IntoDiagnostic, Result};
+use crate::error::JucenitError;
+use miette::{Context, Error, IntoDiagnostic, Result};
 // use acme2::Error;
 // Global vars
 use crate::nginx::SETTINGS;
 use once_cell::sync::Lazy;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, OwnedMutexGuard};
 
 // File manipulation
 use tokio::fs;
@@ -22,127 +22,268 @@ use std::path::{Path, PathBuf};
 use toml::toml;
 use uuid::Uuid;
 // Crate structs
+use super::acme::{Acme, AcmeDirectory, ChallengeType, KeyType, TLS_ALPN_TIMEOUT};
+use super::alpn::TlsAlpnResponder;
+use super::dns::challenge_name;
+use super::jws::update_contacts;
 use super::pebble::*;
-use crate::{Action, ConfigUnit, Match};
+use crate::nginx::client::client;
+use crate::nginx::config::{Operation, CONFIG_LOCK, HANDED_OVER};
+use crate::{Action, ConfigUnit, Match, NginxConfig};
 use openssl::{pkey::PKey, x509::X509};
+use rcgen::{CertificateParams, CustomExtension, KeyPair};
+use serde_json::Value;
+// Database
+use crate::database::entity::{acme_account, prelude::AcmeAccount};
+use crate::database::{connect_db, parse_column};
+use sea_orm::{prelude::*, sea_query::OnConflict, ActiveValue};
 
-// Production url
-pub static LETS_ENCRYPT_URL: Lazy<Arc<Mutex<String>>> = Lazy::new(|| {
-    Arc::new(Mutex::new(
-        "https://acme-v02.api.letsencrypt.org/directory".to_owned(),
-    ))
-});
-// Stagging url
-// pub static LETS_ENCRYPT_URL: Lazy<Arc<Mutex<String>>> = Lazy::new(|| {
-//     Arc::new(Mutex::new(
-//         "https://acme-staging-v02.api.letsencrypt.org/directory".to_owned(),
-//     ))
-// });
 static HTTP_PORT: i32 = 80;
 static TLS_PORT: i32 = 443;
 
+// Account key of the releases storing a single account
+static LEGACY_ACCOUNT_KEY: &str = "/var/spool/jucenit/ssl_account_private_key.pem";
+
 /**
-* Create a new ACMEv2 directory for Let's Encrypt.
-*/
-async fn letsencrypt_directory() -> Result<Arc<Directory>> {
-    let dir = DirectoryBuilder::new(LETS_ENCRYPT_URL.lock().await.clone())
-        .build()
-        .await
-        .into_diagnostic()?;
-    Ok(dir)
-}
-/**
-* Create an ACME account to use for the order. For production
-* purposes, you should keep the account (and private key), so
-* you can renew your certificate easily.
+* Create or retrieve the ACME account of a directory.
+* Every directory has its own account key,
+* the account contacts are updated when the configured ones change.
 */
-pub async fn set_account() -> Result<Arc<Account>> {
-    // Set a Private key path
-    let file_path = "/var/spool/jucenit/ssl_account_private_key.pem".to_owned();
-    let path = Path::new(&file_path);
-
-    #[cfg(debug_assertions)]
-    let dir = pebble_directory().await?;
-    #[cfg(not(debug_assertions))]
-    let dir = letsencrypt_directory().await?;
+pub async fn set_account(directory: &AcmeDirectory) -> Result<Arc<Account>> {
+    let settings = Acme::pull().await?;
+    let (contacts, eab) = settings.account_for(directory).await?;
+    let url = directory.url().await;
+    let dir = directory.build().await?;
 
     // Retrieve previous account
-    if path.exists() {
-        let message = format!("Couldn't open file at: {:?}", file_path);
-        let data = fs::read(file_path.clone()).await.into_diagnostic()?;
-        let pkey = PKey::private_key_from_pem(&data).into_diagnostic()?;
-
+    let db = connect_db().await?;
+    let stored = AcmeAccount::find()
+        .filter(acme_account::Column::Directory.eq(&url))
+        .one(&db)
+        .await
+        .map_err(JucenitError::from)?;
+    let (stored, legacy) = match stored {
+        Some(stored) => {
+            let stored_contacts: Vec<String> =
+                parse_column(&format!("contacts of account {:?}", url), &stored.contacts)?;
+            (Some((stored.private_key, stored_contacts)), false)
+        }
+        None => (legacy_account_key(&db).await?.map(|x| (x, vec![])), true),
+    };
+    if let Some((private_key, stored_contacts)) = stored {
+        let pkey = PKey::private_key_from_pem(private_key.as_bytes()).into_diagnostic()?;
         let mut builder = AccountBuilder::new(dir.clone());
         let account = builder
             .private_key(pkey)
-            .contact(vec!["mailto:areskul@areskul.com".to_string()])
+            .contact(contacts.clone())
             .terms_of_service_agreed(true)
             .only_return_existing(true)
             .build()
-            .await
-            .into_diagnostic()?;
-
-        Ok(account)
+            .await;
+        match account {
+            Ok(account) => {
+                if legacy || stored_contacts != contacts {
+                    update_contacts(directory, &dir, &account, &contacts).await?;
+                }
+                store_account(&db, &url, &private_key, &contacts).await?;
+                if legacy {
+                    println!("acme: imported the account key at {:?}", LEGACY_ACCOUNT_KEY);
+                }
+                return Ok(account);
+            }
+            // The legacy key belongs to another directory.
+            Err(_) if legacy => {}
+            Err(e) => return Err(JucenitError::Acme(e.to_string()).into()),
+        }
+    }
 
     // Create new account
-    } else {
-        let mut builder = AccountBuilder::new(dir.clone());
-        let account = builder
-            .contact(vec!["mailto:areskul@areskul.com".to_string()])
-            .terms_of_service_agreed(true)
-            .build()
-            .await
-            .into_diagnostic()?;
-
-        let pkey = account.private_key();
-        let private_key = pkey.private_key_to_pem_pkcs8().into_diagnostic()?;
-
-        //  Write private key to file
-        let tmp_dir = "/var/spool/jucenit";
-        let message = format!("Couldn't create dir: {:?}", tmp_dir);
-        fs::create_dir_all(tmp_dir)
-            .await
-            .into_diagnostic()
-            .wrap_err(message)?;
+    let mut builder = AccountBuilder::new(dir.clone());
+    builder
+        .contact(contacts.clone())
+        .terms_of_service_agreed(true);
+    match &eab {
+        Some(eab) => {
+            builder.external_account_binding(eab.kid.clone(), eab.key()?);
+        }
+        None => {
+            let required = dir
+                .meta
+                .as_ref()
+                .and_then(|x| x.external_account_required)
+                .unwrap_or_default();
+            if required {
+                let message = format!(
+                    "{:?} requires an external account binding, set [acme.eab] (or [unit.acme.eab]) kid and hmac_key",
+                    url
+                );
+                return Err(JucenitError::Acme(message).into());
+            }
+        }
+    };
+    let account = builder.build().await.map_err(JucenitError::from)?;
 
-        let message = format!("Couldn't create file at: {:?}", file_path);
-        let mut file = fs::File::create(file_path.clone())
-            .await
-            .into_diagnostic()
-            .wrap_err(message)?;
-        file.write_all(&private_key).await.into_diagnostic()?;
+    let private_key = account
+        .private_key()
+        .private_key_to_pem_pkcs8()
+        .into_diagnostic()?;
+    let private_key = String::from_utf8(private_key).into_diagnostic()?;
+    store_account(&db, &url, &private_key, &contacts).await?;
+    Ok(account)
+}
 
-        Ok(account)
+/**
+* Returns the account key of a previous release,
+* to import when no account is stored yet.
+*/
+async fn legacy_account_key(db: &DatabaseConnection) -> Result<Option<String>> {
+    let accounts = AcmeAccount::find()
+        .count(db)
+        .await
+        .map_err(JucenitError::from)?;
+    if accounts > 0 {
+        return Ok(None);
     }
+    Ok(fs::read_to_string(LEGACY_ACCOUNT_KEY).await.ok())
+}
+
+async fn store_account(
+    db: &DatabaseConnection,
+    url: &str,
+    private_key: &str,
+    contacts: &[String],
+) -> Result<()> {
+    let model = acme_account::ActiveModel {
+        directory: ActiveValue::Set(url.to_owned()),
+        private_key: ActiveValue::Set(private_key.to_owned()),
+        contacts: ActiveValue::Set(serde_json::to_string(contacts).into_diagnostic()?),
+        ..Default::default()
+    };
+    AcmeAccount::insert(model)
+        .on_conflict(
+            OnConflict::column(acme_account::Column::Directory)
+                .update_columns([
+                    acme_account::Column::PrivateKey,
+                    acme_account::Column::Contacts,
+                ])
+                .to_owned(),
+        )
+        .exec(db)
+        .await
+        .map_err(JucenitError::from)?;
+    Ok(())
 }
 
 /**
-* Create a self-signed certificate to serve domain and resolve challenge.
+* Create the self-signed certificate answering a tls-alpn-01 challenge:
+* the domain as only name, and the key authorization digest
+* in the critical acmeIdentifier extension (RFC 8737).
 */
-fn make_jucenit_tls_alpn_challenge_config(dns: &str, challenge: &Challenge) -> Result<String> {
-    // Challenge ports
-    let toml = format!(
-        "
-        uuid = '{}'
-        listeners = ['*:{}']
-        [match]
-        hosts = ['{}']
-        uri = '/.well-known/acme-challenge/{}'
-        [action]
-        share = ['/tmp/jucenit/challenge_{}.txt']
-        ",
-        Uuid::new_v4(),
-        TLS_PORT,
-        dns,
-        challenge.token.clone().unwrap(),
-        dns
-    );
-    let unit = ConfigUnit::from_toml_str(&toml)?;
-    let cert = String::new();
+pub(super) fn make_jucenit_tls_alpn_challenge_config(
+    dns: &str,
+    key_authorization: &str,
+) -> Result<String> {
+    let digest = openssl::sha::sha256(key_authorization.as_bytes());
 
-    // todo!();
+    let mut params = CertificateParams::new(vec![dns.to_owned()]).into_diagnostic()?;
+    params.custom_extensions = vec![CustomExtension::new_acme_identifier(&digest)];
+    let key_pair = KeyPair::generate().into_diagnostic()?;
+    let cert = params.self_signed(&key_pair).into_diagnostic()?;
 
-    Ok(cert)
+    let bundle = format!("{}{}", cert.pem(), key_pair.serialize_pem());
+    Ok(bundle)
+}
+/**
+* A tls-alpn-01 challenge in progress:
+* the tls listener is replaced by the responder.
+*
+* The challenge certificate can't be attached to the nginx-unit listener instead:
+* validators only accept a handshake negotiating "acme-tls/1" (RFC 8737 §3)
+* and nginx-unit has no ALPN settings.
+*/
+struct TlsAlpnChallenge {
+    socket: String,
+    previous: Option<Value>,
+    responder: TlsAlpnResponder,
+    // One responder at a time on the tls port
+    _turn: OwnedMutexGuard<()>,
+}
+static TLS_ALPN_TURN: Lazy<Arc<Mutex<()>>> = Lazy::new(|| Arc::new(Mutex::new(())));
+/**
+* Hand the tls port over to a responder serving the challenge certificate for the domain.
+* The configuration is only locked for the listener swap,
+* later writes leave the socket out until it is handed back.
+*/
+async fn set_tls_alpn_challenge(
+    dns: &str,
+    key_authorization: &str,
+    addr: &str,
+) -> Result<TlsAlpnChallenge> {
+    let bundle = make_jucenit_tls_alpn_challenge_config(dns, key_authorization)?;
+    let turn = TLS_ALPN_TURN.clone().lock_owned().await;
+
+    let socket = format!("*:{}", TLS_PORT);
+    let previous = {
+        let _lock = CONFIG_LOCK.lock().await;
+        HANDED_OVER.lock().await.push(socket.clone());
+        let live = client().await.get_config().await?;
+        let previous = live.get("listeners").and_then(|x| x.get(&socket)).cloned();
+        if previous.is_some() {
+            Operation::Delete {
+                path: vec!["listeners".to_owned(), socket.clone()],
+            }
+            .send()
+            .await?;
+        }
+        previous
+    };
+    let responder = match TlsAlpnResponder::serve(addr, &bundle).await {
+        Ok(responder) => responder,
+        Err(e) => {
+            restore_tls_listener(&socket, previous).await?;
+            return Err(e);
+        }
+    };
+    Ok(TlsAlpnChallenge {
+        socket,
+        previous,
+        responder,
+        _turn: turn,
+    })
+}
+/**
+* Stop the responder and restore the tls listener.
+*/
+async fn del_tls_alpn_challenge(challenge: TlsAlpnChallenge) -> Result<()> {
+    let TlsAlpnChallenge {
+        socket,
+        previous,
+        responder,
+        _turn,
+    } = challenge;
+    drop(responder);
+    restore_tls_listener(&socket, previous).await
+}
+/**
+* Render the handed over socket again,
+* and put back the listener jucenit doesn't manage.
+*/
+async fn restore_tls_listener(socket: &str, previous: Option<Value>) -> Result<()> {
+    HANDED_OVER.lock().await.retain(|x| x != socket);
+    NginxConfig::pull().await?.set().await?;
+
+    let _lock = CONFIG_LOCK.lock().await;
+    let live = client().await.get_config().await?;
+    let restored = live.get("listeners").and_then(|x| x.get(socket)).is_some();
+    if let (false, Some(listener)) = (restored, previous) {
+        Operation::Put {
+            path: vec!["listeners".to_owned(), socket.to_owned()],
+            value: listener,
+        }
+        .send()
+        .await?;
+    }
+    Ok(())
 }
 /**
 * Create an nginx-unit match to serve the challenge key file.
@@ -171,7 +312,10 @@ async fn make_jucenit_http_challenge_config(
         Uuid::new_v4(),
         HTTP_PORT,
         dns,
-        challenge.token.clone().unwrap(),
+        challenge
+            .token
+            .clone()
+            .ok_or(JucenitError::Acme("Challenge has no token".to_owned()))?,
         dns
     );
     let unit = ConfigUnit::from_toml_str(&toml)?;
@@ -184,7 +328,13 @@ async fn make_jucenit_http_challenge_config(
 */
 async fn set_challenge_key_file(dns: &str, challenge: &Challenge) -> Result<()> {
     // Write challenge key to temporary file
-    let data = challenge.key_authorization().into_diagnostic()?.unwrap();
+    let data = challenge
+        .key_authorization()
+        .map_err(JucenitError::from)?
+        .ok_or(JucenitError::Acme(format!(
+            "Challenge for {:?} has no key authorization",
+            dns
+        )))?;
 
     // Create and write to file
     let tmp_dir = "/tmp/jucenit";
@@ -220,33 +370,59 @@ async fn del_challenge_key_file(dns: &str, challenge: &Challenge) -> Result<()>
 pub struct Letsencrypt;
 
 impl Letsencrypt {
-    pub async fn get_cert_bundle(dns: &str, account: &Arc<Account>) -> Result<String> {
-        // Create a new order for a specific domain name.
+    /**
+     * Get a certificate covering every hosts of a group (SAN),
+     * named after the group in error messages.
+     */
+    pub async fn get_cert_bundle(
+        dns: &str,
+        hosts: &[String],
+        key_type: &KeyType,
+        account: &Arc<Account>,
+    ) -> Result<String> {
+        // Create a new order for the domain names.
         let mut builder = OrderBuilder::new(account.to_owned());
-        builder.add_dns_identifier(dns.to_owned());
-        let order = builder.build().await.into_diagnostic()?;
+        for host in hosts {
+            builder.add_dns_identifier(host.to_owned());
+        }
+        let order = builder.build().await.map_err(JucenitError::from)?;
 
         // Get the list of needed authorizations for this order.
-        let authorizations = order.authorizations().await.into_diagnostic()?;
+        let acme = Acme::pull().await?;
+        let authorizations = order.authorizations().await.map_err(JucenitError::from)?;
         for auth in authorizations {
-            // Get an tls-alpn-01 challenge
-            // if let Some(challenge) = auth.get_challenge("tls-alpn-01") {
-            //     Self::tls_alpn_challenge(dns, auth, &challenge).await?;
-            // }
-            // Get an http-01 challenge
-            if let Some(challenge) = auth.get_challenge("http-01") {
-                Self::http_challenge(dns, auth, &challenge).await?;
-            }
+            let host = match auth.wildcard {
+                Some(true) => format!("*.{}", auth.identifier.value),
+                _ => auth.identifier.value.to_owned(),
+            };
+            let host = host.as_str();
+            let kind = acme.challenge_for(host).await?;
+            let challenge = auth
+                .get_challenge(kind.name())
+                .ok_or(JucenitError::Acme(format!(
+                    "No {} challenge offered for {:?}",
+                    kind.name(),
+                    host
+                )))?;
+            match kind {
+                ChallengeType::Http01 => Self::http_challenge(host, auth, &challenge).await?,
+                ChallengeType::TlsAlpn01 => {
+                    Self::tls_alpn_challenge(host, auth, &challenge).await?
+                }
+                ChallengeType::Dns01 => Self::dns_challenge(host, auth, &challenge).await?,
+            };
         }
 
         let order = order
             .wait_ready(Duration::from_secs(5), 10)
             .await
-            .into_diagnostic()?;
-        ensure!(OrderStatus::Ready == order.status, "Order no Ready");
+            .map_err(JucenitError::from)?;
+        if !(OrderStatus::Ready == order.status) {
+            return Err(JucenitError::Acme(format!("Order no Ready for {:?}", dns)).into());
+        }
 
-        // Generate an RSA private key for the certificate.
-        let pkey = gen_rsa_private_key(4096).into_diagnostic()?;
+        // Generate a private key for the certificate.
+        let pkey = key_type.generate()?;
         let private_key = pkey.private_key_to_pem_pkcs8().into_diagnostic()?;
         let private_key = String::from_utf8(private_key).into_diagnostic()?;
 
@@ -255,17 +431,30 @@ impl Letsencrypt {
         let order = order
             .finalize(Csr::Automatic(pkey))
             .await
-            .into_diagnostic()?;
+            .map_err(JucenitError::from)?;
 
         let order = order
             .wait_done(Duration::from_secs(5), 10)
             .await
-            .into_diagnostic()?;
-        ensure!(OrderStatus::Valid == order.status, "Order not Valid");
+            .map_err(JucenitError::from)?;
+        if !(OrderStatus::Valid == order.status) {
+            return Err(JucenitError::Acme(format!("Order not Valid for {:?}", dns)).into());
+        }
 
-        // Download the certificate, and panic if it doesn't exist.
-        let certificates = order.certificate().await.into_diagnostic()?.unwrap();
-        ensure!(certificates.len() > 1, "No certificate returned");
+        // Download the certificate.
+        let certificates = order
+            .certificate()
+            .await
+            .map_err(JucenitError::from)?
+            .ok_or(JucenitError::Acme(format!(
+                "No certificate returned for {:?}",
+                dns
+            )))?;
+        if !(certificates.len() > 1) {
+            return Err(
+                JucenitError::Acme(format!("No certificate returned for {:?}", dns)).into(),
+            );
+        }
 
         let mut bundle = String::new();
         for cert in certificates.clone() {
@@ -278,19 +467,20 @@ impl Letsencrypt {
     }
     async fn http_challenge(dns: &str, auth: Authorization, challenge: &Challenge) -> Result<()> {
         // Create route to challenge key file
-        set_challenge_key_file(dns, &challenge).await?;
-        let unit = make_jucenit_http_challenge_config(dns, &challenge).await?;
+        set_challenge_key_file(dns, challenge).await?;
+        let unit = make_jucenit_http_challenge_config(dns, challenge).await?;
         unit.push().await?;
 
-        let challenge = challenge.validate().await.into_diagnostic()?;
+        let challenge = challenge.validate().await.map_err(JucenitError::from)?;
         let challenge = challenge
             .wait_done(Duration::from_secs(5), 10)
             .await
-            .into_diagnostic()?;
-        ensure!(
-            ChallengeStatus::Valid == challenge.status,
-            "Http Challenge not Valid"
-        );
+            .map_err(JucenitError::from)?;
+        if !(ChallengeStatus::Valid == challenge.status) {
+            return Err(
+                JucenitError::Acme(format!("Http Challenge not Valid for {:?}", dns)).into(),
+            );
+        }
 
         // Delete route to challenge key file
         del_challenge_key_file(dns, &challenge).await?;
@@ -299,52 +489,102 @@ impl Letsencrypt {
         let authorization = auth
             .wait_done(Duration::from_secs(5), 10)
             .await
-            .into_diagnostic()?;
-        ensure!(
-            AuthorizationStatus::Valid == authorization.status,
-            "Authorization not Valid"
-        );
+            .map_err(JucenitError::from)?;
+        if !(AuthorizationStatus::Valid == authorization.status) {
+            return Err(
+                JucenitError::Acme(format!("Authorization not Valid for {:?}", dns)).into(),
+            );
+        }
         Ok(())
     }
     /**
-     * Warning: Online ressources are relatively poor on how to implement this challenge.
-     * Refer direcly to the standard at:
+     * Serve a certificate proving the domain ownership on the tls port.
+     * Refer to the standard at:
      * https://datatracker.ietf.org/doc/html/rfc8737
-     * and read the comments
      */
     async fn tls_alpn_challenge(
         dns: &str,
         auth: Authorization,
         challenge: &Challenge,
     ) -> Result<()> {
-        // Create tls cert with challenge info
-        set_challenge_key_file(dns, &challenge).await?;
-
-        let bundle = make_jucenit_tls_alpn_challenge_config(dns, &challenge)?;
-        // JuceConfig::add_unit((match_.clone(), unit)).await?;
+        let key_authorization = challenge
+            .key_authorization()
+            .map_err(JucenitError::from)?
+            .ok_or(JucenitError::Acme(format!(
+                "Challenge for {:?} has no key authorization",
+                dns
+            )))?;
+        let addr = format!("0.0.0.0:{}", TLS_PORT);
+        let responder = set_tls_alpn_challenge(dns, &key_authorization, &addr).await?;
+        let res = tokio::time::timeout(TLS_ALPN_TIMEOUT, async {
+            let challenge = challenge.validate().await.map_err(JucenitError::from)?;
+            let challenge = challenge
+                .wait_done(Duration::from_secs(5), 10)
+                .await
+                .map_err(JucenitError::from)?;
+            if !(challenge.status == ChallengeStatus::Valid) {
+                return Err(
+                    JucenitError::Acme(format!("Tls Challenge not Valid for {:?}", dns)).into(),
+                );
+            }
+            Ok::<(), Error>(())
+        })
+        .await;
+        // Clean up whatever the outcome
+        del_tls_alpn_challenge(responder).await?;
+        res.map_err(|_| JucenitError::Acme(format!("Tls Challenge timed out for {:?}", dns)))??;
 
-        let challenge = challenge.validate().await.into_diagnostic()?;
-        let challenge = challenge
+        let authorization = auth
             .wait_done(Duration::from_secs(5), 10)
             .await
-            .into_diagnostic()?;
-        ensure!(
-            challenge.status == ChallengeStatus::Valid,
-            "Tls Challenge not Valid"
-        );
-
-        // Delete route to challenge key file
-        del_challenge_key_file(dns, &challenge).await?;
-        // JuceConfig::del_unit(match_.clone()).await?;
+            .map_err(JucenitError::from)?;
+        if !(authorization.status == AuthorizationStatus::Valid) {
+            return Err(
+                JucenitError::Acme(format!("Authorization not Valid for {:?}", dns)).into(),
+            );
+        }
+        Ok(())
+    }
+    async fn dns_challenge(dns: &str, auth: Authorization, challenge: &Challenge) -> Result<()> {
+        let settings = Acme::pull().await?.dns()?;
+        let provider = settings.provider().await;
+        let name = challenge_name(dns);
+        let value = challenge
+            .key_authorization_encoded()
+            .map_err(JucenitError::from)?
+            .ok_or(JucenitError::Acme(format!(
+                "Challenge for {:?} has no key authorization",
+                dns
+            )))?;
+        provider.set_txt(&name, &value).await?;
+        let res: Result<()> = async {
+            settings.wait_propagation(&provider, &name, &value).await?;
+            let challenge = challenge.validate().await.map_err(JucenitError::from)?;
+            let challenge = challenge
+                .wait_done(Duration::from_secs(5), 10)
+                .await
+                .map_err(JucenitError::from)?;
+            if !(challenge.status == ChallengeStatus::Valid) {
+                return Err(
+                    JucenitError::Acme(format!("Dns Challenge not Valid for {:?}", dns)).into(),
+                );
+            }
+            Ok(())
+        }
+        .await;
+        // Clean up whatever the outcome
+        provider.del_txt(&name, &value).await?;
+        res?;
 
         let authorization = auth
             .wait_done(Duration::from_secs(5), 10)
             .await
-            .into_diagnostic()?;
-        ensure!(
-            authorization.status == AuthorizationStatus::Valid,
-            "Authorization not Valid"
-        );
+            .map_err(JucenitError::from)?;
+        if !(authorization.status == AuthorizationStatus::Valid) {
+            return Err(
+                JucenitError::Acme(format!("Authorization not Valid for {:?}", dns)).into(),
+            );
+        }
         Ok(())
     }
 }
@@ -356,6 +596,7 @@ mod tests {
     use crate::database::{connect_db, fresh_db};
     use crate::nginx::CertificateStore;
     use crate::ConfigFile;
+    use serial_test::serial;
     use std::path::PathBuf;
 
     use miette::Result;
@@ -377,9 +618,55 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn make_tls_alpn_certificate() -> Result<()> {
+        let bundle = make_jucenit_tls_alpn_challenge_config("example.com", "token.thumbprint")?;
+        let cert = X509::from_pem(bundle.as_bytes()).into_diagnostic()?;
+        let text = String::from_utf8(cert.to_text().into_diagnostic()?).into_diagnostic()?;
+        assert!(text.contains("DNS:example.com"));
+        // acmeIdentifier extension
+        assert!(text.contains("1.3.6.1.5.5.7.1.31: critical"));
+        Ok(())
+    }
+    #[tokio::test]
+    #[serial]
+    async fn serve_tls_alpn_certificate() -> Result<()> {
+        let dns = "alpn.example.com";
+        ConfigFile::from_toml_str(
+            "
+        [[unit]]
+        uuid = 'a2f7b0c4-5a39-4c1e-9d8e-1f3b6c0a7e52'
+        listeners = ['*:443']
+        [unit.match]
+        hosts = ['alpn.example.com']
+        [unit.action]
+        proxy = 'http://127.0.0.1:8333'
+        ",
+        )?
+        .set()
+        .await?;
+        let live = client().await.get_config().await?;
+        let listener = live["listeners"]["*:443"].clone();
+        assert!(!listener.is_null());
+
+        let challenge = set_tls_alpn_challenge(dns, "token.thumbprint", "127.0.0.1:0").await?;
+        // The responder holds the tls port,
+        // writes still go through and leave it out.
+        NginxConfig::pull().await?.set().await?;
+        let live = client().await.get_config().await?;
+        assert!(live["listeners"].get("*:443").is_none());
+
+        del_tls_alpn_challenge(challenge).await?;
+        let live = client().await.get_config().await?;
+        assert_eq!(live["listeners"]["*:443"], listener);
+
+        ConfigFile::default().set().await?;
+        Ok(())
+    }
     #[tokio::test]
     async fn set_creds() -> Result<()> {
-        let res = set_account().await?;
+        let directory = Acme::pull().await?.directory();
+        let res = set_account(&directory).await?;
         Ok(())
     }
     #[tokio::test]
@@ -387,8 +674,11 @@ mod tests {
         set_testing_config().await?;
 
         let dns = "example.com";
-        let account = set_account().await?.clone();
-        let res = Letsencrypt::get_cert_bundle(dns, &account).await?;
+        let directory = Acme::pull().await?.directory_for(dns).await?;
+        let account = set_account(&directory).await?.clone();
+        let res =
+            Letsencrypt::get_cert_bundle(dns, &[dns.to_owned()], &KeyType::default(), &account)
+                .await?;
         // println!("{:#?}", res);
         Ok(())
     }
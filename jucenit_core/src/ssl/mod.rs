@@ -1,6 +1,20 @@
+mod acme;
+mod alpn;
+pub mod dns;
 mod fake;
+mod group;
+mod jws;
 mod letsencrypt;
 pub mod pebble;
+mod renewal;
 // Reexport
+pub use acme::{
+    Acme, AcmeDirectory, ChallengeType, DirectoryPreset, Eab, Grouping, KeyType, KeyTypes,
+    UnitAcme, DEFAULT_RENEWAL_RATIO,
+};
 pub use fake::Fake;
+pub use group::{
+    certificate_groups, certificate_name, certificate_names, group_hosts, set_certificate_groups,
+};
 pub use letsencrypt::{set_account, Letsencrypt};
+pub use renewal::{renewal_id, set_renewal_id, should_renew, RenewalInfo};
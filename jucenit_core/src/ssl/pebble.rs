@@ -10,6 +10,7 @@ use acme2::{
     DirectoryBuilder, OrderBuilder, OrderStatus,
 };
 // Error Handling
+use crate::error::JucenitError;
 use miette::{Error, IntoDiagnostic, Result};
 // use acme2::Error;
 
@@ -19,22 +20,30 @@ pub static PEBBLE_URL: Lazy<Arc<Mutex<String>>> =
 pub static PEBBLE_CERT_URL: Lazy<Arc<Mutex<String>>> =
     Lazy::new(|| Arc::new(Mutex::new("https://localhost:15000/roots/0".to_owned())));
 
-pub async fn pebble_http_client() -> reqwest::Client {
-    let raw = tokio::fs::read("/etc/pebble/test/certs/pebble.minica.pem")
-        .await
-        .unwrap();
-    let cert = reqwest::Certificate::from_pem(&raw).unwrap();
-    reqwest::Client::builder()
+pub const PEBBLE_CA_PATH: &str = "/etc/pebble/test/certs/pebble.minica.pem";
+
+pub async fn pebble_http_client() -> Result<reqwest::Client> {
+    let raw = tokio::fs::read(PEBBLE_CA_PATH).await.map_err(|e| {
+        JucenitError::Config(format!(
+            "Couldn't read the pebble CA {:?}: {}",
+            PEBBLE_CA_PATH, e
+        ))
+    })?;
+    let cert = reqwest::Certificate::from_pem(&raw).map_err(|e| {
+        JucenitError::Config(format!("Invalid pebble CA {:?}: {}", PEBBLE_CA_PATH, e))
+    })?;
+    let client = reqwest::Client::builder()
         .add_root_certificate(cert)
         .build()
-        .unwrap()
+        .map_err(|e| JucenitError::Acme(e.to_string()))?;
+    Ok(client)
 }
 pub async fn pebble_directory() -> Result<Arc<Directory>> {
-    let http_client = pebble_http_client().await;
+    let http_client = pebble_http_client().await?;
     let dir = DirectoryBuilder::new(PEBBLE_URL.lock().await.clone())
         .http_client(http_client)
         .build()
         .await
-        .into_diagnostic()?;
+        .map_err(JucenitError::from)?;
     Ok(dir)
 }
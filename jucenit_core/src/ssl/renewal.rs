@@ -0,0 +1,351 @@
+//!
+//! Decide when to renew a certificate.
+//!
+//! The CA suggested window (ACME Renewal Information, RFC 9773)
+//! is followed when the directory advertises it,
+//! otherwise certificates are renewed at a fraction of their lifetime.
+//!
+
+use super::acme::{Acme, AcmeDirectory};
+use crate::nginx::certificate::CertificateInfo;
+use chrono::{DateTime, Utc};
+use openssl::sha::sha256;
+use openssl::x509::X509;
+use serde::Deserialize;
+use std::collections::HashMap;
+// Global vars
+use once_cell::sync::Lazy;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+// Database
+use crate::database::connect_db;
+use crate::database::entity::{prelude::*, *};
+use sea_orm::{prelude::*, sea_query::OnConflict, ActiveValue};
+// Error Handling
+use crate::error::JucenitError;
+use miette::{IntoDiagnostic, Result};
+
+// Bounds of the delay before asking the CA again (RFC 9773 section 4.3)
+const MIN_RETRY_AFTER: i64 = 60 * 60;
+const MAX_RETRY_AFTER: i64 = 24 * 60 * 60;
+const DEFAULT_RETRY_AFTER: i64 = 6 * 60 * 60;
+// Delay before reading a directory again
+const DIRECTORY_TTL: i64 = 24 * 60 * 60;
+
+// Renewal information endpoint, and when it was read
+type Endpoint = (Option<String>, DateTime<Utc>);
+// Renewal windows, and until when they hold
+type Cached = (RenewalInfo, DateTime<Utc>);
+
+/**
+* Renewal information endpoint per directory url, and when it was read.
+*/
+static ENDPOINTS: Lazy<Arc<Mutex<HashMap<String, Endpoint>>>> =
+    Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+/**
+* Renewal windows per renewal id, and until when they hold (Retry-After).
+*/
+static WINDOWS: Lazy<Arc<Mutex<HashMap<String, Cached>>>> =
+    Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenewalInfo {
+    pub suggested_window: Window,
+    pub explanation_url: Option<String>,
+}
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Window {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl RenewalInfo {
+    /**
+     * Query the CA for the renewal window of a certificate.
+     * Returns None when the directory doesn't support renewal information.
+     *
+     * The CA is asked again once the window Retry-After has passed,
+     * the directory once a day.
+     */
+    pub async fn get(directory: &AcmeDirectory, renewal_id: &str) -> Result<Option<RenewalInfo>> {
+        let now = Utc::now();
+        if let Some((info, until)) = WINDOWS.lock().await.get(renewal_id) {
+            if now < *until {
+                return Ok(Some(info.clone()));
+            }
+        }
+        let client = directory.http_client().await?;
+        let endpoint = match RenewalInfo::endpoint(directory, &client).await? {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        let url = format!("{}/{}", endpoint.trim_end_matches('/'), renewal_id);
+        let res = client.get(&url).send().await.into_diagnostic()?;
+        if !res.status().is_success() {
+            let message = format!("{} answered {} for renewal information", url, res.status());
+            return Err(JucenitError::Acme(message).into());
+        }
+        let retry_after = res
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|x| x.to_str().ok())
+            .map(|x| x.to_owned());
+        let info: RenewalInfo = res.json().await.into_diagnostic()?;
+        let until = now + retry_delay(retry_after.as_deref(), now);
+        WINDOWS
+            .lock()
+            .await
+            .insert(renewal_id.to_owned(), (info.clone(), until));
+        Ok(Some(info))
+    }
+    /**
+     * Returns the renewal information url advertised by the directory.
+     */
+    async fn endpoint(
+        directory: &AcmeDirectory,
+        client: &reqwest::Client,
+    ) -> Result<Option<String>> {
+        let url = directory.url().await;
+        let now = Utc::now();
+        if let Some((endpoint, at)) = ENDPOINTS.lock().await.get(&url) {
+            if now < *at + chrono::Duration::seconds(DIRECTORY_TTL) {
+                return Ok(endpoint.clone());
+            }
+        }
+        let dir: serde_json::Value = client
+            .get(&url)
+            .send()
+            .await
+            .into_diagnostic()?
+            .json()
+            .await
+            .into_diagnostic()?;
+        let endpoint = dir
+            .get("renewalInfo")
+            .and_then(|x| x.as_str())
+            .map(|x| x.to_owned());
+        ENDPOINTS.lock().await.insert(url, (endpoint.clone(), now));
+        Ok(endpoint)
+    }
+    /**
+     * Returns the time to renew at.
+     * Spread uniformly in the window, but stable for a given certificate
+     * (across restarts and toolchains)
+     * so that polling doesn't bias renewals toward the window start.
+     */
+    pub fn renewal_time(&self, renewal_id: &str) -> DateTime<Utc> {
+        let digest = sha256(renewal_id.as_bytes());
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&digest[0..8]);
+        let fraction = u64::from_be_bytes(bytes) as f64 / u64::MAX as f64;
+
+        let Window { start, end } = self.suggested_window;
+        let span = (end - start).num_seconds().max(0) as f64;
+        start + chrono::Duration::seconds((span * fraction) as i64)
+    }
+}
+
+/**
+* Returns the delay before asking for a renewal window again,
+* from a Retry-After header (seconds or http date), within sane bounds.
+*/
+fn retry_delay(retry_after: Option<&str>, now: DateTime<Utc>) -> chrono::Duration {
+    let seconds = match retry_after.map(|x| x.trim()) {
+        Some(x) => match x.parse::<i64>() {
+            Ok(seconds) => seconds,
+            Err(_) => DateTime::parse_from_rfc2822(x)
+                .map(|x| (x.with_timezone(&Utc) - now).num_seconds())
+                .unwrap_or(DEFAULT_RETRY_AFTER),
+        },
+        None => DEFAULT_RETRY_AFTER,
+    };
+    chrono::Duration::seconds(seconds.clamp(MIN_RETRY_AFTER, MAX_RETRY_AFTER))
+}
+
+/**
+* Returns the renewal information identifier of a certificate bundle:
+* base64url(authority key identifier) "." base64url(serial number).
+* Returns None when the certificate has no authority key identifier (ex: self-signed).
+*/
+pub fn renewal_id(bundle: &str) -> Result<Option<String>> {
+    let cert = X509::from_pem(bundle.as_bytes()).into_diagnostic()?;
+    let aki = match cert.authority_key_id() {
+        Some(x) => x.as_slice().to_owned(),
+        None => return Ok(None),
+    };
+    let serial = cert.serial_number().to_bn().into_diagnostic()?.to_vec();
+    Ok(Some(encode_renewal_id(&aki, &serial)))
+}
+fn encode_renewal_id(aki: &[u8], serial: &[u8]) -> String {
+    // Der integers are signed, keep them positive
+    let mut serial = serial.to_owned();
+    if serial.first().is_none_or(|x| *x >= 0x80) {
+        serial.insert(0, 0);
+    }
+    format!("{}.{}", base64_url(aki), base64_url(&serial))
+}
+pub(super) fn base64_url(bytes: &[u8]) -> String {
+    openssl::base64::encode_block(bytes)
+        .trim_end_matches('=')
+        .replace('+', "-")
+        .replace('/', "_")
+}
+
+/**
+* Remember the renewal information identifier of a freshly issued certificate.
+*/
+pub async fn set_renewal_id(dns: &str, bundle: &str) -> Result<()> {
+    let db = connect_db().await?;
+    let certificate = certificate::ActiveModel {
+        host: ActiveValue::Set(dns.to_owned()),
+        renewal_id: ActiveValue::Set(renewal_id(bundle)?),
+        ..Default::default()
+    };
+    Certificate::insert(certificate)
+        .on_conflict(
+            OnConflict::column(certificate::Column::Host)
+                .update_column(certificate::Column::RenewalId)
+                .to_owned(),
+        )
+        .exec(&db)
+        .await
+        .map_err(JucenitError::from)?;
+    Ok(())
+}
+
+/**
+* Whether a certificate is due for renewal.
+* Falls back to the lifetime ratio when the CA can't be asked.
+* The store name differs from the group name for additional key types.
+*/
+pub async fn should_renew(name: &str, dns: &str, cert: &CertificateInfo) -> Result<bool> {
+    let acme = Acme::pull().await?;
+    if acme.ari() {
+        let db = connect_db().await?;
+        let renewal_id = Certificate::find()
+            .filter(certificate::Column::Host.eq(dns))
+            .one(&db)
+            .await
+            .map_err(JucenitError::from)?
+            .and_then(|x| x.renewal_id);
+        if let Some(renewal_id) = renewal_id {
+            let directory = acme.directory_for(name).await?;
+            match RenewalInfo::get(&directory, &renewal_id).await {
+                Ok(Some(info)) => return Ok(Utc::now() >= info.renewal_time(&renewal_id)),
+                Ok(None) => {}
+                Err(e) => println!("{:?}", e),
+            }
+        }
+    }
+    cert.validity.should_renew_at(acme.ratio()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssl;
+    use crate::ssl::Letsencrypt as LetsencryptCertificate;
+
+    #[test]
+    fn encode_identifier() {
+        // Example from RFC 9773 section 4.1
+        let aki = [
+            0x69, 0x88, 0x5B, 0x6B, 0x87, 0x46, 0x40, 0x41, 0xE1, 0xB3, 0x7B, 0x84, 0x7B, 0xA0,
+            0xAE, 0x2C, 0xDE, 0x01, 0xC8, 0xD4,
+        ];
+        let serial = [0x87, 0x65, 0x43, 0x21];
+        assert_eq!(
+            encode_renewal_id(&aki, &serial),
+            "aYhba4dGQEHhs3uEe6CuLN4ByNQ.AIdlQyE"
+        );
+    }
+    #[test]
+    fn pick_time_in_window() -> Result<()> {
+        let info: RenewalInfo = serde_json::from_value(serde_json::json!({
+          "suggestedWindow": {
+            "start": "2026-01-03T00:00:00Z",
+            "end": "2026-01-07T00:00:00Z"
+          }
+        }))
+        .into_diagnostic()?;
+        let time = info.renewal_time("aYhba4dGQEHhs3uEe6CuLN4ByNQ.AIdlQyE");
+        assert!(time >= info.suggested_window.start && time <= info.suggested_window.end);
+        assert_eq!(
+            time,
+            info.renewal_time("aYhba4dGQEHhs3uEe6CuLN4ByNQ.AIdlQyE")
+        );
+        // Pinned, must not move with the toolchain
+        assert_eq!(time.to_rfc3339(), "2026-01-05T21:41:15+00:00");
+        Ok(())
+    }
+    #[test]
+    fn bound_retry_after() {
+        let now: DateTime<Utc> = "2026-01-03T00:00:00Z".parse().unwrap();
+        assert_eq!(retry_delay(Some("21600"), now).num_seconds(), 21600);
+        assert_eq!(
+            retry_delay(Some("Sat, 03 Jan 2026 12:00:00 GMT"), now).num_seconds(),
+            12 * 60 * 60
+        );
+        // Too eager, too lazy, missing or invalid
+        assert_eq!(retry_delay(Some("5"), now).num_seconds(), MIN_RETRY_AFTER);
+        assert_eq!(
+            retry_delay(Some("31536000"), now).num_seconds(),
+            MAX_RETRY_AFTER
+        );
+        assert_eq!(retry_delay(None, now).num_seconds(), DEFAULT_RETRY_AFTER);
+        assert_eq!(
+            retry_delay(Some("soon"), now).num_seconds(),
+            DEFAULT_RETRY_AFTER
+        );
+    }
+    #[tokio::test]
+    async fn reuse_windows_until_retry_after() -> Result<()> {
+        let info: RenewalInfo = serde_json::from_value(serde_json::json!({
+          "suggestedWindow": {
+            "start": "2026-01-03T00:00:00Z",
+            "end": "2026-01-07T00:00:00Z"
+          }
+        }))
+        .into_diagnostic()?;
+        let until = Utc::now() + chrono::Duration::seconds(60);
+        WINDOWS
+            .lock()
+            .await
+            .insert("cached.id".to_owned(), (info.clone(), until));
+        // Unreachable directory, the CA isn't asked.
+        let directory = AcmeDirectory::Custom {
+            url: "http://127.0.0.1:9/directory".to_owned(),
+            root_ca: None,
+        };
+        assert_eq!(RenewalInfo::get(&directory, "cached.id").await?, Some(info));
+        Ok(())
+    }
+    /**
+     * Requires a running pebble instance.
+     */
+    #[tokio::test]
+    async fn get_renewal_info() -> Result<()> {
+        let dns = "example.com";
+        let directory = Acme::pull().await?.directory();
+        let account = ssl::set_account(&directory).await?;
+        let bundle = LetsencryptCertificate::get_cert_bundle(
+            dns,
+            &[dns.to_owned()],
+            &ssl::KeyType::default(),
+            &account,
+        )
+        .await?;
+        let renewal_id = renewal_id(&bundle)?.unwrap();
+        let info = match RenewalInfo::get(&directory, &renewal_id).await? {
+            Some(info) => info,
+            None => {
+                println!("The directory doesn't advertise renewal information, skipped");
+                return Ok(());
+            }
+        };
+        println!("{:#?}", info);
+        assert!(info.suggested_window.start < info.suggested_window.end);
+        Ok(())
+    }
+}
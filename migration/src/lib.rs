@@ -1,6 +1,16 @@
 pub use sea_orm_migration::prelude::*;
 
 mod m20240606_110915_create_table;
+mod m20261018_120000_add_listener_forwarded;
+mod m20261018_130000_create_global_option;
+mod m20261018_140000_create_application;
+mod m20261018_150000_create_upstream;
+mod m20261019_090000_add_upstream_health_check;
+mod m20261019_100000_add_match_source;
+mod m20261019_110000_create_certificate;
+mod m20261019_120000_create_acme_account;
+mod m20261019_130000_add_match_acme;
+mod m20261019_140000_add_host_certificate_group;
 
 pub use m20240606_110915_create_table::*;
 
@@ -9,6 +19,18 @@ pub struct Migrator;
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(m20240606_110915_create_table::Migration)]
+        vec![
+            Box::new(m20240606_110915_create_table::Migration),
+            Box::new(m20261018_120000_add_listener_forwarded::Migration),
+            Box::new(m20261018_130000_create_global_option::Migration),
+            Box::new(m20261018_140000_create_application::Migration),
+            Box::new(m20261018_150000_create_upstream::Migration),
+            Box::new(m20261019_090000_add_upstream_health_check::Migration),
+            Box::new(m20261019_100000_add_match_source::Migration),
+            Box::new(m20261019_110000_create_certificate::Migration),
+            Box::new(m20261019_120000_create_acme_account::Migration),
+            Box::new(m20261019_130000_add_match_acme::Migration),
+            Box::new(m20261019_140000_add_host_certificate_group::Migration),
+        ]
     }
 }
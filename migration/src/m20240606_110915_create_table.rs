@@ -7,7 +7,7 @@
 //! ```
 //!
 
-use miette::{IntoDiagnostic, Result};
+use miette::Result;
 use sea_orm_migration::prelude::*;
 use sea_query::Index;
 
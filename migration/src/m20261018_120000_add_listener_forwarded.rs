@@ -0,0 +1,42 @@
+//!
+//! Store trusted proxies settings (nginx-unit "forwarded" object)
+//! alongside the listener.
+//!
+
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Listener::Table)
+                    .add_column(ColumnDef::new(Listener::Forwarded).json())
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Listener::Table)
+                    .drop_column(Listener::Forwarded)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Listener {
+    Table, // special attribute
+    Forwarded,
+}
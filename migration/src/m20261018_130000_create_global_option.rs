@@ -0,0 +1,53 @@
+//!
+//! Store server wide options (nginx-unit "settings" and "access_log")
+//! declared in configuration files.
+//!
+
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(GlobalOption::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(GlobalOption::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(GlobalOption::Name)
+                            .string()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(GlobalOption::RawParams).json().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(GlobalOption::Table).to_owned())
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden, Debug)]
+enum GlobalOption {
+    Table, // special attribute
+    Id,
+    Name, // Option section name (ex: "settings")
+    RawParams,
+}
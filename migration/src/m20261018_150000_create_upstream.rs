@@ -0,0 +1,103 @@
+//!
+//! Store nginx-unit upstreams (load balanced server groups)
+//! and their weighted servers.
+//!
+
+use sea_orm_migration::prelude::*;
+use sea_query::Index;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Upstream
+        manager
+            .create_table(
+                Table::create()
+                    .table(Upstream::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Upstream::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(Upstream::Name)
+                            .string()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // Upstream servers
+        manager
+            .create_table(
+                Table::create()
+                    .table(UpstreamServer::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(UpstreamServer::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(UpstreamServer::UpstreamId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(UpstreamServer::Address).string().not_null())
+                    .col(ColumnDef::new(UpstreamServer::RawParams).json().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-upstream_id")
+                            .from(UpstreamServer::Table, UpstreamServer::UpstreamId)
+                            .to(Upstream::Table, Upstream::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .index(
+                        Index::create()
+                            .unique()
+                            .col(UpstreamServer::UpstreamId)
+                            .col(UpstreamServer::Address),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(UpstreamServer::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Upstream::Table).to_owned())
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden, Debug)]
+enum Upstream {
+    Table, // special attribute
+    Id,
+    Name, // Upstream name (ex: "api")
+}
+
+#[derive(DeriveIden, Debug)]
+enum UpstreamServer {
+    Table, // special attribute
+    Id,
+    Address,   // Server ip socket (ex: "10.0.0.1:80")
+    RawParams, // Server options (ex: weight)
+    // Relations
+    UpstreamId,
+}
@@ -0,0 +1,69 @@
+//!
+//! Store upstream health check settings
+//! and the servers currently removed from rotation.
+//!
+
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Upstream::Table)
+                    .add_column(ColumnDef::new(Upstream::HealthCheck).json())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UpstreamServer::Table)
+                    .add_column(
+                        ColumnDef::new(UpstreamServer::Down)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UpstreamServer::Table)
+                    .drop_column(UpstreamServer::Down)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Upstream::Table)
+                    .drop_column(Upstream::HealthCheck)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Upstream {
+    Table,       // special attribute
+    HealthCheck, // Probe settings (ex: {"probe":"http","path":"/healthz"})
+}
+
+#[derive(DeriveIden)]
+enum UpstreamServer {
+    Table, // special attribute
+    Down,  // Removed from rotation by failing health checks
+}
@@ -0,0 +1,40 @@
+//!
+//! Store the file units were loaded from,
+//! to point configuration errors back to it.
+//!
+
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(NgMatch::Table)
+                    .add_column(ColumnDef::new(NgMatch::Source).string())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(NgMatch::Table)
+                    .drop_column(NgMatch::Source)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum NgMatch {
+    Table,  // special attribute
+    Source, // Config file path (ex: "/etc/jucenit/jucenit.toml")
+}
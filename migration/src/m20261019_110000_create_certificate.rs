@@ -0,0 +1,51 @@
+//!
+//! Store issued certificates identifiers,
+//! to query the CA for their renewal window.
+//!
+
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Certificate::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Certificate::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(Certificate::Host)
+                            .string()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(Certificate::RenewalId).string())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Certificate::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden, Debug)]
+enum Certificate {
+    Table, // special attribute
+    Id,
+    Host,      // Certificate name in the nginx-unit store (ex: "example.com")
+    RenewalId, // ACME renewal information certificate identifier (RFC 9773)
+}
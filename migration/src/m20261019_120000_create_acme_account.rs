@@ -0,0 +1,53 @@
+//!
+//! Store ACME accounts,
+//! one per directory (ex: Let's Encrypt, ZeroSSL, a private CA).
+//!
+
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(AcmeAccount::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(AcmeAccount::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(AcmeAccount::Directory)
+                            .string()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(AcmeAccount::PrivateKey).string().not_null())
+                    .col(ColumnDef::new(AcmeAccount::Contacts).json().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(AcmeAccount::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden, Debug)]
+enum AcmeAccount {
+    Table, // special attribute
+    Id,
+    Directory,  // Directory url (ex: "https://acme-v02.api.letsencrypt.org/directory")
+    PrivateKey, // Account key (pem)
+    Contacts,   // Registered contacts (ex: ["mailto:admin@example.com"])
+}
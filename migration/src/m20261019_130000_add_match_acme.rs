@@ -0,0 +1,39 @@
+//!
+//! Store per unit acme settings (ex: a certificate authority override).
+//!
+
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(NgMatch::Table)
+                    .add_column(ColumnDef::new(NgMatch::Acme).json())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(NgMatch::Table)
+                    .drop_column(NgMatch::Acme)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum NgMatch {
+    Table, // special attribute
+    Acme,  // Unit acme settings (ex: {"directory": "zerossl"})
+}
@@ -0,0 +1,40 @@
+//!
+//! Map hosts to the certificate covering them,
+//! when issued as one multi-domain (SAN) certificate.
+//!
+
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Host::Table)
+                    .add_column(ColumnDef::new(Host::CertificateGroup).string())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Host::Table)
+                    .drop_column(Host::CertificateGroup)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Host {
+    Table,            // special attribute
+    CertificateGroup, // Certificate name (ex: "example.com" for "www.example.com")
+}